@@ -1,7 +1,7 @@
 use crate::common;
 use crate::common::assistant::ChatRequestMessage;
 use crate::common::http::GetResponse;
-use crate::server::http_client::HttpClient;
+use crate::server::http_client::{HttpClient, RequestProfile};
 use futures_util::TryStreamExt;
 use http::Method;
 use serde_json::Value;
@@ -269,15 +269,32 @@ pub async fn ask_ai<R: Runtime>(
     server_id: String,
     assistant_id: String,
     client_id: String,
+) -> Result<(), String> {
+    ask_assistant(&server_id, &assistant_id, &message, |line| {
+        let _ = app_handle.emit(&client_id, line).map_err(|err| {
+            println!("Failed to emit: {:?}", err);
+        });
+    })
+    .await
+}
+
+/// Ask assistant `assistant_id` of server `server_id`, `on_line` is called
+/// with every line of the reply as it streams in.
+pub(crate) async fn ask_assistant(
+    server_id: &str,
+    assistant_id: &str,
+    message: &str,
+    mut on_line: impl FnMut(String),
 ) -> Result<(), String> {
     let body = serde_json::json!({ "message": message });
 
     let path = format!("/assistant/{}/_ask", assistant_id);
 
-    println!("Sending request to {}", &path);
+    log::debug!("Sending request to {}", &path);
 
-    let response = HttpClient::send_request(
-        server_id.as_str(),
+    let response = HttpClient::send_request_with_profile(
+        server_id,
+        RequestProfile::Streaming,
         Method::POST,
         path.as_str(),
         None,
@@ -297,11 +314,7 @@ pub async fn ask_ai<R: Runtime>(
     let mut lines = tokio::io::BufReader::new(reader).lines();
 
     while let Ok(Some(line)) = lines.next_line().await {
-        dbg!("Received line: {}", &line);
-
-        let _ = app_handle.emit(&client_id, line).map_err(|err| {
-            println!("Failed to emit: {:?}", err);
-        });
+        on_line(line);
     }
 
     Ok(())
@@ -1,108 +1,440 @@
-use std::{fs::create_dir, io::Read};
+//! Starting Coco when the user logs in.
+//!
+//! Whether Coco starts at login is part of the [settings](crate::settings),
+//! this module keeps the entry of the system in line with them. On Linux, the
+//! XDG autostart `.desktop` file is written directly, on the other platforms
+//! the autostart plugin manages the entry.
+//!
+//! The user may also change the entry outside of Coco, e.g., by removing it
+//! or disabling it in the session settings of their desktop. That is detected
+//! upon app start and reflected in the settings instead of being overwritten.
 
-use tauri::{Manager, Runtime};
-use tauri_plugin_autostart::ManagerExt;
+use crate::settings::{self, SETTINGS_AUTOSTART};
+use crate::COCO_TAURI_STORE;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Runtime};
+use tauri_plugin_store::StoreExt;
 
-// Start or stop according to configuration
-pub fn enable_autostart(app: &mut tauri::App) {
-    use tauri_plugin_autostart::MacosLauncher;
-    use tauri_plugin_autostart::ManagerExt;
+/// Argument Coco is started with from the autostart entry.
+const AUTOSTART_ARG: &str = "--autostart";
+/// Argument carrying the start delay, in seconds.
+const AUTOSTART_DELAY_ARG: &str = "--autostart-delay=";
+/// The longest start delay, in seconds.
+const MAX_AUTOSTART_DELAY_SECS: u32 = 600;
+
+/// File the autostart state was kept in before it became a setting.
+const LEGACY_AUTOSTART_FILE: &str = "autostart.txt";
+
+/// Whether Coco has written its autostart entry. Tells an entry that the user
+/// removed from one that has never been there.
+const AUTOSTART_ENTRY_INSTALLED: &str = "autostart_entry_installed";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutostartSettings {
+    /// Start Coco when the user logs in.
+    pub enabled: bool,
+    /// Keep the window hidden when started at login.
+    pub start_hidden: bool,
+    /// Seconds to wait after the login before starting.
+    pub delay_secs: u32,
+}
+
+impl Default for AutostartSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            start_hidden: true,
+            delay_secs: 0,
+        }
+    }
+}
+
+impl AutostartSettings {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.delay_secs > MAX_AUTOSTART_DELAY_SECS {
+            return Err(format!(
+                "the autostart delay should be at most {} seconds",
+                MAX_AUTOSTART_DELAY_SECS
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Arguments Coco is started with from the autostart entry.
+    fn launch_args(&self) -> Vec<String> {
+        let mut args = vec![AUTOSTART_ARG.to_string()];
+        if self.delay_secs > 0 {
+            args.push(format!("{}{}", AUTOSTART_DELAY_ARG, self.delay_secs));
+        }
+        args
+    }
+}
+
+/// Whether this instance was started from the autostart entry.
+pub(crate) fn started_at_login() -> bool {
+    std::env::args().any(|arg| arg == AUTOSTART_ARG)
+}
+
+/// Wait for the start delay if this instance was started from the autostart
+/// entry. Called before anything else upon app start.
+pub(crate) fn wait_for_start_delay() {
+    let delay = std::env::args()
+        .find_map(|arg| {
+            arg.strip_prefix(AUTOSTART_DELAY_ARG)
+                .and_then(|secs| secs.parse::<u32>().ok())
+        })
+        .unwrap_or(0)
+        .min(MAX_AUTOSTART_DELAY_SECS);
+
+    if delay > 0 {
+        std::thread::sleep(Duration::from_secs(delay.into()));
+    }
+}
+
+/// Move the state kept in `autostart.txt` to the settings, called before they
+/// are loaded.
+pub(crate) fn migrate_autostart_file<R: Runtime>(app_handle: &AppHandle<R>) {
+    let Ok(config_dir) = app_handle.path().app_config_dir() else {
+        return;
+    };
+    let path = config_dir.join(LEGACY_AUTOSTART_FILE);
+    if !path.exists() {
+        return;
+    }
 
-    app.handle()
-        .plugin(tauri_plugin_autostart::init(
-            MacosLauncher::AppleScript,
-            None,
-        ))
-        .unwrap();
-
-    let autostart_manager = app.autolaunch();
-
-    // close autostart
-    // autostart_manager.disable().unwrap();
-    // return;
-
-    match (
-        autostart_manager.is_enabled(),
-        current_autostart(app.app_handle()),
-    ) {
-        (Ok(false), Ok(true)) => match autostart_manager.enable() {
-            Ok(_) => println!("Autostart enabled successfully."),
-            Err(err) => eprintln!("Failed to enable autostart: {}", err),
-        },
-        (Ok(true), Ok(false)) => match autostart_manager.disable() {
-            Ok(_) => println!("Autostart disable successfully."),
-            Err(err) => eprintln!("Failed to disable autostart: {}", err),
-        },
-        _ => (),
-    }
-}
-
-fn current_autostart<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<bool, String> {
-    use std::fs::File;
-
-    let path = app.path().app_config_dir().unwrap();
-    let mut old_value = true;
-
-    if path.exists() {
-        let file_path = path.join("autostart.txt");
-        if file_path.exists() {
-            let mut file = File::open(file_path).unwrap();
-            let mut data = String::new();
-            if let Ok(_) = file.read_to_string(&mut data) {
-                if data.is_empty() == false {
-                    old_value = data.parse().unwrap_or(true)
-                }
+    let store = match app_handle.store(COCO_TAURI_STORE) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!("failed to migrate the autostart state: {}", e);
+            return;
+        }
+    };
+
+    if !store.has(SETTINGS_AUTOSTART) {
+        let enabled = match std::fs::read_to_string(&path) {
+            Ok(content) => content.trim().parse().unwrap_or(true),
+            Err(e) => {
+                log::warn!("failed to read [{}]: {}", path.display(), e);
+                true
             }
+        };
+        let autostart = AutostartSettings {
+            enabled,
+            ..Default::default()
+        };
+        store.set(
+            SETTINGS_AUTOSTART,
+            serde_json::to_value(&autostart).expect("Failed to serialize autostart settings"),
+        );
+        // The entry was written by the autostart plugin so far
+        store.set(AUTOSTART_ENTRY_INSTALLED, enabled);
+    }
+
+    if let Err(e) = std::fs::remove_file(&path) {
+        log::warn!("failed to remove [{}]: {}", path.display(), e);
+    }
+}
+
+/// Reconcile the autostart entry with the settings, then keep it in line with
+/// them. Called upon app start, once the settings are loaded.
+pub fn enable_autostart(app: &mut tauri::App) {
+    #[cfg(not(target_os = "linux"))]
+    {
+        use tauri_plugin_autostart::MacosLauncher;
+
+        // The arguments are fixed once the plugin is registered, a change of
+        // the delay is applied to the entry upon the next app start.
+        let plugin = tauri_plugin_autostart::Builder::new()
+            .macos_launcher(MacosLauncher::AppleScript)
+            .args(settings::settings().autostart.launch_args())
+            .build();
+        if let Err(e) = app.handle().plugin(plugin) {
+            log::error!("failed to register the autostart plugin: {}", e);
+            return;
+        }
+    }
+
+    let app_handle = app.handle();
+    let autostart = settings::settings().autostart;
+    let installed = entry_installed(app_handle);
+
+    match (autostart.enabled, entry::is_active(app_handle), installed) {
+        (true, Ok(false), true) => {
+            log::info!("the autostart entry was removed or disabled outside of Coco");
+            set_enabled(app_handle, false);
         }
+        (false, Ok(true), _) => {
+            log::info!("the autostart entry was added outside of Coco");
+            set_enabled(app_handle, true);
+        }
+        (_, Ok(_), _) => apply(app_handle, &autostart),
+        (_, Err(e), _) => log::error!("failed to check the autostart entry: {}", e),
+    }
+
+    settings::subscribe(|previous, current| {
+        if previous.autostart == current.autostart {
+            return;
+        }
+        if let Some(app_handle) = crate::GLOBAL_TAURI_APP_HANDLE.get() {
+            apply(app_handle, &current.autostart);
+        }
+    });
+
+    if started_at_login() && !settings::settings().autostart.start_hidden {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            crate::show_coco(app_handle).await;
+        });
+    }
+}
+
+fn set_enabled<R: Runtime>(app_handle: &AppHandle<R>, enabled: bool) {
+    // The subscriber is not registered yet, write the entry here
+    match settings::update(app_handle, |settings| {
+        settings.autostart.enabled = enabled;
+        Ok(())
+    }) {
+        Ok(settings) => apply(app_handle, &settings.autostart),
+        Err(e) => log::error!("failed to update the autostart settings: {}", e),
+    }
+}
+
+/// Write or remove the autostart entry according to `autostart`.
+fn apply<R: Runtime>(app_handle: &AppHandle<R>, autostart: &AutostartSettings) {
+    let result = if autostart.enabled {
+        entry::write(app_handle, autostart)
+    } else {
+        entry::remove(app_handle)
     };
 
-    Ok(old_value)
+    match result {
+        Ok(()) => set_entry_installed(app_handle, autostart.enabled),
+        Err(e) => log::error!("failed to update the autostart entry: {}", e),
+    }
 }
 
-#[tauri::command]
-pub async fn change_autostart<R: Runtime>(
-    app: tauri::AppHandle<R>,
-    open: bool,
-) -> Result<(), String> {
-    use std::fs::File;
-    use std::io::Write;
+fn entry_installed<R: Runtime>(app_handle: &AppHandle<R>) -> bool {
+    app_handle
+        .store(COCO_TAURI_STORE)
+        .ok()
+        .and_then(|store| store.get(AUTOSTART_ENTRY_INSTALLED))
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+}
 
-    let autostart_manager = app.autolaunch();
+fn set_entry_installed<R: Runtime>(app_handle: &AppHandle<R>, installed: bool) {
+    match app_handle.store(COCO_TAURI_STORE) {
+        Ok(store) => store.set(AUTOSTART_ENTRY_INSTALLED, installed),
+        Err(e) => log::error!("failed to save the autostart state: {}", e),
+    }
+}
+
+/// The XDG autostart entry.
+#[cfg(target_os = "linux")]
+mod entry {
+    use super::AutostartSettings;
+    use std::path::PathBuf;
+    use tauri::{AppHandle, Runtime};
+
+    fn path<R: Runtime>(app_handle: &AppHandle<R>) -> Result<PathBuf, String> {
+        let config_dir = dirs::config_dir().ok_or("not found the config directory")?;
+        Ok(config_dir
+            .join("autostart")
+            .join(format!("{}.desktop", app_handle.package_info().name)))
+    }
+
+    /// Whether the entry exists and is not disabled.
+    pub(super) fn is_active<R: Runtime>(app_handle: &AppHandle<R>) -> Result<bool, String> {
+        let path = path(app_handle)?;
+        if !path.exists() {
+            return Ok(false);
+        }
 
-    let change = |open: bool| -> Result<(), String> {
-        #[allow(unused_assignments)]
-        let mut open_str = String::from("");
-        if open {
-            autostart_manager
-                .enable()
-                .map_err(|_| "enable autostar failed".to_owned())?;
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read [{}]: {}", path.display(), e))?;
+        Ok(super::desktop_entry_is_active(&content))
+    }
 
-            open_str = "true".to_owned();
-        } else {
-            autostart_manager
-                .disable()
-                .map_err(|_| "disable autostar failed".to_owned())?;
+    pub(super) fn write<R: Runtime>(
+        app_handle: &AppHandle<R>,
+        autostart: &AutostartSettings,
+    ) -> Result<(), String> {
+        // The AppImage is extracted to a temporary directory, start the image
+        let exe = match std::env::var_os("APPIMAGE") {
+            Some(appimage) => PathBuf::from(appimage),
+            None => std::env::current_exe().map_err(|e| e.to_string())?,
+        };
+        let content = super::desktop_entry(
+            &app_handle.package_info().name,
+            &exe.to_string_lossy(),
+            &autostart.launch_args(),
+        );
 
-            open_str = "false".to_owned();
+        let path = path(app_handle)?;
+        // Rewritten only if it is outdated, e.g., the app has been moved
+        if std::fs::read_to_string(&path).is_ok_and(|existing| existing == content) {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("failed to create [{}]: {}", dir.display(), e))?;
         }
-        let path = app
-            .path()
-            .app_config_dir()
-            .map_err(|_| "not found app config directory".to_owned())?;
-        if path.exists() == false {
-            create_dir(&path).map_err(|_| "creating app config directory failed".to_owned())?;
+        std::fs::write(&path, content)
+            .map_err(|e| format!("failed to write [{}]: {}", path.display(), e))?;
+        log::info!("wrote the autostart entry [{}]", path.display());
+
+        Ok(())
+    }
+
+    pub(super) fn remove<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
+        let path = path(app_handle)?;
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                log::info!("removed the autostart entry [{}]", path.display());
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("failed to remove [{}]: {}", path.display(), e)),
         }
+    }
+}
+
+/// The entry managed by the autostart plugin.
+#[cfg(not(target_os = "linux"))]
+mod entry {
+    use super::AutostartSettings;
+    use tauri::{AppHandle, Runtime};
+    use tauri_plugin_autostart::ManagerExt;
 
-        let file_path = path.join("autostart.txt");
-        let mut file = File::create(file_path).unwrap();
-        file.write_all(open_str.as_bytes()).unwrap();
+    pub(super) fn is_active<R: Runtime>(app_handle: &AppHandle<R>) -> Result<bool, String> {
+        app_handle
+            .autolaunch()
+            .is_enabled()
+            .map_err(|e| e.to_string())
+    }
+
+    pub(super) fn write<R: Runtime>(
+        app_handle: &AppHandle<R>,
+        _autostart: &AutostartSettings,
+    ) -> Result<(), String> {
+        app_handle.autolaunch().enable().map_err(|e| e.to_string())
+    }
 
+    pub(super) fn remove<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
+        let autolaunch = app_handle.autolaunch();
+        if autolaunch.is_enabled().map_err(|e| e.to_string())? {
+            autolaunch.disable().map_err(|e| e.to_string())?;
+        }
         Ok(())
+    }
+}
+
+/// Quote an argument of the `Exec` key of a desktop entry.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn quote_exec_arg(arg: &str) -> String {
+    let escaped = if arg
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+=:,".contains(c))
+    {
+        arg.to_string()
+    } else {
+        let mut quoted = String::from("\"");
+        for c in arg.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
     };
 
-    match (autostart_manager.is_enabled().unwrap(), open) {
-        (false, true) => change(true),
-        (true, false) => change(false),
-        _ => Err("no change".to_owned()),
+    // The value of the key is unescaped once more, and `%` starts field codes
+    escaped.replace('\\', "\\\\").replace('%', "%%")
+}
+
+/// The content of the autostart desktop entry.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn desktop_entry(name: &str, exe: &str, args: &[String]) -> String {
+    let exec = std::iter::once(exe)
+        .chain(args.iter().map(String::as_str))
+        .map(quote_exec_arg)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Version=1.0\n\
+         Name={name}\n\
+         Comment=Start {name} when logging in\n\
+         Exec={exec}\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n\
+         X-Coco-Managed=true\n"
+    )
+}
+
+/// Whether a desktop entry is enabled, desktops disable it with `Hidden` or
+/// the GNOME specific key instead of removing it.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn desktop_entry_is_active(content: &str) -> bool {
+    let mut in_main_group = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_main_group = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_main_group {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match (key.trim(), value.trim()) {
+            ("Hidden", "true") | ("X-GNOME-Autostart-enabled", "false") => return false,
+            _ => {}
+        }
     }
+
+    true
+}
+
+/// Kept for the frontend, the settings can also be updated with
+/// [`update_settings`](crate::settings::update_settings).
+#[tauri::command]
+pub async fn change_autostart<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    open: bool,
+) -> Result<(), String> {
+    settings::update(&app, |settings| {
+        settings.autostart.enabled = open;
+        Ok(())
+    })
+    .map(|_| ())
+}
+
+#[test]
+fn test_desktop_entry() {
+    let args = AutostartSettings {
+        delay_secs: 30,
+        ..Default::default()
+    }
+    .launch_args();
+    let content = desktop_entry("Coco-AI", "/opt/Coco AI/coco$1", &args);
+
+    assert!(content.contains("Exec=\"/opt/Coco AI/coco\\\\$1\" --autostart --autostart-delay=30\n"));
+    assert!(desktop_entry_is_active(&content));
+    assert!(!desktop_entry_is_active(&content.replace(
+        "X-GNOME-Autostart-enabled=true",
+        "X-GNOME-Autostart-enabled=false"
+    )));
+    assert!(!desktop_entry_is_active(&format!(
+        "{}Hidden=true\n",
+        content
+    )));
+    assert_eq!(quote_exec_arg("100%"), "\"100%%\"");
 }
@@ -0,0 +1,647 @@
+//! Command-line interface.
+//!
+//! ```text
+//! coco search <query> [--size <n>] [--json]
+//! coco open <query>
+//! coco ask <assistant ID> <message> [--server <server ID>] [--json]
+//! coco server add <endpoint>
+//! ```
+//!
+//! The commands run in the running instance: it listens on a loopback port,
+//! which is written with a per-session token to `cli.json` in the app data
+//! directory, only readable by the user. The CLI sends the command, then
+//! prints the responses it streams back. If Coco is not running, it starts
+//! headless, runs the command and exits. Whether it runs is told by `cli.lock`,
+//! which the running instance holds locked.
+//!
+//! On Windows, release builds have no console, so nothing is printed.
+
+use crate::common::search::MultiSourceQueryResponse;
+use crate::document_hotkey::DocumentHotkeyTarget;
+use crate::local::LOCAL_QUERY_SOURCE_TYPE;
+use crate::server::servers::get_all_servers;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpStream};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+const USAGE: &str = "\
+Usage:
+  coco search <query> [--size <n>] [--json]   Search and print the results
+  coco open <query>                           Open the best result
+  coco ask <assistant> <message> [--server <server>] [--json]
+                                              Ask an assistant
+  coco server add <endpoint>                  Add a Coco server
+  coco help                                   Print this help";
+
+/// File with the port and the token of the running instance.
+const CLI_ENDPOINT_FILE: &str = "cli.json";
+/// File locked by the running instance, to tell whether Coco is running when
+/// its endpoint file is missing.
+const CLI_LOCK_FILE: &str = "cli.lock";
+const CLI_TOKEN_LEN: usize = 32;
+const DEFAULT_SEARCH_SIZE: u64 = 10;
+/// Time limit of the searches, in milliseconds.
+const CLI_QUERY_TIMEOUT: u64 = 5000;
+/// The running instance should answer within this delay, the replies of the
+/// assistants included.
+const CLI_READ_TIMEOUT: Duration = Duration::from_secs(300);
+/// The request of a client should be sent within this delay.
+const CLI_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum size of the request of a client, in bytes.
+const MAX_CLI_REQUEST_SIZE: u64 = 64 * 1024;
+
+/// Set when Coco runs a command headless.
+static HEADLESS_ARGS: OnceLock<CliArgs> = OnceLock::new();
+/// The endpoint file of this instance, removed upon exit.
+static ENDPOINT_FILE: OnceLock<PathBuf> = OnceLock::new();
+/// The lock file of this instance, held until exit.
+static LOCK_FILE: OnceLock<std::fs::File> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum CliCommand {
+    Search {
+        query: String,
+        size: u64,
+    },
+    Open {
+        query: String,
+    },
+    Ask {
+        assistant_id: String,
+        message: String,
+        server_id: Option<String>,
+    },
+    AddServer {
+        endpoint: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliArgs {
+    pub command: CliCommand,
+    /// Print the responses as JSON.
+    pub json: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParsedArgs {
+    /// A regular start, e.g., with a deep link.
+    NotCli,
+    Help,
+    Command(CliArgs),
+}
+
+#[derive(Serialize, Deserialize)]
+struct CliRequest {
+    token: String,
+    #[serde(flatten)]
+    command: CliCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CliResponse {
+    /// A line of the reply of an assistant.
+    Chunk { line: String },
+    /// The outcome of the command, the last response.
+    Result { value: Json },
+    /// The command failed, the last response.
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct CliEndpoint {
+    port: u16,
+    token: String,
+}
+
+/// Parse the arguments Coco is started with, the program name included.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<ParsedArgs, String> {
+    let mut args = args.into_iter().skip(1);
+    let Some(subcommand) = args.next() else {
+        return Ok(ParsedArgs::NotCli);
+    };
+    if !matches!(
+        subcommand.as_str(),
+        "search" | "open" | "ask" | "server" | "help" | "--help"
+    ) {
+        return Ok(ParsedArgs::NotCli);
+    }
+
+    let mut json = false;
+    let mut size = DEFAULT_SEARCH_SIZE;
+    let mut server_id = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--size" => {
+                size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .filter(|size| *size > 0)
+                    .ok_or("--size expects a positive number")?;
+            }
+            "--server" => server_id = Some(args.next().ok_or("--server expects a server ID")?),
+            "--help" => return Ok(ParsedArgs::Help),
+            "--" => positional.extend(args.by_ref()),
+            _ => positional.push(arg),
+        }
+    }
+
+    let query = || {
+        let query = positional.join(" ");
+        if query.trim().is_empty() {
+            Err(format!("`coco {}` expects a query", subcommand))
+        } else {
+            Ok(query)
+        }
+    };
+    let command = match subcommand.as_str() {
+        "search" => CliCommand::Search {
+            query: query()?,
+            size,
+        },
+        "open" => CliCommand::Open { query: query()? },
+        "ask" => match positional.split_first() {
+            Some((assistant_id, message)) if !message.is_empty() => CliCommand::Ask {
+                assistant_id: assistant_id.clone(),
+                message: message.join(" "),
+                server_id,
+            },
+            _ => return Err("`coco ask` expects an assistant and a message".into()),
+        },
+        "server" => match positional.as_slice() {
+            [action, endpoint] if action == "add" => CliCommand::AddServer {
+                endpoint: endpoint.clone(),
+            },
+            _ => return Err("`coco server` expects `add <endpoint>`".into()),
+        },
+        _ => return Ok(ParsedArgs::Help),
+    };
+
+    Ok(ParsedArgs::Command(CliArgs { command, json }))
+}
+
+/// Handle the command-line arguments before anything else, called upon app
+/// start. It exits once the command has run in the running instance, Coco
+/// continues to start headless otherwise.
+pub fn handle_args(identifier: &str) {
+    let args = match parse_args(std::env::args()) {
+        Ok(ParsedArgs::NotCli) => return,
+        Ok(ParsedArgs::Help) => {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        Ok(ParsedArgs::Command(args)) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(code) = run_in_running_instance(identifier, &args) {
+        std::process::exit(code);
+    }
+    if is_locked(identifier) {
+        eprintln!("Coco is running but does not accept commands yet, please try again");
+        std::process::exit(1);
+    }
+    let _ = HEADLESS_ARGS.set(args);
+}
+
+/// The command Coco runs headless, if any.
+pub(crate) fn headless_args() -> Option<&'static CliArgs> {
+    HEADLESS_ARGS.get()
+}
+
+fn endpoint_file(identifier: &str) -> Option<PathBuf> {
+    // Same as the app local data directory of Tauri
+    dirs::data_local_dir().map(|dir| dir.join(identifier).join(CLI_ENDPOINT_FILE))
+}
+
+fn lock_file(identifier: &str) -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join(identifier).join(CLI_LOCK_FILE))
+}
+
+/// Whether another instance holds the lock file, i.e., Coco is running.
+fn is_locked(identifier: &str) -> bool {
+    let Some(path) = lock_file(identifier) else {
+        return false;
+    };
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+
+    // The lock is released once the file is closed
+    file.try_lock_shared().is_err()
+}
+
+/// Hold the lock file until exit, so that the CLI does not start headless
+/// while this instance runs.
+fn lock_instance(identifier: &str) -> Result<(), String> {
+    let path = lock_file(identifier).ok_or("not found the app data directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    file.try_lock_exclusive().map_err(|e| e.to_string())?;
+    let _ = LOCK_FILE.set(file);
+
+    Ok(())
+}
+
+/// Send `args` to the running instance and print its responses. Return the
+/// exit code, or `None` if Coco is not running.
+fn run_in_running_instance(identifier: &str, args: &CliArgs) -> Option<i32> {
+    let content = std::fs::read_to_string(endpoint_file(identifier)?).ok()?;
+    let endpoint: CliEndpoint = serde_json::from_str(&content).ok()?;
+    let mut stream = TcpStream::connect_timeout(
+        &(Ipv4Addr::LOCALHOST, endpoint.port).into(),
+        Duration::from_secs(1),
+    )
+    .ok()?;
+
+    let request = CliRequest {
+        token: endpoint.token,
+        command: args.command.clone(),
+    };
+    let mut line = serde_json::to_string(&request).expect("Failed to serialize CLI request");
+    line.push('\n');
+    if stream.write_all(line.as_bytes()).is_err() {
+        return None;
+    }
+    let _ = stream.set_read_timeout(Some(CLI_READ_TIMEOUT));
+
+    let mut printer = Printer::new(args);
+    for line in BufReader::new(stream).lines() {
+        let response = line
+            .map_err(|e| e.to_string())
+            .and_then(|line| serde_json::from_str(&line).map_err(|e| e.to_string()));
+        match response {
+            Ok(response) => printer.print(response),
+            Err(e) => {
+                printer.print(CliResponse::Error {
+                    message: format!("failed to read the response of Coco: {}", e),
+                });
+                break;
+            }
+        }
+    }
+
+    Some(printer.exit_code())
+}
+
+/// Listen for the commands of the CLI, called upon app start.
+pub(crate) fn start_cli_listener<R: Runtime>(app_handle: AppHandle<R>) {
+    if let Err(e) = lock_instance(&app_handle.config().identifier) {
+        log::warn!("failed to lock the CLI lock file: {}", e);
+    }
+
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = listen(app_handle).await {
+            log::error!("failed to listen for the CLI: {}", e);
+        }
+    });
+}
+
+async fn listen<R: Runtime>(app_handle: AppHandle<R>) -> Result<(), String> {
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .map_err(|e| e.to_string())?;
+    let endpoint = CliEndpoint {
+        port: listener.local_addr().map_err(|e| e.to_string())?.port(),
+        token: crate::server::auth::random_string(CLI_TOKEN_LEN),
+    };
+
+    let path =
+        endpoint_file(&app_handle.config().identifier).ok_or("not found the app data directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    crate::server::secret_store::write_private_file(
+        &path,
+        serde_json::to_string(&endpoint)
+            .expect("Failed to serialize CLI endpoint")
+            .as_bytes(),
+    )?;
+    let _ = ENDPOINT_FILE.set(path);
+
+    loop {
+        let (stream, _) = listener.accept().await.map_err(|e| e.to_string())?;
+        let app_handle = app_handle.clone();
+        let token = endpoint.token.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = handle_connection(app_handle, stream, &token).await {
+                log::warn!("failed to handle a CLI command: {}", e);
+            }
+        });
+    }
+}
+
+/// Remove the endpoint file, called upon exit.
+pub(crate) fn remove_endpoint_file() {
+    if let Some(path) = ENDPOINT_FILE.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+async fn handle_connection<R: Runtime>(
+    app_handle: AppHandle<R>,
+    stream: tokio::net::TcpStream,
+    token: &str,
+) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = tokio::io::BufReader::new(reader.take(MAX_CLI_REQUEST_SIZE));
+    let mut line = String::new();
+    let read = tokio::time::timeout(CLI_REQUEST_TIMEOUT, reader.read_line(&mut line))
+        .await
+        .map_err(|_| "timed out reading the request".to_string())?
+        .map_err(|e| e.to_string())?;
+    if read == 0 {
+        return Ok(());
+    }
+    if !line.ends_with('\n') {
+        return Err("the request is too large or incomplete".into());
+    }
+
+    let (tx, mut rx) = unbounded_channel();
+    match serde_json::from_str::<CliRequest>(&line) {
+        Ok(request) if bool::from(request.token.as_bytes().ct_eq(token.as_bytes())) => {
+            tauri::async_runtime::spawn(async move {
+                run_command(app_handle, request.command, tx).await;
+            });
+        }
+        Ok(_) => {
+            let _ = tx.send(CliResponse::Error {
+                message: "invalid token".into(),
+            });
+        }
+        Err(e) => {
+            let _ = tx.send(CliResponse::Error {
+                message: format!("invalid command: {}", e),
+            });
+        }
+    }
+
+    while let Some(response) = rx.recv().await {
+        let mut line = serde_json::to_string(&response).expect("Failed to serialize CLI response");
+        line.push('\n');
+        writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Run the command of the headless instance and print its responses, return
+/// the exit code.
+pub(crate) async fn run_headless<R: Runtime>(app_handle: AppHandle<R>, args: &CliArgs) -> i32 {
+    // The frontend does it otherwise
+    if let Err(e) = crate::local::init_local_search_source(&app_handle).await {
+        log::warn!("failed to initialize the local search sources: {}", e);
+    }
+
+    let (tx, mut rx) = unbounded_channel();
+    let command = args.command.clone();
+    tauri::async_runtime::spawn(async move {
+        run_command(app_handle, command, tx).await;
+    });
+
+    let mut printer = Printer::new(args);
+    while let Some(response) = rx.recv().await {
+        printer.print(response);
+    }
+    printer.exit_code()
+}
+
+async fn run_command<R: Runtime>(
+    app_handle: AppHandle<R>,
+    command: CliCommand,
+    tx: UnboundedSender<CliResponse>,
+) {
+    log::debug!("running CLI command {:?}", command);
+
+    let response = match execute(app_handle, command, &tx).await {
+        Ok(value) => CliResponse::Result { value },
+        Err(message) => CliResponse::Error { message },
+    };
+    let _ = tx.send(response);
+}
+
+async fn search<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    query: String,
+    size: u64,
+) -> Result<MultiSourceQueryResponse, String> {
+    let query_strings = HashMap::from([("query".to_string(), query)]);
+    crate::search::query_coco_fusion(
+        app_handle.clone(),
+        0,
+        size,
+        query_strings,
+        CLI_QUERY_TIMEOUT,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+async fn execute<R: Runtime>(
+    app_handle: AppHandle<R>,
+    command: CliCommand,
+    tx: &UnboundedSender<CliResponse>,
+) -> Result<Json, String> {
+    match command {
+        CliCommand::Search { query, size } => {
+            let response = search(&app_handle, query, size).await?;
+            Ok(serde_json::to_value(response).expect("Failed to serialize search results"))
+        }
+        CliCommand::Open { query } => {
+            let response = search(&app_handle, query.clone(), 1).await?;
+            let hit = response
+                .hits
+                .into_iter()
+                .next()
+                .ok_or_else(|| format!("nothing found for [{}]", query))?;
+            // Whatever is not local comes from a server, e.g., its offline
+            // cache, and is not trusted to run commands
+            let server_id = hit
+                .source
+                .filter(|source| source.r#type != LOCAL_QUERY_SOURCE_TYPE)
+                .map(|source| source.id);
+
+            let target = DocumentHotkeyTarget::from_document(&hit.document, server_id)?;
+            target.open(app_handle).await?;
+            Ok(serde_json::to_value(target).expect("Failed to serialize document"))
+        }
+        CliCommand::Ask {
+            assistant_id,
+            message,
+            server_id,
+        } => {
+            let server_id = match server_id {
+                Some(id) => id,
+                None => {
+                    // Only guess the server if there is no ambiguity
+                    let mut enabled = get_all_servers().into_iter().filter(|s| s.enabled);
+                    match (enabled.next(), enabled.next()) {
+                        (Some(server), None) => server.id,
+                        _ => return Err("specify the server with --server".into()),
+                    }
+                }
+            };
+
+            crate::assistant::ask_assistant(&server_id, &assistant_id, &message, |line| {
+                let _ = tx.send(CliResponse::Chunk { line });
+            })
+            .await?;
+            Ok(Json::Null)
+        }
+        CliCommand::AddServer { endpoint } => {
+            let server =
+                crate::server::servers::add_coco_server(app_handle.clone(), endpoint, None, None)
+                    .await?;
+            Ok(serde_json::to_value(server).expect("Failed to serialize server"))
+        }
+    }
+}
+
+/// Prints the responses to a command.
+struct Printer<'a> {
+    args: &'a CliArgs,
+    failed: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn new(args: &'a CliArgs) -> Self {
+        Self {
+            args,
+            failed: false,
+        }
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.failed {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn print(&mut self, response: CliResponse) {
+        let text = |value: &Json, key: &str| {
+            value
+                .get(key)
+                .and_then(Json::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        match response {
+            CliResponse::Error { message } => {
+                self.failed = true;
+                eprintln!("error: {}", message);
+            }
+            CliResponse::Chunk { line } if self.args.json => println!("{}", line),
+            CliResponse::Chunk { line } => {
+                let Ok(chunk) = serde_json::from_str::<Json>(&line) else {
+                    return;
+                };
+                if chunk.get("chunk_type").and_then(Json::as_str) == Some("response") {
+                    print!("{}", text(&chunk, "message_chunk"));
+                    let _ = std::io::stdout().flush();
+                }
+            }
+            CliResponse::Result { value } if self.args.json => {
+                if !value.is_null() {
+                    println!("{}", value);
+                }
+            }
+            CliResponse::Result { value } => match &self.args.command {
+                CliCommand::Search { .. } => {
+                    for failed in value["failed"].as_array().into_iter().flatten() {
+                        eprintln!(
+                            "warning: {} failed: {}",
+                            text(&failed["source"], "name"),
+                            text(failed, "error")
+                        );
+                    }
+                    for hit in value["hits"].as_array().into_iter().flatten() {
+                        let document = &hit["document"];
+                        println!(
+                            "{}\t{}\t[{}]",
+                            text(document, "title"),
+                            text(document, "url"),
+                            text(&hit["source"], "name")
+                        );
+                    }
+                }
+                CliCommand::Open { .. } => println!("Opened {}", text(&value, "title")),
+                CliCommand::Ask { .. } => println!(),
+                CliCommand::AddServer { .. } => println!(
+                    "Added server {} ({}), enable it in the settings",
+                    text(&value, "name"),
+                    text(&value, "id")
+                ),
+            },
+        }
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let parse = |args: &[&str]| {
+        parse_args(
+            std::iter::once("coco")
+                .chain(args.iter().copied())
+                .map(String::from),
+        )
+    };
+
+    assert_eq!(parse(&[]), Ok(ParsedArgs::NotCli));
+    assert_eq!(parse(&["coco://search?q=rust"]), Ok(ParsedArgs::NotCli));
+    assert_eq!(parse(&["--autostart"]), Ok(ParsedArgs::NotCli));
+    assert_eq!(parse(&["help"]), Ok(ParsedArgs::Help));
+    assert_eq!(
+        parse(&["search", "rust", "book", "--json", "--size", "3"]),
+        Ok(ParsedArgs::Command(CliArgs {
+            command: CliCommand::Search {
+                query: "rust book".into(),
+                size: 3,
+            },
+            json: true,
+        }))
+    );
+    assert_eq!(
+        parse(&["ask", "assistant", "--server", "s1", "hello", "there"]),
+        Ok(ParsedArgs::Command(CliArgs {
+            command: CliCommand::Ask {
+                assistant_id: "assistant".into(),
+                message: "hello there".into(),
+                server_id: Some("s1".into()),
+            },
+            json: false,
+        }))
+    );
+    assert!(parse(&["search"]).is_err());
+    assert!(parse(&["ask", "assistant"]).is_err());
+    assert!(parse(&["server", "remove", "x"]).is_err());
+}
@@ -8,7 +8,7 @@ pub struct RichLabel {
     pub icon: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DataSourceReference {
     pub r#type: Option<String>,
     pub name: Option<String>,
@@ -33,7 +33,103 @@ pub struct AuthProvider {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MinimalClientVersion {
-    number: String,
+    pub number: String,
+}
+
+/// TLS settings of a single Coco server.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ServerTlsConfig {
+    /// Extra CA certificate (PEM) to trust when talking to this server, in
+    /// addition to the system ones.
+    #[serde(default)]
+    pub ca_certificate: Option<String>,
+    /// Client certificate (PEM) for mutual TLS, requires `client_key`.
+    #[serde(default)]
+    pub client_certificate: Option<String>,
+    /// PKCS #8 private key (PEM) of `client_certificate`. It is a secret,
+    /// never serialized, see [`persist_servers()`](crate::server::servers::persist_servers).
+    #[serde(default, skip_serializing)]
+    pub client_key: Option<String>,
+    /// Base64 encoded SHA-256 digest of the server certificate's
+    /// SubjectPublicKeyInfo, connections presenting another key are rejected.
+    #[serde(default)]
+    pub spki_pin: Option<String>,
+    /// Accept invalid certificates of this server, falls back to the global
+    /// setting if not set.
+    #[serde(default)]
+    pub allow_self_signature: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// Use the proxy environment variables (`HTTP_PROXY`, `HTTPS_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY`) if they are set.
+    #[default]
+    System,
+    /// Always connect directly.
+    None,
+    /// Use the proxies specified in [`ProxyConfig`].
+    Manual,
+}
+
+/// Proxy settings, used globally or for a single Coco server.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ProxyConfig {
+    #[serde(default)]
+    pub mode: ProxyMode,
+    /// Proxy URL for `http://` requests.
+    #[serde(default)]
+    pub http_proxy: Option<String>,
+    /// Proxy URL for `https://` requests.
+    #[serde(default)]
+    pub https_proxy: Option<String>,
+    /// `socks5://` proxy URL for the requests not covered by the proxies above.
+    #[serde(default)]
+    pub socks5_proxy: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Hosts that are connected to directly, a domain also matches its
+    /// subdomains, `*` matches everything.
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+/// Timeouts of one kind of requests, in milliseconds.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TimeoutProfile {
+    pub connect_timeout_ms: u64,
+    /// Maximum time to wait for the next chunk of the response, `None` means
+    /// no limit.
+    #[serde(default)]
+    pub read_timeout_ms: Option<u64>,
+    /// Time limit of the whole request, including reading the response body,
+    /// `None` means no limit.
+    #[serde(default)]
+    pub total_timeout_ms: Option<u64>,
+}
+
+/// Timeouts of the requests sent to a single Coco server.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerTimeouts {
+    /// Used by the usual request/response round trips.
+    #[serde(default = "default_normal_timeouts")]
+    pub normal: TimeoutProfile,
+    /// Used by the requests that stream their response or body, e.g., chat
+    /// replies and attachment uploads.
+    #[serde(default = "default_streaming_timeouts")]
+    pub streaming: TimeoutProfile,
+}
+
+impl Default for ServerTimeouts {
+    fn default() -> Self {
+        Self {
+            normal: default_normal_timeouts(),
+            streaming: default_streaming_timeouts(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,6 +159,48 @@ pub struct Server {
     #[serde(default = "default_priority_type")]
     pub priority: u32,
     pub stats: Option<HashMap<String, Value>>,
+    /// Local TLS settings, they are not returned by the server.
+    #[serde(default)]
+    pub tls: Option<ServerTlsConfig>,
+    /// Local proxy settings, the global ones are used if not set. The password
+    /// is a secret, never serialized, see
+    /// [`persist_servers()`](crate::server::servers::persist_servers).
+    #[serde(default, serialize_with = "serialize_without_password")]
+    pub proxy: Option<ProxyConfig>,
+    /// Local timeout settings, the defaults are used if not set.
+    #[serde(default)]
+    pub timeouts: Option<ServerTimeouts>,
+    /// Set when the server rejected our token, the user has to log in again.
+    #[serde(default)]
+    pub needs_relogin: bool,
+    /// Set when this client is older than the `minimal_client_version` of the
+    /// server, it is then excluded from search and chat.
+    #[serde(default)]
+    pub incompatible_reason: Option<String>,
+    /// Accounts used to log in to this server, empty if the user never logged
+    /// in, `profile` is the one of the active account.
+    #[serde(default)]
+    pub accounts: Vec<ServerAccount>,
+    /// ID of the account whose token is used, [`DEFAULT_ACCOUNT_ID`] if not set.
+    #[serde(default)]
+    pub active_account: Option<String>,
+    /// Set for the servers managed by the policy file, they cannot be edited or
+    /// removed from the settings.
+    #[serde(default)]
+    pub locked: bool,
+}
+
+/// ID of the account of the servers that were logged in to before multiple
+/// accounts were supported, its token is stored under the server ID alone.
+pub const DEFAULT_ACCOUNT_ID: &str = "default";
+
+/// A named account of a Coco server, its token is kept in the secret store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerAccount {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub profile: Option<UserProfile>,
 }
 
 impl PartialEq for Server {
@@ -73,6 +211,36 @@ impl PartialEq for Server {
 
 impl Eq for Server {}
 
+impl Server {
+    pub fn active_account_id(&self) -> &str {
+        self.active_account.as_deref().unwrap_or(DEFAULT_ACCOUNT_ID)
+    }
+
+    /// Set the profile of the active account, which is added if needed.
+    pub fn set_active_profile(&mut self, profile: Option<UserProfile>) {
+        let account_id = self.active_account_id().to_string();
+        self.set_account_profile(&account_id, profile);
+    }
+
+    /// Set the profile of the account `account_id`, which is added if needed.
+    pub fn set_account_profile(&mut self, account_id: &str, profile: Option<UserProfile>) {
+        match self.accounts.iter_mut().find(|a| a.id == account_id) {
+            Some(account) => account.profile = profile.clone(),
+            None => self.accounts.push(ServerAccount {
+                name: profile
+                    .as_ref()
+                    .map(|p| p.name.clone())
+                    .unwrap_or_else(|| account_id.to_string()),
+                id: account_id.to_string(),
+                profile: profile.clone(),
+            }),
+        }
+        if self.active_account_id() == account_id {
+            self.profile = profile;
+        }
+    }
+}
+
 impl Hash for Server {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.id.hash(state);
@@ -84,17 +252,38 @@ pub struct ServerAccessToken {
     #[serde(default = "default_empty_string")] // Custom default function for empty string
     pub id: String,
     pub access_token: String,
-    pub expired_at: u32, //unix timestamp in seconds
+    pub expired_at: u32, //unix timestamp in seconds, 0 if unknown
+    /// Used to get a new access token before this one expires, if the server
+    /// supports it.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
 }
 
+/// Tokens saved by the previous versions have their lifetime, rather than a
+/// timestamp, in `expired_at`. No valid timestamp is that small.
+const MIN_VALID_EXPIRED_AT: u32 = 1_000_000_000;
+
 impl ServerAccessToken {
     pub fn new(id: String, access_token: String, expired_at: u32) -> Self {
         Self {
             id,
             access_token,
             expired_at: expired_at,
+            refresh_token: None,
         }
     }
+
+    /// Unix timestamp in seconds of the expiry, `None` if unknown.
+    pub fn expiry(&self) -> Option<u32> {
+        (self.expired_at >= MIN_VALID_EXPIRED_AT).then_some(self.expired_at)
+    }
+
+    /// Whether the token expires in less than `margin_secs` seconds, false if
+    /// its expiry is unknown.
+    pub fn expires_within(&self, now: u32, margin_secs: u32) -> bool {
+        self.expiry()
+            .is_some_and(|expiry| expiry <= now.saturating_add(margin_secs))
+    }
 }
 
 impl PartialEq for ServerAccessToken {
@@ -132,3 +321,32 @@ fn default_priority_type() -> u32 {
 fn default_user_profile_type() -> Option<UserProfile> {
     None
 }
+
+fn serialize_without_password<S: serde::Serializer>(
+    proxy: &Option<ProxyConfig>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    proxy
+        .as_ref()
+        .map(|proxy| ProxyConfig {
+            password: None,
+            ..proxy.clone()
+        })
+        .serialize(serializer)
+}
+
+fn default_normal_timeouts() -> TimeoutProfile {
+    TimeoutProfile {
+        connect_timeout_ms: 3_000,
+        read_timeout_ms: Some(3_000),
+        total_timeout_ms: Some(10_000),
+    }
+}
+
+fn default_streaming_timeouts() -> TimeoutProfile {
+    TimeoutProfile {
+        connect_timeout_ms: 3_000,
+        read_timeout_ms: Some(60_000),
+        total_timeout_ms: None,
+    }
+}
@@ -0,0 +1,301 @@
+//! Router of the `coco://` deep links.
+//!
+//! Links arrive when Coco is started with one (the initial URLs of the
+//! deep-link plugin), while it is running (`on_open_url()`), and through the
+//! arguments of a second instance (single-instance plugin). All of them end up
+//! in [`handle_deep_link_urls()`], which parses, validates and dispatches them
+//! to the existing commands.
+//!
+//! Supported links:
+//!
+//! * `coco://oauth_callback?request_id=...&code=...&state=...`
+//! * `coco://search?q=...`
+//! * `coco://add-server?endpoint=...`
+//! * `coco://chat/<session ID>[?server=<server ID>]`
+
+use crate::server::servers::get_all_servers;
+use crate::{open_settings, show_coco};
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use url::Url;
+
+const DEEP_LINK_SCHEME: &str = "coco";
+
+/// Emitted with the outcome of an SSO login completed through a deep link.
+const EVENT_SSO_LOGIN_RESULT: &str = "sso-login-result";
+/// Emitted to the frontend to run a search.
+const EVENT_DEEP_LINK_SEARCH: &str = "deep-link-search";
+/// Emitted to the frontend to ask the user whether to add a server.
+const EVENT_DEEP_LINK_ADD_SERVER: &str = "deep-link-add-server";
+/// Emitted to the frontend to show a chat session.
+const EVENT_DEEP_LINK_OPEN_CHAT: &str = "deep-link-open-chat";
+
+/// The same link may be delivered by both the deep-link and the
+/// single-instance plugins, it is only handled once within this delay.
+const DUPLICATE_LINK_WINDOW: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref RECENT_LINKS: Mutex<Vec<(String, Instant)>> = Mutex::new(Vec::new());
+    /// Endpoint of the last `add-server` link, until the frontend asks the user.
+    static ref PENDING_SERVER_ENDPOINT: Mutex<Option<String>> = Mutex::new(None);
+}
+
+#[derive(Debug, PartialEq)]
+enum DeepLink {
+    OAuthCallback {
+        request_id: String,
+        code: String,
+        state: Option<String>,
+        expire_in: Option<u64>,
+    },
+    Search {
+        query: String,
+    },
+    AddServer {
+        endpoint: String,
+    },
+    Chat {
+        session_id: String,
+        server_id: Option<String>,
+    },
+}
+
+fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn required_query_param(url: &Url, name: &str) -> Result<String, String> {
+    query_param(url, name).ok_or_else(|| format!("missing parameter [{}]", name))
+}
+
+fn parse_deep_link(link: &str) -> Result<DeepLink, String> {
+    let url = Url::parse(link.trim()).map_err(|e| format!("invalid URL: {}", e))?;
+    if url.scheme() != DEEP_LINK_SCHEME {
+        return Err(format!("unsupported scheme [{}]", url.scheme()));
+    }
+
+    match url.host_str().unwrap_or_default() {
+        "oauth_callback" => Ok(DeepLink::OAuthCallback {
+            request_id: required_query_param(&url, "request_id")?,
+            code: required_query_param(&url, "code")?,
+            state: query_param(&url, "state"),
+            expire_in: query_param(&url, "expire_in").and_then(|v| v.parse().ok()),
+        }),
+        "search" => Ok(DeepLink::Search {
+            query: required_query_param(&url, "q")?,
+        }),
+        "add-server" => {
+            let endpoint = required_query_param(&url, "endpoint")?;
+            let parsed = Url::parse(&endpoint)
+                .map_err(|e| format!("invalid endpoint [{}]: {}", endpoint, e))?;
+            if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+                return Err(format!("endpoint [{}] is not an HTTP(S) URL", endpoint));
+            }
+
+            Ok(DeepLink::AddServer { endpoint })
+        }
+        "chat" => {
+            let session_id = url
+                .path_segments()
+                .and_then(|mut segments| segments.next())
+                .filter(|segment| !segment.is_empty())
+                .ok_or("missing chat session ID")?;
+            if !session_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!("invalid chat session ID [{}]", session_id));
+            }
+
+            Ok(DeepLink::Chat {
+                session_id: session_id.to_string(),
+                server_id: query_param(&url, "server"),
+            })
+        }
+        action => Err(format!("unsupported action [{}]", action)),
+    }
+}
+
+/// Whether `link` was handled recently, remembers it otherwise.
+fn is_duplicate(link: &str) -> bool {
+    let mut recent = RECENT_LINKS.lock().unwrap();
+    recent.retain(|(_, at)| at.elapsed() < DUPLICATE_LINK_WINDOW);
+
+    if recent.iter().any(|(recent_link, _)| recent_link == link) {
+        return true;
+    }
+    recent.push((link.to_string(), Instant::now()));
+
+    false
+}
+
+/// Handle the URLs Coco was opened with, the ones that are not deep links,
+/// e.g., the program path in `argv`, are ignored.
+pub(crate) fn handle_deep_link_urls<I, S>(app_handle: &AppHandle, urls: I)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    for link in urls {
+        let link = link.as_ref().trim();
+        if !link.starts_with(&format!("{}:", DEEP_LINK_SCHEME)) || is_duplicate(link) {
+            continue;
+        }
+
+        let deep_link = match parse_deep_link(link) {
+            Ok(deep_link) => deep_link,
+            Err(e) => {
+                log::warn!("ignoring deep link [{}]: {}", link, e);
+                continue;
+            }
+        };
+
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = dispatch(&app_handle, deep_link).await {
+                log::error!("failed to handle deep link: {}", e);
+            }
+        });
+    }
+}
+
+async fn dispatch(app_handle: &AppHandle, deep_link: DeepLink) -> Result<(), String> {
+    log::debug!("handling deep link {:?}", deep_link);
+
+    match deep_link {
+        DeepLink::OAuthCallback {
+            request_id,
+            code,
+            state,
+            expire_in,
+        } => {
+            let server_id = crate::server::auth::pending_sso_login_server(&request_id)
+                .ok_or_else(|| format!("no pending login for request [{}]", request_id))?;
+
+            let result = crate::server::auth::handle_sso_callback(
+                app_handle.clone(),
+                server_id.clone(),
+                request_id,
+                code,
+                state,
+                expire_in,
+            )
+            .await;
+
+            let _ = app_handle.emit(
+                EVENT_SSO_LOGIN_RESULT,
+                serde_json::json!({
+                    "server_id": server_id,
+                    "error": result.as_ref().err(),
+                }),
+            );
+
+            result
+        }
+        DeepLink::Search { query } => {
+            show_coco(app_handle.clone()).await;
+            app_handle
+                .emit(
+                    EVENT_DEEP_LINK_SEARCH,
+                    serde_json::json!({ "query": query }),
+                )
+                .map_err(|e| e.to_string())
+        }
+        DeepLink::AddServer { endpoint } => {
+            // Anyone can craft such a link, the server is neither contacted
+            // nor saved until the user confirms, through `add_coco_server`.
+            // The settings window may not be listening yet, so it also takes
+            // the endpoint once loaded.
+            *PENDING_SERVER_ENDPOINT.lock().unwrap() = Some(endpoint);
+            open_settings(app_handle);
+            app_handle
+                .emit(EVENT_DEEP_LINK_ADD_SERVER, ())
+                .map_err(|e| e.to_string())
+        }
+        DeepLink::Chat {
+            session_id,
+            server_id,
+        } => {
+            let server_id = match server_id {
+                Some(id) => id,
+                None => {
+                    // Only guess the server if there is no ambiguity
+                    let mut enabled = get_all_servers().into_iter().filter(|s| s.enabled);
+                    match (enabled.next(), enabled.next()) {
+                        (Some(server), None) => server.id,
+                        _ => return Err("the server of the chat session is not specified".into()),
+                    }
+                }
+            };
+
+            let session = crate::assistant::open_session_chat(
+                app_handle.clone(),
+                server_id.clone(),
+                session_id.clone(),
+            )
+            .await?;
+
+            show_coco(app_handle.clone()).await;
+            app_handle
+                .emit(
+                    EVENT_DEEP_LINK_OPEN_CHAT,
+                    serde_json::json!({
+                        "server_id": server_id,
+                        "session_id": session_id,
+                        "session": session,
+                    }),
+                )
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Handle a deep link pasted by the user, e.g., when the browser could not
+/// open Coco.
+#[tauri::command]
+pub async fn handle_deep_link(app_handle: AppHandle, url: String) -> Result<(), String> {
+    let deep_link = parse_deep_link(&url)?;
+    dispatch(&app_handle, deep_link).await
+}
+
+/// Take the endpoint of the last `coco://add-server` link, the frontend asks
+/// the user whether to add it.
+#[tauri::command]
+pub async fn take_pending_server_endpoint() -> Option<String> {
+    PENDING_SERVER_ENDPOINT.lock().unwrap().take()
+}
+
+#[test]
+fn test_parse_deep_link() {
+    assert_eq!(
+        parse_deep_link("coco://search?q=hello%20world"),
+        Ok(DeepLink::Search {
+            query: "hello world".into()
+        })
+    );
+    assert_eq!(
+        parse_deep_link("coco://chat/abc-123?server=s1"),
+        Ok(DeepLink::Chat {
+            session_id: "abc-123".into(),
+            server_id: Some("s1".into())
+        })
+    );
+    assert_eq!(
+        parse_deep_link("coco://oauth_callback?request_id=r&code=c&state=s"),
+        Ok(DeepLink::OAuthCallback {
+            request_id: "r".into(),
+            code: "c".into(),
+            state: Some("s".into()),
+            expire_in: None,
+        })
+    );
+    assert!(parse_deep_link("coco://add-server?endpoint=file:///etc/passwd").is_err());
+    assert!(parse_deep_link("coco://chat/..%2Fadmin").is_err());
+    assert!(parse_deep_link("coco://unknown").is_err());
+    assert!(parse_deep_link("https://search?q=x").is_err());
+}
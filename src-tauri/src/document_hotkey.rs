@@ -0,0 +1,286 @@
+//! Hotkeys opening a [`Document`], e.g., a result of a server, a saved search
+//! or an assistant.
+//!
+//! They are [action shortcuts](crate::shortcut::ShortcutAction::OpenDocument),
+//! what the hotkey opens is resolved once, when it is bound, and saved with it.
+//! Pressing it thus does not query the search source, which may be offline by
+//! then.
+
+use crate::common::document::{DataSourceReference, Document};
+use crate::local::application::QUERYSOURCE_ID_DATASOURCE_ID_DATASOURCE_NAME;
+use crate::shortcut::{self, ActionShortcut, ShortcutAction};
+use crate::show_coco;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// Emitted once Coco is shown by the hotkey of a saved search.
+const EVENT_SHORTCUT_SEARCH: &str = "shortcut-search";
+/// Emitted once Coco is shown by the hotkey of an assistant.
+const EVENT_SHORTCUT_OPEN_ASSISTANT: &str = "shortcut-open-assistant";
+
+/// What opening a document does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DocumentTarget {
+    /// Open the URL, e.g., in the browser.
+    Url { url: String },
+    /// Show Coco and run the search.
+    Search { query: String },
+    /// Show Coco to chat with the assistant of the server.
+    Assistant {
+        server_id: String,
+        assistant_id: String,
+    },
+    /// Run the command, e.g., a local application.
+    Command { path: String },
+}
+
+/// A document bound to a hotkey, with what is needed to show and open it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentHotkeyTarget {
+    /// ID of the document.
+    pub id: String,
+    /// The server the document comes from, if any.
+    pub server_id: Option<String>,
+    pub title: String,
+    pub icon: Option<String>,
+    pub source: Option<DataSourceReference>,
+    pub target: DocumentTarget,
+}
+
+fn payload_string(document: &Document, key: &str) -> Option<String> {
+    document
+        .payload
+        .as_ref()
+        .and_then(|payload| payload.get(key))
+        .and_then(|value| value.as_str())
+        .map(str::to_string)
+}
+
+impl DocumentHotkeyTarget {
+    /// Resolve what opening `document` does, `server_id` is the server it
+    /// comes from.
+    ///
+    /// Besides the documents with a URL, these are supported:
+    ///
+    /// * the local applications
+    /// * the documents of type `assistant`, their ID is the assistant ID
+    /// * the documents of type `search`, the query is the `query` of their
+    ///   payload, or their title
+    /// * the local documents of type `command`, the command is the `command`
+    ///   of their payload, or their URL
+    ///
+    /// Nothing that comes from a server is run locally, its documents are
+    /// opened as URLs.
+    pub fn from_document(document: &Document, server_id: Option<String>) -> Result<Self, String> {
+        let is_local = server_id.is_none();
+        let is_application = is_local
+            && document.source.as_ref().and_then(|s| s.id.as_deref())
+                == Some(QUERYSOURCE_ID_DATASOURCE_ID_DATASOURCE_NAME);
+
+        let target = match document.r#type.as_deref() {
+            _ if is_application => DocumentTarget::Command {
+                path: document.url.clone().unwrap_or_else(|| document.id.clone()),
+            },
+            Some("assistant") => DocumentTarget::Assistant {
+                server_id: server_id
+                    .clone()
+                    .ok_or("the server of the assistant is not specified")?,
+                assistant_id: document.id.clone(),
+            },
+            Some("search") => DocumentTarget::Search {
+                query: payload_string(document, "query")
+                    .or_else(|| document.title.clone())
+                    .ok_or("the saved search has no query")?,
+            },
+            Some("command") if is_local => DocumentTarget::Command {
+                path: payload_string(document, "command")
+                    .or_else(|| document.url.clone())
+                    .ok_or("the command has nothing to run")?,
+            },
+            _ => DocumentTarget::Url {
+                url: document
+                    .url
+                    .clone()
+                    .ok_or_else(|| format!("document [{}] has no URL", document.id))?,
+            },
+        };
+
+        let target = Self {
+            id: document.id.clone(),
+            server_id,
+            title: document
+                .title
+                .clone()
+                .unwrap_or_else(|| document.id.clone()),
+            icon: document.icon.clone(),
+            source: document.source.clone(),
+            target,
+        };
+        target.validate()?;
+
+        Ok(target)
+    }
+
+    /// Whether both are the same document, their metadata aside.
+    pub(crate) fn is_same(&self, other: &Self) -> bool {
+        self.id == other.id && self.server_id == other.server_id
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let empty = match &self.target {
+            DocumentTarget::Url { url } => url.trim().is_empty(),
+            DocumentTarget::Search { query } => query.trim().is_empty(),
+            DocumentTarget::Assistant {
+                server_id,
+                assistant_id,
+            } => server_id.is_empty() || assistant_id.is_empty(),
+            DocumentTarget::Command { path } => path.trim().is_empty(),
+        };
+        if self.id.is_empty() || empty {
+            return Err(format!(
+                "the target of document [{}] is incomplete",
+                self.title
+            ));
+        }
+
+        if self.server_id.is_some() {
+            match &self.target {
+                DocumentTarget::Command { .. } => {
+                    return Err(format!(
+                        "document [{}] of a server cannot run a command",
+                        self.title
+                    ));
+                }
+                DocumentTarget::Url { url } => {
+                    let parsed = url::Url::parse(url)
+                        .map_err(|e| format!("bad URL of document [{}]: {}", self.title, e))?;
+                    if parsed.scheme() == "file" {
+                        return Err(format!(
+                            "document [{}] of a server cannot open a local file",
+                            self.title
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn open<R: Runtime>(&self, app_handle: AppHandle<R>) -> Result<(), String> {
+        match &self.target {
+            DocumentTarget::Url { url } => crate::util::open(app_handle, url.clone()).await,
+            DocumentTarget::Command { path } => crate::util::open(app_handle, path.clone()).await,
+            DocumentTarget::Search { query } => {
+                show_coco(app_handle.clone()).await;
+                app_handle
+                    .emit(EVENT_SHORTCUT_SEARCH, serde_json::json!({ "query": query }))
+                    .map_err(|e| e.to_string())
+            }
+            DocumentTarget::Assistant {
+                server_id,
+                assistant_id,
+            } => {
+                show_coco(app_handle.clone()).await;
+                app_handle
+                    .emit(
+                        EVENT_SHORTCUT_OPEN_ASSISTANT,
+                        serde_json::json!({
+                            "server_id": server_id,
+                            "assistant_id": assistant_id,
+                            "title": self.title,
+                        }),
+                    )
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Bind `hotkey` to open `document`, a result of server `server_id` if it
+/// comes from a server.
+#[tauri::command]
+pub async fn register_document_hotkey<R: Runtime>(
+    app_handle: AppHandle<R>,
+    hotkey: String,
+    document: Document,
+    server_id: Option<String>,
+) -> Result<ActionShortcut, String> {
+    let action_shortcut = ActionShortcut {
+        shortcut: hotkey,
+        action: ShortcutAction::OpenDocument {
+            document: DocumentHotkeyTarget::from_document(&document, server_id)?,
+        },
+    };
+    shortcut::bind(&app_handle, action_shortcut.clone())?;
+
+    Ok(action_shortcut)
+}
+
+#[test]
+fn test_document_hotkey_target() {
+    let mut document = Document {
+        id: "doc".into(),
+        title: Some("Doc".into()),
+        url: Some("https://example.com/doc".into()),
+        ..Default::default()
+    };
+    let target = DocumentHotkeyTarget::from_document(&document, Some("server".into())).unwrap();
+    assert_eq!(
+        target.target,
+        DocumentTarget::Url {
+            url: "https://example.com/doc".into()
+        }
+    );
+
+    document.r#type = Some("assistant".into());
+    assert!(DocumentHotkeyTarget::from_document(&document, None).is_err());
+    let assistant = DocumentHotkeyTarget::from_document(&document, Some("server".into())).unwrap();
+    assert!(assistant.is_same(&target));
+
+    document.r#type = Some("search".into());
+    let search = DocumentHotkeyTarget::from_document(&document, None).unwrap();
+    assert_eq!(
+        search.target,
+        DocumentTarget::Search {
+            query: "Doc".into()
+        }
+    );
+    assert!(!search.is_same(&target));
+
+    // Only local documents run commands
+    document.r#type = Some("command".into());
+    let command = DocumentHotkeyTarget::from_document(&document, None).unwrap();
+    assert_eq!(
+        command.target,
+        DocumentTarget::Command {
+            path: "https://example.com/doc".into()
+        }
+    );
+    let command = DocumentHotkeyTarget::from_document(&document, Some("server".into())).unwrap();
+    assert_eq!(command.target, target.target);
+
+    document.r#type = None;
+    document.url = Some("/usr/bin/true".into());
+    document.source = Some(DataSourceReference {
+        r#type: None,
+        name: None,
+        id: Some(QUERYSOURCE_ID_DATASOURCE_ID_DATASOURCE_NAME.into()),
+        icon: None,
+    });
+    assert!(DocumentHotkeyTarget::from_document(&document, Some("server".into())).is_err());
+    let application = DocumentHotkeyTarget::from_document(&document, None).unwrap();
+    assert_eq!(
+        application.target,
+        DocumentTarget::Command {
+            path: "/usr/bin/true".into()
+        }
+    );
+
+    // Neither do the ones saved with a hotkey
+    let mut forged = application.clone();
+    forged.server_id = Some("server".into());
+    assert!(forged.validate().is_err());
+}
@@ -1,12 +1,20 @@
 mod assistant;
 mod autostart;
+mod cli;
 mod common;
+mod deep_link;
+mod document_hotkey;
 mod local;
+mod local_api;
+mod policy;
 mod search;
 mod server;
 mod settings;
+mod settings_bundle;
+mod settings_sync;
 mod setup;
 mod shortcut;
+mod store_migration;
 mod util;
 
 use crate::common::register::SearchSourceRegistry;
@@ -24,7 +32,6 @@ use tauri::ActivationPolicy;
 use tauri::{
     AppHandle, Emitter, Manager, PhysicalPosition, Runtime, WebviewWindow, Window, WindowEvent,
 };
-use tauri_plugin_autostart::MacosLauncher;
 
 /// Tauri store name
 pub(crate) const COCO_TAURI_STORE: &str = "coco_tauri_store";
@@ -61,25 +68,30 @@ struct Payload {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    autostart::wait_for_start_delay();
+
     let ctx = tauri::generate_context!();
 
+    // Exits if the command runs in the running instance
+    cli::handle_args(&ctx.config().identifier);
+
     let mut app_builder = tauri::Builder::default();
 
+    // A headless instance exits once its command has run, the regular starts
+    // should not be forwarded to it
     #[cfg(desktop)]
-    {
-        app_builder = app_builder.plugin(tauri_plugin_single_instance::init(|_app, argv, _cwd| {
-            log::debug!("a new app instance was opened with {argv:?} and the deep link event was already triggered");
-            // when defining deep link schemes at runtime, you must also check `argv` here
+    if cli::headless_args().is_none() {
+        app_builder = app_builder.plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
+            log::debug!("a new app instance was opened with {argv:?}");
+            // The deep-link plugin may have been notified as well, the router
+            // ignores the links it has just handled
+            deep_link::handle_deep_link_urls(app, argv);
         }));
     }
 
     app_builder = app_builder
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_shell::init())
-        .plugin(tauri_plugin_autostart::init(
-            MacosLauncher::AppleScript,
-            None,
-        ))
         .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(tauri_plugin_dialog::init())
@@ -87,6 +99,7 @@ pub fn run() {
         .plugin(tauri_plugin_macos_permissions::init())
         .plugin(tauri_plugin_screenshots::init())
         .plugin(tauri_plugin_process::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_windows_version::init())
         .plugin(set_up_tauri_logger());
@@ -103,6 +116,10 @@ pub fn run() {
             shortcut::change_shortcut,
             shortcut::unregister_shortcut,
             shortcut::get_current_shortcut,
+            shortcut::get_action_shortcuts,
+            shortcut::register_action_shortcut,
+            shortcut::unregister_action_shortcut,
+            document_hotkey::register_document_hotkey,
             change_autostart,
             show_coco,
             hide_coco,
@@ -115,12 +132,21 @@ pub fn run() {
             server::servers::refresh_coco_server_info,
             server::servers::enable_server,
             server::servers::disable_server,
+            server::servers::set_server_tls_config,
+            server::servers::set_server_proxy_config,
+            server::servers::set_server_timeouts,
+            server::auth::start_sso_login,
             server::auth::handle_sso_callback,
+            deep_link::handle_deep_link,
+            deep_link::take_pending_server_endpoint,
+            server::auth::login_with_access_token,
             server::profile::get_user_profiles,
             server::datasource::datasource_search,
             server::datasource::mcp_server_search,
             server::connector::get_connectors_by_server,
             search::query_coco_fusion,
+            search::adaptive_timeout::get_search_source_latency_stats,
+            search::adaptive_timeout::set_search_source_timeout,
             assistant::chat_history,
             assistant::new_chat,
             assistant::send_message,
@@ -156,8 +182,29 @@ pub fn run() {
             local::application::enable_app_search,
             local::application::add_app_search_path,
             local::application::remove_app_search_path,
+            settings::get_settings,
+            settings::update_settings,
             settings::set_allow_self_signature,
             settings::get_allow_self_signature,
+            settings::set_offline_cache_enabled,
+            settings::get_offline_cache_enabled,
+            settings::set_proxy_config,
+            settings::get_proxy_config,
+            server::offline_cache::record_opened_document,
+            server::offline_cache::clear_offline_cache,
+            server::circuit_breaker::get_server_circuit_states,
+            server::health_monitor::get_server_health_reports,
+            server::account::list_server_accounts,
+            server::account::add_server_account,
+            server::account::switch_server_account,
+            server::account::remove_server_account,
+            policy::get_policy,
+            settings_bundle::export_settings,
+            settings_bundle::import_settings,
+            settings_sync::enable_settings_sync,
+            settings_sync::disable_settings_sync,
+            settings_sync::sync_settings,
+            settings_sync::get_settings_sync_status,
             assistant::ask_ai
         ])
         .setup(|app| {
@@ -175,9 +222,21 @@ pub fn run() {
                 init(app.handle()).await;
             });
 
-            shortcut::enable_shortcut(app);
+            if let Some(args) = cli::headless_args() {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let code = cli::run_headless(app_handle.clone(), args).await;
+                    app_handle.exit(code);
+                });
+            } else {
+                shortcut::enable_shortcut(app);
+
+                enable_autostart(app);
 
-            enable_autostart(app);
+                cli::start_cli_listener(app.handle().clone());
+
+                local_api::enable_local_api(app.handle());
+            }
 
             #[cfg(target_os = "macos")]
             app.set_activation_policy(ActivationPolicy::Accessory);
@@ -199,9 +258,21 @@ pub fn run() {
                 }
             }
 
-            // app.deep_link().on_open_url(|event| {
-            //     dbg!(event.urls());
-            // });
+            {
+                use tauri_plugin_deep_link::DeepLinkExt;
+
+                let handle = app.handle().clone();
+                app.deep_link().on_open_url(move |event| {
+                    deep_link::handle_deep_link_urls(&handle, event.urls());
+                });
+
+                // The links Coco was started with
+                match app.deep_link().get_current() {
+                    Ok(Some(urls)) => deep_link::handle_deep_link_urls(app.handle(), urls),
+                    Ok(None) => {}
+                    Err(e) => log::error!("Failed to get the initial deep links: {}", e),
+                }
+            }
 
             let main_window = app.get_webview_window(MAIN_WINDOW_LABEL).unwrap();
             let settings_window = app.get_webview_window(SETTINGS_WINDOW_LABEL).unwrap();
@@ -234,6 +305,11 @@ pub fn run() {
                 return;
             }
         }
+        tauri::RunEvent::Exit => {
+            server::offline_cache::flush_offline_cache();
+            cli::remove_endpoint_file();
+            local_api::remove_endpoint_file();
+        }
         _ => {
             let _ = app_handle;
         }
@@ -241,6 +317,18 @@ pub fn run() {
 }
 
 pub async fn init<R: Runtime>(app_handle: &AppHandle<R>) {
+    store_migration::migrate_stores(app_handle);
+    autostart::migrate_autostart_file(app_handle);
+    // The proxy password of the settings is a secret
+    if let Err(err) = server::secret_store::init_secret_store(app_handle) {
+        log::error!("Failed to initialize the secret store: {}", err);
+    }
+    settings::load_settings(app_handle);
+    server::http_client::watch_settings();
+    server::offline_cache::watch_settings();
+    shortcut::watch_settings();
+    policy::load_policy(app_handle);
+
     // Await the async functions to load the servers and tokens
     if let Err(err) = load_or_insert_default_server(app_handle).await {
         log::error!("Failed to load servers: {}", err);
@@ -250,6 +338,20 @@ pub async fn init<R: Runtime>(app_handle: &AppHandle<R>) {
         log::error!("Failed to load server tokens: {}", err);
     }
 
+    if let Err(err) = policy::apply_policy(app_handle).await {
+        log::error!("Failed to apply the policy: {}", err);
+    }
+
+    if let Err(err) = server::offline_cache::load_offline_cache(app_handle) {
+        log::error!("Failed to load the offline document cache: {}", err);
+    }
+
+    search::adaptive_timeout::load_timeout_overrides(app_handle);
+
+    server::auth::start_token_refresh_task();
+    server::health_monitor::start_health_monitor(app_handle.clone());
+    settings_sync::start_settings_sync(app_handle.clone());
+
     let coco_servers = server::servers::get_all_servers();
 
     // Get the registry from Tauri's state
@@ -581,6 +683,14 @@ fn set_up_tauri_logger() -> TauriPlugin<tauri::Wry> {
     }
 
     let mut builder = tauri_plugin_log::Builder::new();
+    // The output of a headless command is printed to stdout
+    if cli::headless_args().is_some() {
+        use tauri_plugin_log::{Target, TargetKind};
+
+        builder = builder
+            .clear_targets()
+            .target(Target::new(TargetKind::LogDir { file_name: None }));
+    }
     builder = builder.format(|out, message, record| {
         let now = chrono::Local::now().format("%m-%d %H:%M:%S");
         let level = format_log_level(record.level());
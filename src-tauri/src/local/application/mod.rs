@@ -12,6 +12,14 @@ pub use with_feature::*;
 #[cfg(not(feature = "use_pizza_engine"))]
 pub use without_feature::*;
 
+pub(crate) const TAURI_STORE_DISABLED_APP_LIST_AND_SEARCH_PATH: &str =
+    "disabled_app_list_and_search_path";
+pub(crate) const TAURI_STORE_APP_HOTKEY: &str = "app_hotkey";
+pub(crate) const TAURI_STORE_APP_ALIAS: &str = "app_alias";
+
+pub(crate) const TAURI_STORE_KEY_SEARCH_PATH: &str = "search_path";
+pub(crate) const TAURI_STORE_KEY_DISABLED_APP_LIST: &str = "disabled_app_list";
+
 #[derive(Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct AppEntry {
@@ -3,11 +3,16 @@ use super::super::Task;
 use super::super::RUNTIME_TX;
 use super::AppEntry;
 use super::AppMetadata;
+use super::{
+    TAURI_STORE_APP_ALIAS, TAURI_STORE_APP_HOTKEY, TAURI_STORE_DISABLED_APP_LIST_AND_SEARCH_PATH,
+    TAURI_STORE_KEY_DISABLED_APP_LIST, TAURI_STORE_KEY_SEARCH_PATH,
+};
 use crate::common::document::{DataSourceReference, Document};
 use crate::common::error::SearchError;
 use crate::common::search::{QueryResponse, QuerySource, SearchQuery};
 use crate::common::traits::SearchSource;
 use crate::local::LOCAL_QUERY_SOURCE_TYPE;
+use crate::shortcut::{check_shortcut, ShortcutOwner};
 use crate::util::open;
 use crate::GLOBAL_TAURI_APP_HANDLE;
 use applications::{App, AppTrait};
@@ -41,13 +46,6 @@ const FIELD_ICON_PATH: &str = "icon_path";
 const FIELD_APP_ALIAS: &str = "app_alias";
 const APPLICATION_SEARCH_SOURCE_ID: &str = "application";
 
-const TAURI_STORE_DISABLED_APP_LIST_AND_SEARCH_PATH: &str = "disabled_app_list_and_search_path";
-const TAURI_STORE_APP_HOTKEY: &str = "app_hotkey";
-const TAURI_STORE_APP_ALIAS: &str = "app_alias";
-
-const TAURI_STORE_KEY_SEARCH_PATH: &str = "search_path";
-const TAURI_STORE_KEY_DISABLED_APP_LIST: &str = "disabled_app_list";
-
 const THREAD_NAME_APP_SYNCHRONIZER: &str = "local app search - app list synchronizer";
 
 /// We use this as:
@@ -745,39 +743,93 @@ fn register_app_hotkey_upon_start<R: Runtime>(
 ) -> Result<(), String> {
     let app_hotkey_store = tauri_app_handle
         .store(TAURI_STORE_APP_HOTKEY)
-        .unwrap_or_else(|_| panic!("store [{}] not found/loaded", TAURI_STORE_APP_HOTKEY));
+        .map_err(|e| e.to_string())?;
 
+    // A hotkey that cannot be registered should not prevent the other ones
     for (app_path, hotkey) in app_hotkey_store.entries() {
-        let hotkey = match hotkey {
-            Json::String(str) => str,
-            _ => unreachable!("hotkey should be stored in a string"),
+        let Json::String(hotkey) = hotkey else {
+            warn!("ignoring the hotkey of [{}], it is not a string", app_path);
+            continue;
         };
+        let owner = ShortcutOwner::Application(app_path.clone());
+        if let Err(e) = check_shortcut(&tauri_app_handle, &hotkey, &owner) {
+            warn!("ignoring the hotkey of [{}]: {}", app_path, e);
+            continue;
+        }
 
-        tauri_app_handle
+        if let Err(e) = tauri_app_handle
             .global_shortcut()
-            .on_shortcut(hotkey.as_str(), app_hotkey_handler(app_path))
-            .map_err(|e| e.to_string())?;
+            .on_shortcut(hotkey.as_str(), app_hotkey_handler(app_path.clone()))
+        {
+            error!(
+                "failed to register hotkey [{}] of [{}]: {}",
+                hotkey, app_path, e
+            );
+        }
     }
 
     Ok(())
 }
 
+/// Unregister the `previous_hotkeys`, then register the hotkeys in the store,
+/// used when the store is rewritten, e.g., by a settings import.
+pub(crate) fn reload_app_hotkeys<R: Runtime>(
+    tauri_app_handle: &AppHandle<R>,
+    previous_hotkeys: &[String],
+) -> Result<(), String> {
+    for hotkey in previous_hotkeys {
+        if let Err(e) = tauri_app_handle
+            .global_shortcut()
+            .unregister(hotkey.as_str())
+        {
+            warn!(
+                "failed to unregister application hotkey [{}]: {}",
+                hotkey, e
+            );
+        }
+    }
+
+    register_app_hotkey_upon_start(tauri_app_handle.clone())
+}
+
 #[tauri::command]
 pub async fn register_app_hotkey<R: Runtime>(
     tauri_app_handle: AppHandle<R>,
     app_path: String,
     hotkey: String,
 ) -> Result<(), String> {
+    let owner = ShortcutOwner::Application(app_path.clone());
+    let shortcut = check_shortcut(&tauri_app_handle, &hotkey, &owner)?;
+
     let app_hotkey_store = tauri_app_handle
         .store(TAURI_STORE_APP_HOTKEY)
-        .unwrap_or_else(|_| panic!("store [{}] not found/loaded", TAURI_STORE_APP_HOTKEY));
+        .map_err(|e| e.to_string())?;
+    let previous_hotkey = app_hotkey_store
+        .get(app_path.as_str())
+        .and_then(|hotkey| hotkey.as_str().map(str::to_string));
 
-    app_hotkey_store.set(app_path.clone(), hotkey.as_str());
+    // Registered before it is saved, so that an error of the OS is returned
+    if !tauri_app_handle.global_shortcut().is_registered(shortcut) {
+        tauri_app_handle
+            .global_shortcut()
+            .on_shortcut(shortcut, app_hotkey_handler(app_path.clone()))
+            .map_err(|e| format!("failed to register hotkey [{}]: {}", hotkey, e))?;
+    }
 
-    tauri_app_handle
-        .global_shortcut()
-        .on_shortcut(hotkey.as_str(), app_hotkey_handler(app_path))
-        .map_err(|e| e.to_string())?;
+    if let Some(previous_hotkey) = previous_hotkey {
+        if previous_hotkey.parse::<Shortcut>().ok() != Some(shortcut) {
+            if let Err(e) = tauri_app_handle
+                .global_shortcut()
+                .unregister(previous_hotkey.as_str())
+            {
+                warn!(
+                    "failed to unregister hotkey [{}] of [{}]: {}",
+                    previous_hotkey, app_path, e
+                );
+            }
+        }
+    }
+    app_hotkey_store.set(app_path, hotkey.as_str());
 
     Ok(())
 }
@@ -789,7 +841,7 @@ pub async fn unregister_app_hotkey<R: Runtime>(
 ) -> Result<(), String> {
     let app_hotkey_store = tauri_app_handle
         .store(TAURI_STORE_APP_HOTKEY)
-        .unwrap_or_else(|_| panic!("store [{}] not found/loaded", TAURI_STORE_APP_HOTKEY));
+        .map_err(|e| e.to_string())?;
 
     let Some(hotkey) = app_hotkey_store.get(app_path.as_str()) else {
         let error_msg = format!(
@@ -800,20 +852,24 @@ pub async fn unregister_app_hotkey<R: Runtime>(
         return Err(error_msg);
     };
 
-    let hotkey = match hotkey {
-        Json::String(str) => str,
-        _ => unreachable!("hotkey should be stored in a string"),
-    };
-
     let deleted = app_hotkey_store.delete(app_path.as_str());
     if !deleted {
         return Err("failed to delete application hotkey from store".into());
     }
 
-    tauri_app_handle
+    // A hotkey that is not a string could not have been registered
+    let Json::String(hotkey) = hotkey else {
+        return Ok(());
+    };
+    if tauri_app_handle
         .global_shortcut()
-        .unregister(hotkey.as_str())
-        .map_err(|e| e.to_string())?;
+        .is_registered(hotkey.as_str())
+    {
+        tauri_app_handle
+            .global_shortcut()
+            .unregister(hotkey.as_str())
+            .map_err(|e| format!("failed to unregister hotkey [{}]: {}", hotkey, e))?;
+    }
 
     Ok(())
 }
@@ -53,6 +53,14 @@ pub async fn register_app_hotkey<R: Runtime>(
     unreachable!("app list should be empty, there is no way this can be invoked")
 }
 
+/// There are no application hotkeys without the search engine.
+pub(crate) fn reload_app_hotkeys<R: Runtime>(
+    _tauri_app_handle: &AppHandle<R>,
+    _previous_hotkeys: &[String],
+) -> Result<(), String> {
+    Ok(())
+}
+
 #[tauri::command]
 pub async fn unregister_app_hotkey<R: Runtime>(
     _tauri_app_handle: AppHandle<R>,
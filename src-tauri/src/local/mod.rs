@@ -8,6 +8,7 @@ use std::collections::HashMap;
 use std::sync::OnceLock;
 
 use crate::common::register::SearchSourceRegistry;
+use crate::policy::policy;
 use serde_json::Value as Json;
 use tauri::{AppHandle, Manager, Runtime};
 use tauri_plugin_store::StoreExt;
@@ -70,11 +71,20 @@ pub(crate) async fn init_local_search_source<R: Runtime>(
     application::ApplicationSearchSource::init(app_handle.clone()).await?;
 
     for (id, enabled) in enabled_status_store.entries() {
-        let enabled = match enabled {
-            Json::Bool(b) => b,
-            _ => unreachable!("enabled state should be stored as a boolean"),
+        let Json::Bool(enabled) = enabled else {
+            log::warn!(
+                "ignoring the invalid enabled state of [{}]: {}",
+                id,
+                enabled
+            );
+            continue;
         };
 
+        if enabled && policy().is_local_source_disabled(&id) {
+            log::info!("local query source [{}] is disabled by the policy", id);
+            continue;
+        }
+
         if enabled {
             if id == application::QUERYSOURCE_ID_DATASOURCE_ID_DATASOURCE_NAME {
                 registry
@@ -105,9 +115,13 @@ pub async fn get_disabled_local_query_sources<R: Runtime>(app_handle: AppHandle<
     let mut disabled_local_query_sources = Vec::new();
 
     for (id, enabled) in enabled_status_store.entries() {
-        let enabled = match enabled {
-            Json::Bool(b) => b,
-            _ => unreachable!("enabled state should be stored as a boolean"),
+        let Json::Bool(enabled) = enabled else {
+            log::warn!(
+                "ignoring the invalid enabled state of [{}]: {}",
+                id,
+                enabled
+            );
+            continue;
         };
 
         if !enabled {
@@ -115,6 +129,12 @@ pub async fn get_disabled_local_query_sources<R: Runtime>(app_handle: AppHandle<
         }
     }
 
+    for id in &policy().disabled_local_sources {
+        if !disabled_local_query_sources.contains(id) {
+            disabled_local_query_sources.push(id.clone());
+        }
+    }
+
     disabled_local_query_sources
 }
 
@@ -122,7 +142,14 @@ pub async fn get_disabled_local_query_sources<R: Runtime>(app_handle: AppHandle<
 pub async fn enable_local_query_source<R: Runtime>(
     app_handle: AppHandle<R>,
     query_source_id: String,
-) {
+) -> Result<(), String> {
+    if policy().is_local_source_disabled(&query_source_id) {
+        return Err(format!(
+            "[{}] is disabled by your administrator",
+            query_source_id
+        ));
+    }
+
     let registry = app_handle.state::<SearchSourceRegistry>();
     if query_source_id == application::QUERYSOURCE_ID_DATASOURCE_ID_DATASOURCE_NAME {
         let application_search = application::ApplicationSearchSource;
@@ -142,6 +169,8 @@ pub async fn enable_local_query_source<R: Runtime>(
           )
         });
     enabled_status_store.set(query_source_id, Json::Bool(true));
+
+    Ok(())
 }
 
 #[tauri::command]
@@ -0,0 +1,603 @@
+//! Local API, to script Coco from other programs.
+//!
+//! It is opt-in, and served over HTTP either on a Unix domain socket in the app
+//! data directory or on a loopback port. Requests are authenticated with a
+//! per-user token, sent as `Authorization: Bearer <token>`. The endpoint and
+//! the token are written to `local_api.json` in the app data directory, only
+//! readable by the user.
+//!
+//! ```text
+//! GET  /v1/servers                      The Coco servers, without their settings
+//! POST /v1/search                       {"query", "from", "size", "querysource"}
+//! GET  /v1/local_sources                The disabled local sources
+//! POST /v1/local_sources/<id>/enable
+//! POST /v1/local_sources/<id>/disable
+//! POST /v1/open                         {"path"}
+//! POST /v1/chat                         {"assistant_id", "message", "server_id"}
+//! ```
+//!
+//! Responses are JSON, errors are `{"error": <message>}`. The reply of an
+//! assistant is streamed as JSON lines: `{"type": "chunk", "line": <line>}`,
+//! then `{"type": "done"}` or `{"type": "error", "message": <message>}`.
+
+use crate::common::health::Health;
+use crate::common::server::Server;
+use crate::server::servers::get_all_servers;
+use crate::settings;
+use hyper::body::HttpBody;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::accept::Accept;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as Json};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::oneshot;
+
+/// File with the endpoint and the token of the local API.
+const LOCAL_API_ENDPOINT_FILE: &str = "local_api.json";
+const LOCAL_API_SOCKET_FILE: &str = "coco.sock";
+/// Entry of the token in the secret store.
+const LOCAL_API_TOKEN_KEY: &str = "local-api-token";
+const LOCAL_API_TOKEN_LEN: usize = 32;
+const DEFAULT_LOCAL_API_PORT: u16 = 2901;
+const DEFAULT_SEARCH_SIZE: u64 = 10;
+/// Time limit of the searches, in milliseconds.
+const LOCAL_API_QUERY_TIMEOUT: u64 = 5000;
+/// Largest accepted request body.
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// The running server.
+struct RunningServer {
+    shutdown: oneshot::Sender<()>,
+    /// Ends once the server has shut down.
+    task: JoinHandle<()>,
+}
+
+static RUNNING_SERVER: Mutex<Option<RunningServer>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalApiTransport {
+    UnixSocket,
+    Http,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalApiSettings {
+    pub enabled: bool,
+    pub transport: LocalApiTransport,
+    /// Loopback port of the `http` transport.
+    pub port: u16,
+}
+
+impl Default for LocalApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            transport: if cfg!(unix) {
+                LocalApiTransport::UnixSocket
+            } else {
+                LocalApiTransport::Http
+            },
+            port: DEFAULT_LOCAL_API_PORT,
+        }
+    }
+}
+
+impl LocalApiSettings {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.transport == LocalApiTransport::UnixSocket && !cfg!(unix) {
+            return Err("Unix domain sockets are not supported on this platform".into());
+        }
+        if self.transport == LocalApiTransport::Http && self.port == 0 {
+            return Err("the port of the local API should not be 0".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Where and how to reach the local API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalApiEndpoint {
+    pub transport: LocalApiTransport,
+    /// Path of the socket of the `unix_socket` transport.
+    pub socket: Option<PathBuf>,
+    /// URL of the `http` transport.
+    pub url: Option<String>,
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    query: String,
+    #[serde(default)]
+    from: u64,
+    size: Option<u64>,
+    /// Only search this source.
+    querysource: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenRequest {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    assistant_id: String,
+    message: String,
+    /// Can be omitted if there is a single enabled server.
+    server_id: Option<String>,
+}
+
+/// What the local API tells about a server, nothing of its settings.
+#[derive(Serialize)]
+struct ServerSummary {
+    id: String,
+    name: String,
+    endpoint: String,
+    enabled: bool,
+    available: bool,
+    health: Option<Health>,
+}
+
+impl From<Server> for ServerSummary {
+    fn from(server: Server) -> Self {
+        Self {
+            id: server.id,
+            name: server.name,
+            endpoint: server.endpoint,
+            enabled: server.enabled,
+            available: server.available,
+            health: server.health,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChatEvent {
+    Chunk { line: String },
+    Done,
+    Error { message: String },
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+type ApiResult = Result<Response<Body>, ApiError>;
+
+/// Start the local API if it is enabled and follow its settings, called upon
+/// app start.
+pub(crate) fn enable_local_api<R: Runtime>(app_handle: &AppHandle<R>) {
+    apply(app_handle, &settings::settings().local_api);
+
+    settings::subscribe(|previous, current| {
+        if previous.local_api == current.local_api {
+            return;
+        }
+        if let Some(app_handle) = crate::GLOBAL_TAURI_APP_HANDLE.get() {
+            apply(app_handle, &current.local_api);
+        }
+    });
+}
+
+/// Stop the running server, then start it again if `local_api` is enabled.
+fn apply<R: Runtime>(app_handle: &AppHandle<R>, local_api: &LocalApiSettings) {
+    let mut running_server = RUNNING_SERVER.lock().unwrap();
+    let previous = stop(running_server.take());
+    if !local_api.enabled {
+        return;
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let app_handle = app_handle.clone();
+    let local_api = local_api.clone();
+    let task = tauri::async_runtime::spawn(async move {
+        // Until then, the previous server holds the port or the socket
+        if let Some(previous) = previous {
+            let _ = previous.await;
+        }
+        if let Err(e) = start(app_handle, local_api, rx).await {
+            log::error!("failed to start the local API: {}", e);
+            remove_endpoint_file();
+        }
+    });
+    *running_server = Some(RunningServer { shutdown: tx, task });
+}
+
+/// Stop `running_server`, if any, return its task.
+fn stop(running_server: Option<RunningServer>) -> Option<JoinHandle<()>> {
+    remove_endpoint_file();
+
+    running_server.map(|running_server| {
+        let _ = running_server.shutdown.send(());
+        running_server.task
+    })
+}
+
+fn data_dir() -> Option<PathBuf> {
+    crate::GLOBAL_TAURI_APP_HANDLE
+        .get()
+        .and_then(|app_handle| app_handle.path().app_local_data_dir().ok())
+}
+
+/// Remove the endpoint file, called upon exit.
+pub(crate) fn remove_endpoint_file() {
+    if let Some(dir) = data_dir() {
+        let _ = std::fs::remove_file(dir.join(LOCAL_API_ENDPOINT_FILE));
+    }
+}
+
+/// The token of the local API, generated once and kept in the secret store.
+fn token() -> String {
+    let store = match crate::server::secret_store::secret_store() {
+        Ok(store) => store,
+        Err(e) => {
+            log::warn!(
+                "{}, the token of the local API is only valid for this session",
+                e
+            );
+            return crate::server::auth::random_string(LOCAL_API_TOKEN_LEN);
+        }
+    };
+
+    match store.get(LOCAL_API_TOKEN_KEY) {
+        Ok(Some(token)) => return token,
+        Ok(None) => {}
+        Err(e) => log::warn!("failed to read the token of the local API: {}", e),
+    }
+    let token = crate::server::auth::random_string(LOCAL_API_TOKEN_LEN);
+    if let Err(e) = store.set(LOCAL_API_TOKEN_KEY, &token) {
+        log::warn!("failed to save the token of the local API: {}", e);
+    }
+    token
+}
+
+async fn start<R: Runtime>(
+    app_handle: AppHandle<R>,
+    local_api: LocalApiSettings,
+    shutdown: oneshot::Receiver<()>,
+) -> Result<(), String> {
+    let dir = app_handle
+        .path()
+        .app_local_data_dir()
+        .map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let token = token();
+
+    match local_api.transport {
+        LocalApiTransport::Http => {
+            let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, local_api.port));
+            let incoming = hyper::server::conn::AddrIncoming::bind(&addr)
+                .map_err(|e| format!("failed to listen on [{}]: {}", addr, e))?;
+            let endpoint = LocalApiEndpoint {
+                transport: local_api.transport,
+                socket: None,
+                url: Some(format!("http://{}", addr)),
+                token: token.clone(),
+            };
+
+            write_endpoint_file(&dir, &endpoint)?;
+            log::info!("local API listening on [{}]", addr);
+            serve(app_handle, incoming, token, shutdown).await
+        }
+        #[cfg(unix)]
+        LocalApiTransport::UnixSocket => {
+            use std::os::unix::fs::PermissionsExt;
+
+            let path = dir.join(LOCAL_API_SOCKET_FILE);
+            // Left over by an instance which did not exit cleanly
+            let _ = std::fs::remove_file(&path);
+            let listener = tokio::net::UnixListener::bind(&path)
+                .map_err(|e| format!("failed to listen on [{}]: {}", path.display(), e))?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+                .map_err(|e| e.to_string())?;
+            let endpoint = LocalApiEndpoint {
+                transport: local_api.transport,
+                socket: Some(path.clone()),
+                url: None,
+                token: token.clone(),
+            };
+
+            write_endpoint_file(&dir, &endpoint)?;
+            log::info!("local API listening on [{}]", path.display());
+            let incoming = hyper::server::accept::from_stream(futures::stream::unfold(
+                listener,
+                |listener| async move {
+                    let stream = listener.accept().await.map(|(stream, _)| stream);
+                    Some((stream, listener))
+                },
+            ));
+            let result = serve(app_handle, incoming, token, shutdown).await;
+            let _ = std::fs::remove_file(&path);
+            result
+        }
+        #[cfg(not(unix))]
+        LocalApiTransport::UnixSocket => {
+            Err("Unix domain sockets are not supported on this platform".into())
+        }
+    }
+}
+
+fn write_endpoint_file(dir: &std::path::Path, endpoint: &LocalApiEndpoint) -> Result<(), String> {
+    crate::server::secret_store::write_private_file(
+        &dir.join(LOCAL_API_ENDPOINT_FILE),
+        serde_json::to_string(endpoint)
+            .expect("Failed to serialize local API endpoint")
+            .as_bytes(),
+    )
+}
+
+async fn serve<R, I>(
+    app_handle: AppHandle<R>,
+    incoming: I,
+    token: String,
+    shutdown: oneshot::Receiver<()>,
+) -> Result<(), String>
+where
+    R: Runtime,
+    I: Accept,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let token = Arc::new(token);
+    let make_service = make_service_fn(move |_| {
+        let app_handle = app_handle.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let app_handle = app_handle.clone();
+                let token = token.clone();
+                async move {
+                    let response = handle_request(app_handle, request, &token)
+                        .await
+                        .unwrap_or_else(|e| {
+                            json_response(e.status, &json!({ "error": e.message }))
+                        });
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+
+    hyper::Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(async {
+            let _ = shutdown.await;
+        })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn json_response(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_vec(value).expect("Failed to serialize local API response"),
+        ))
+        .expect("Failed to build local API response")
+}
+
+fn ok(value: impl Serialize) -> ApiResult {
+    Ok(json_response(StatusCode::OK, &value))
+}
+
+async fn read_json<T: serde::de::DeserializeOwned>(request: Request<Body>) -> Result<T, ApiError> {
+    let too_large = || ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "the request is too large");
+
+    let mut body = request.into_body();
+    if body.size_hint().lower() > MAX_REQUEST_SIZE as u64 {
+        return Err(too_large());
+    }
+    // Stop reading as soon as the limit is exceeded
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| ApiError::bad_request(e.to_string()))?;
+        if bytes.len() + chunk.len() > MAX_REQUEST_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&bytes)
+        .map_err(|e| ApiError::bad_request(format!("invalid request: {}", e)))
+}
+
+fn is_authorized(request: &Request<Body>, token: &str) -> bool {
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // In constant time, not to leak the token
+        .is_some_and(|value| value.as_bytes().ct_eq(token.as_bytes()).into())
+}
+
+async fn handle_request<R: Runtime>(
+    app_handle: AppHandle<R>,
+    request: Request<Body>,
+    token: &str,
+) -> ApiResult {
+    if !is_authorized(&request, token) {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "invalid token"));
+    }
+    if request
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+        .is_some_and(|len| len > MAX_REQUEST_SIZE)
+    {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "the request is too large",
+        ));
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    log::debug!("local API request {} {}", method, path);
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (&method, segments.as_slice()) {
+        (&Method::GET, ["v1", "servers"]) => ok(get_all_servers()
+            .into_iter()
+            .map(ServerSummary::from)
+            .collect::<Vec<_>>()),
+        (&Method::POST, ["v1", "search"]) => search(app_handle, read_json(request).await?).await,
+        (&Method::GET, ["v1", "local_sources"]) => {
+            ok(crate::local::get_disabled_local_query_sources(app_handle).await)
+        }
+        (&Method::POST, ["v1", "local_sources", id, "enable"]) => {
+            crate::local::enable_local_query_source(app_handle, id.to_string())
+                .await
+                .map_err(|e| ApiError::new(StatusCode::FORBIDDEN, e))?;
+            ok(Json::Null)
+        }
+        (&Method::POST, ["v1", "local_sources", id, "disable"]) => {
+            crate::local::disable_local_query_source(app_handle, id.to_string()).await;
+            ok(Json::Null)
+        }
+        (&Method::POST, ["v1", "open"]) => {
+            let OpenRequest { path } = read_json(request).await?;
+            crate::util::open(app_handle, path)
+                .await
+                .map_err(ApiError::internal)?;
+            ok(Json::Null)
+        }
+        (&Method::POST, ["v1", "chat"]) => chat(read_json(request).await?),
+        _ => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("no such endpoint: {} {}", method, path),
+        )),
+    }
+}
+
+async fn search<R: Runtime>(app_handle: AppHandle<R>, request: SearchRequest) -> ApiResult {
+    let mut query_strings = HashMap::from([("query".to_string(), request.query)]);
+    if let Some(querysource) = request.querysource {
+        query_strings.insert("querysource".to_string(), querysource);
+    }
+
+    let response = crate::search::query_coco_fusion(
+        app_handle,
+        request.from,
+        request.size.unwrap_or(DEFAULT_SEARCH_SIZE),
+        query_strings,
+        LOCAL_API_QUERY_TIMEOUT,
+    )
+    .await
+    .map_err(|e| ApiError::internal(e.to_string()))?;
+    ok(response)
+}
+
+/// Stream the reply of the assistant, the response starts before the
+/// assistant replies so failures are reported in the stream.
+fn chat(request: ChatRequest) -> ApiResult {
+    let server_id = match request.server_id {
+        Some(id) => id,
+        None => {
+            // Only guess the server if there is no ambiguity
+            let mut enabled = get_all_servers().into_iter().filter(|s| s.enabled);
+            match (enabled.next(), enabled.next()) {
+                (Some(server), None) => server.id,
+                _ => return Err(ApiError::bad_request("specify the server with server_id")),
+            }
+        }
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<ChatEvent>();
+    tauri::async_runtime::spawn(async move {
+        let result = crate::assistant::ask_assistant(
+            &server_id,
+            &request.assistant_id,
+            &request.message,
+            |line| {
+                let _ = tx.send(ChatEvent::Chunk { line });
+            },
+        )
+        .await;
+        let _ = tx.send(match result {
+            Ok(()) => ChatEvent::Done,
+            Err(message) => ChatEvent::Error { message },
+        });
+    });
+
+    let lines = futures::stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        let mut line = serde_json::to_vec(&event).expect("Failed to serialize chat event");
+        line.push(b'\n');
+        Some((Ok::<_, std::io::Error>(line), rx))
+    });
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::wrap_stream(lines))
+        .expect("Failed to build local API response"))
+}
+
+#[test]
+fn test_local_api_settings() {
+    let mut local_api = LocalApiSettings::default();
+    assert!(!local_api.enabled);
+    assert!(local_api.validate().is_ok());
+
+    local_api.transport = LocalApiTransport::Http;
+    local_api.port = 0;
+    assert!(local_api.validate().is_err());
+
+    let local_api: LocalApiSettings =
+        serde_json::from_value(json!({ "enabled": true, "transport": "http" })).unwrap();
+    assert_eq!(local_api.port, DEFAULT_LOCAL_API_PORT);
+    assert_eq!(local_api.transport, LocalApiTransport::Http);
+}
+
+#[test]
+fn test_is_authorized() {
+    let request = |authorization: &str| {
+        Request::builder()
+            .header(AUTHORIZATION, authorization)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    assert!(is_authorized(&request("Bearer token"), "token"));
+    assert!(!is_authorized(&request("Bearer other"), "token"));
+    assert!(!is_authorized(&request("Bearer tok"), "token"));
+    assert!(!is_authorized(&request("token"), "token"));
+    assert!(!is_authorized(
+        &Request::builder().body(Body::empty()).unwrap(),
+        "token"
+    ));
+}
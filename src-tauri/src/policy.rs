@@ -0,0 +1,390 @@
+//! Policy files, written by administrators to provision Coco on a fleet of
+//! machines.
+//!
+//! Two files are read at startup, a system-wide one and a per-user one (see
+//! [`policy_file_paths()`]), the system-wide one takes precedence. Example:
+//!
+//! ```json
+//! {
+//!   "servers": [
+//!     {
+//!       "id": "corp",
+//!       "name": "Corp Search",
+//!       "endpoint": "https://coco.corp.example",
+//!       "priority": 10,
+//!       "tls": { "ca_certificate": "-----BEGIN CERTIFICATE-----..." }
+//!     }
+//!   ],
+//!   "builtin_server": "hidden",
+//!   "disabled_local_sources": ["calculator"]
+//! }
+//! ```
+//!
+//! Provisioned servers are locked unless `"locked": false` is set: they cannot
+//! be edited, disabled or removed from the settings.
+
+use crate::common::register::SearchSourceRegistry;
+use crate::common::server::{AuthProvider, Provider, Server, ServerTlsConfig, Sso, Version};
+use crate::server::servers::{
+    get_all_servers, get_server_by_id, persist_servers, refresh_coco_server_info,
+    remove_server_by_id, remove_server_tokens, save_server, BUILTIN_SERVER_ID,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tauri::{AppHandle, Manager, Runtime};
+
+const POLICY_FILE_NAME: &str = "policy.json";
+
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinServerPolicy {
+    #[default]
+    Visible,
+    /// It cannot be disabled or removed.
+    Locked,
+    /// It is removed.
+    Hidden,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyServer {
+    pub id: String,
+    pub endpoint: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub priority: Option<u32>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_true")]
+    pub locked: bool,
+    #[serde(default)]
+    pub tls: Option<ServerTlsConfig>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub servers: Vec<PolicyServer>,
+    /// `None` if not set, the built-in server is then visible.
+    pub builtin_server: Option<BuiltinServerPolicy>,
+    /// IDs of the local query sources, e.g., "calculator", that cannot be
+    /// enabled.
+    pub disabled_local_sources: Vec<String>,
+    /// Files this policy was read from.
+    #[serde(skip_deserializing)]
+    pub sources: Vec<PathBuf>,
+}
+
+impl Policy {
+    /// Merge `other`, which takes precedence, into `self`.
+    fn merge(&mut self, other: Policy) {
+        for server in other.servers {
+            self.servers.retain(|s| s.id != server.id);
+            self.servers.push(server);
+        }
+        // Set by `other`, it wins even if `self` is more restrictive
+        self.builtin_server = other.builtin_server.or(self.builtin_server);
+        for id in other.disabled_local_sources {
+            if !self.disabled_local_sources.contains(&id) {
+                self.disabled_local_sources.push(id);
+            }
+        }
+        self.sources.extend(other.sources);
+    }
+
+    pub fn server(&self, id: &str) -> Option<&PolicyServer> {
+        self.servers.iter().find(|server| server.id == id)
+    }
+
+    pub fn is_local_source_disabled(&self, id: &str) -> bool {
+        self.disabled_local_sources
+            .iter()
+            .any(|source| source == id)
+    }
+}
+
+/// The policy in effect, empty if there is no policy file.
+pub(crate) fn policy() -> &'static Policy {
+    static EMPTY_POLICY: OnceLock<Policy> = OnceLock::new();
+    POLICY
+        .get()
+        .unwrap_or_else(|| EMPTY_POLICY.get_or_init(Policy::default))
+}
+
+/// The system-wide policy file, then the per-user one.
+fn policy_file_paths<R: Runtime>(app_handle: &AppHandle<R>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    paths.push(PathBuf::from("/etc/coco").join(POLICY_FILE_NAME));
+    #[cfg(target_os = "macos")]
+    paths.push(PathBuf::from("/Library/Application Support/Coco").join(POLICY_FILE_NAME));
+    #[cfg(target_os = "windows")]
+    if let Some(program_data) = std::env::var_os("ProgramData") {
+        paths.push(
+            PathBuf::from(program_data)
+                .join("Coco")
+                .join(POLICY_FILE_NAME),
+        );
+    }
+
+    match app_handle.path().app_config_dir() {
+        Ok(dir) => paths.push(dir.join(POLICY_FILE_NAME)),
+        Err(e) => log::warn!("failed to get the app config directory: {}", e),
+    }
+
+    paths
+}
+
+fn read_policy_file(path: &Path) -> Result<Policy, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read [{}]: {}", path.display(), e))?;
+    let mut policy: Policy = serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid policy file [{}]: {}", path.display(), e))?;
+
+    for server in &policy.servers {
+        if server.id.trim().is_empty() {
+            return Err(format!(
+                "Invalid policy file [{}]: a server has no ID",
+                path.display()
+            ));
+        }
+        let endpoint = url::Url::parse(&server.endpoint).map_err(|e| {
+            format!(
+                "Invalid policy file [{}]: bad endpoint of server [{}]: {}",
+                path.display(),
+                server.id,
+                e
+            )
+        })?;
+        if !matches!(endpoint.scheme(), "http" | "https") {
+            return Err(format!(
+                "Invalid policy file [{}]: server [{}] is not an HTTP(S) endpoint",
+                path.display(),
+                server.id
+            ));
+        }
+        if let Some(tls) = &server.tls {
+            crate::server::tls::validate_tls_config(tls)?;
+        }
+    }
+    policy.sources.push(path.to_path_buf());
+
+    Ok(policy)
+}
+
+/// Read the policy files, called once upon app start before the servers are
+/// loaded. An invalid file is ignored entirely.
+pub(crate) fn load_policy<R: Runtime>(app_handle: &AppHandle<R>) {
+    let mut paths = policy_file_paths(app_handle);
+    // The per-user file is merged first so that the system-wide one wins
+    paths.reverse();
+
+    let mut policy = Policy::default();
+    for path in paths {
+        if !path.exists() {
+            continue;
+        }
+
+        match read_policy_file(&path) {
+            Ok(file_policy) => {
+                log::info!("applying the policy file [{}]", path.display());
+                policy.merge(file_policy);
+            }
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    let _ = POLICY.set(policy);
+}
+
+fn provisioned_server(entry: &PolicyServer) -> Server {
+    Server {
+        id: entry.id.clone(),
+        builtin: false,
+        enabled: entry.enabled,
+        name: entry.name.clone().unwrap_or_else(|| entry.id.clone()),
+        endpoint: entry.endpoint.trim_end_matches('/').to_string(),
+        provider: Provider {
+            name: "".to_string(),
+            icon: "".to_string(),
+            website: "".to_string(),
+            eula: "".to_string(),
+            privacy_policy: "".to_string(),
+            banner: "".to_string(),
+            description: "".to_string(),
+        },
+        version: Version {
+            number: "".to_string(),
+        },
+        minimal_client_version: None,
+        updated: "".to_string(),
+        public: false,
+        // Until its info is fetched
+        available: false,
+        health: None,
+        profile: None,
+        auth_provider: AuthProvider {
+            sso: Sso {
+                url: "".to_string(),
+            },
+        },
+        priority: entry.priority.unwrap_or_default(),
+        stats: None,
+        tls: entry.tls.clone(),
+        proxy: None,
+        timeouts: None,
+        needs_relogin: false,
+        incompatible_reason: None,
+        accounts: Vec::new(),
+        active_account: None,
+        locked: entry.locked,
+    }
+}
+
+/// Override the settings of `server` managed by the policy, e.g., after its
+/// info is fetched from the server.
+pub(crate) fn apply_server_policy(server: &mut Server) {
+    let policy = policy();
+
+    if server.id == BUILTIN_SERVER_ID {
+        server.locked = policy.builtin_server == Some(BuiltinServerPolicy::Locked);
+        if server.locked {
+            server.enabled = true;
+        }
+        return;
+    }
+
+    let Some(entry) = policy.server(&server.id) else {
+        // It may have been provisioned by a previous policy
+        server.locked = false;
+        return;
+    };
+
+    if let Some(name) = &entry.name {
+        server.name = name.clone();
+    }
+    if let Some(priority) = entry.priority {
+        server.priority = priority;
+    }
+    if entry.tls.is_some() {
+        server.tls = entry.tls.clone();
+    }
+    server.endpoint = entry.endpoint.trim_end_matches('/').to_string();
+    server.locked = entry.locked;
+    if entry.locked {
+        server.enabled = entry.enabled;
+    }
+}
+
+/// Provision the servers of the policy and hide or lock the built-in one,
+/// called upon app start once the servers are loaded.
+pub(crate) async fn apply_policy<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
+    let policy = policy();
+
+    if policy.builtin_server == Some(BuiltinServerPolicy::Hidden)
+        && get_server_by_id(BUILTIN_SERVER_ID).is_some()
+    {
+        log::info!("hiding the built-in server as required by the policy");
+        let registry = app_handle.state::<SearchSourceRegistry>();
+        registry.remove_source(BUILTIN_SERVER_ID).await;
+        remove_server_tokens(BUILTIN_SERVER_ID);
+        remove_server_by_id(BUILTIN_SERVER_ID.to_string());
+    }
+
+    let mut new_servers = Vec::new();
+    for entry in &policy.servers {
+        if get_server_by_id(&entry.id).is_none() {
+            log::info!("provisioning server [{}] from the policy", entry.id);
+            save_server(&provisioned_server(entry));
+            new_servers.push(entry.id.clone());
+        }
+    }
+
+    // Also unlocks the servers that are no longer in the policy
+    for mut server in get_all_servers() {
+        apply_server_policy(&mut server);
+        save_server(&server);
+    }
+
+    persist_servers(app_handle).await?;
+
+    // Fetch the info of the new servers in the background, they are
+    // registered as search sources once it is available
+    for id in new_servers {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = refresh_coco_server_info(app_handle, id.clone()).await {
+                log::warn!("failed to fetch the info of server [{}]: {}", id, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Return an error if the server specified by `id` is locked by the policy.
+pub(crate) fn ensure_server_editable(id: &str) -> Result<(), String> {
+    match get_server_by_id(id) {
+        Some(server) if server.locked => Err(format!(
+            "Server [{}] is managed by your administrator and cannot be changed",
+            server.name
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Return the policy in effect, so that the settings can show what is
+/// managed by the administrator.
+#[tauri::command]
+pub async fn get_policy() -> Policy {
+    policy().clone()
+}
+
+#[test]
+fn test_merge_policies() {
+    let mut user: Policy = serde_json::from_str(
+        r#"{
+            "servers": [{ "id": "a", "endpoint": "https://user.example", "locked": false }],
+            "builtin_server": "locked",
+            "disabled_local_sources": ["calculator"]
+        }"#,
+    )
+    .unwrap();
+    let system: Policy = serde_json::from_str(
+        r#"{
+            "servers": [{ "id": "a", "endpoint": "https://system.example" }],
+            "disabled_local_sources": ["calculator", "applications"]
+        }"#,
+    )
+    .unwrap();
+
+    user.merge(system);
+
+    assert_eq!(user.servers.len(), 1);
+    assert_eq!(user.servers[0].endpoint, "https://system.example");
+    assert!(user.servers[0].locked && user.servers[0].enabled);
+    assert_eq!(user.builtin_server, Some(BuiltinServerPolicy::Locked));
+    assert_eq!(
+        user.disabled_local_sources,
+        vec!["calculator".to_string(), "applications".to_string()]
+    );
+}
+
+#[test]
+fn test_merge_builtin_server_policies() {
+    let mut user: Policy = serde_json::from_str(r#"{ "builtin_server": "hidden" }"#).unwrap();
+    let system: Policy = serde_json::from_str(r#"{ "builtin_server": "locked" }"#).unwrap();
+
+    // The user cannot hide the server the administrator locked on
+    user.merge(system);
+    assert_eq!(user.builtin_server, Some(BuiltinServerPolicy::Locked));
+}
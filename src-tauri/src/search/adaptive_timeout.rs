@@ -0,0 +1,331 @@
+//! Per-source query timeouts that adapt to the observed latency.
+//!
+//! Local sources answer in milliseconds while a remote Coco server may need a
+//! second or more, so a single timeout for every source is either too short
+//! for the slow ones or too long for the fast ones. We record the latency of
+//! every source and derive its timeout from a high percentile of it, capped by
+//! the `query_timeout` of the query (the global ceiling). Users can override
+//! the timeout of a source.
+//!
+//! A timeout counts as a sample at the time it took, and grows the timeout of
+//! the source right away, so that a source getting slower is not cut off every
+//! time until enough slow samples have been recorded. Failures are not
+//! samples, a source failing fast does not answer quickly.
+//!
+//! A source that keeps timing out is backed off: it won't be queried until its
+//! backoff expires, the backoff grows exponentially with every timeout.
+
+use crate::COCO_TAURI_STORE;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+use tauri_plugin_store::StoreExt;
+
+/// Key of the user timeout overrides in the Tauri store, stored as an object
+/// of `{ <source ID>: <timeout in milliseconds> }`.
+pub(crate) const SEARCH_SOURCE_TIMEOUT_OVERRIDES: &str = "search_source_timeout_overrides";
+
+/// Number of latency samples we keep for every source.
+const MAX_LATENCY_SAMPLES: usize = 64;
+/// Use the ceiling until we have seen this many samples.
+const MIN_LATENCY_SAMPLES: usize = 5;
+const LATENCY_PERCENTILE: f64 = 0.95;
+/// The timeout is the latency percentile multiplied by this, so that the
+/// occasional slower response is not cut off.
+const LATENCY_HEADROOM: f64 = 1.5;
+const MIN_TIMEOUT: Duration = Duration::from_millis(100);
+/// The timeout of a source is multiplied by this every time it times out.
+const TIMEOUT_GROWTH: f64 = 1.5;
+
+/// A source gets backed off after timing out this many times in a row.
+const TIMEOUTS_BEFORE_BACKOFF: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+
+/// Event emitted when a search source gets backed off.
+const EVENT_SEARCH_SOURCE_BACKED_OFF: &str = "search-source-backed-off";
+
+#[derive(Default)]
+struct SourceLatency {
+    /// Latency of the latest successful or timed out queries, in milliseconds.
+    samples: VecDeque<u64>,
+    /// Grown timeout after the latest timeouts, cleared upon success.
+    grown_timeout: Option<Duration>,
+    consecutive_timeouts: u32,
+    total_timeouts: u64,
+    backoff_until: Option<Instant>,
+}
+
+lazy_static! {
+    static ref SOURCE_LATENCY: RwLock<HashMap<String, SourceLatency>> = RwLock::new(HashMap::new());
+    static ref TIMEOUT_OVERRIDES: RwLock<HashMap<String, u64>> = RwLock::new(HashMap::new());
+}
+
+/// Return the `percentile` (in range `[0, 1]`) of `samples` using the
+/// nearest-rank method, `None` if `samples` is empty.
+fn percentile(samples: &VecDeque<u64>, percentile: f64) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let mut sorted: Vec<u64> = samples.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = (percentile * sorted.len() as f64).ceil() as usize;
+    let index = rank.clamp(1, sorted.len()) - 1;
+
+    Some(sorted[index])
+}
+
+/// Load the user timeout overrides from the Tauri store, called upon app start
+/// and when the settings are imported.
+pub(crate) fn load_timeout_overrides<R: Runtime>(app_handle: &AppHandle<R>) {
+    let store = app_handle
+        .store(COCO_TAURI_STORE)
+        .expect("create or load a store should not fail");
+
+    let mut guard = TIMEOUT_OVERRIDES.write().unwrap();
+    guard.clear();
+
+    let Some(JsonValue::Object(overrides)) = store.get(SEARCH_SOURCE_TIMEOUT_OVERRIDES) else {
+        return;
+    };
+
+    for (source_id, timeout) in overrides {
+        match timeout.as_u64() {
+            Some(timeout) => {
+                guard.insert(source_id, timeout);
+            }
+            None => log::warn!(
+                "ignoring invalid timeout override [{}] of search source [{}]",
+                timeout,
+                source_id
+            ),
+        }
+    }
+}
+
+/// Timeout to use when querying the source specified by `source_id`, it never
+/// exceeds `ceiling`.
+pub(crate) fn timeout_for(source_id: &str, ceiling: Duration) -> Duration {
+    if let Some(timeout) = TIMEOUT_OVERRIDES.read().unwrap().get(source_id) {
+        return Duration::from_millis(*timeout).min(ceiling);
+    }
+
+    let latency = SOURCE_LATENCY.read().unwrap();
+    let Some(source) = latency.get(source_id) else {
+        return ceiling;
+    };
+    if source.samples.len() < MIN_LATENCY_SAMPLES {
+        return ceiling;
+    }
+
+    let p = percentile(&source.samples, LATENCY_PERCENTILE).expect("samples are not empty");
+    let adaptive = Duration::from_millis((p as f64 * LATENCY_HEADROOM).ceil() as u64);
+
+    adaptive
+        .max(source.grown_timeout.unwrap_or_default())
+        .max(MIN_TIMEOUT)
+        .min(ceiling)
+}
+
+/// If the source specified by `source_id` is backed off, return for how long.
+pub(crate) fn backoff_remaining(source_id: &str) -> Option<Duration> {
+    let latency = SOURCE_LATENCY.read().unwrap();
+    let backoff_until = latency.get(source_id)?.backoff_until?;
+
+    backoff_until.checked_duration_since(Instant::now())
+}
+
+impl SourceLatency {
+    fn push_sample(&mut self, elapsed: Duration) {
+        if self.samples.len() == MAX_LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(elapsed.as_millis() as u64);
+    }
+}
+
+pub(crate) fn record_success(source_id: &str, elapsed: Duration) {
+    let mut latency = SOURCE_LATENCY.write().unwrap();
+    let source = latency.entry(source_id.to_string()).or_default();
+
+    source.push_sample(elapsed);
+    source.grown_timeout = None;
+    source.consecutive_timeouts = 0;
+    source.backoff_until = None;
+}
+
+/// Record that the source specified by `source_id` timed out after `elapsed`
+/// while queried with `timeout`, and return its backoff if it keeps timing out.
+fn timeout_backoff(source_id: &str, elapsed: Duration, timeout: Duration) -> Option<Duration> {
+    let mut latency = SOURCE_LATENCY.write().unwrap();
+    let source = latency.entry(source_id.to_string()).or_default();
+
+    // It would have taken at least that long
+    source.push_sample(elapsed);
+    // Capped by the ceiling in `timeout_for()`
+    source.grown_timeout = Some(Duration::from_millis(
+        (timeout.as_millis() as f64 * TIMEOUT_GROWTH).ceil() as u64,
+    ));
+    source.consecutive_timeouts += 1;
+    source.total_timeouts += 1;
+
+    if source.consecutive_timeouts < TIMEOUTS_BEFORE_BACKOFF {
+        return None;
+    }
+
+    let exponent = (source.consecutive_timeouts - TIMEOUTS_BEFORE_BACKOFF).min(16);
+    let backoff = INITIAL_BACKOFF
+        .saturating_mul(2_u32.pow(exponent))
+        .min(MAX_BACKOFF);
+    source.backoff_until = Some(Instant::now() + backoff);
+
+    Some(backoff)
+}
+
+/// Record that the source specified by `source_id` timed out after `elapsed`
+/// while queried with `timeout`, back it off and notify the frontend if it
+/// keeps timing out.
+pub(crate) fn record_timeout<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    source_id: &str,
+    elapsed: Duration,
+    timeout: Duration,
+) {
+    let Some(backoff) = timeout_backoff(source_id, elapsed, timeout) else {
+        return;
+    };
+
+    log::warn!(
+        "search source [{}] keeps timing out, backing it off for {:?}",
+        source_id,
+        backoff
+    );
+
+    let _ = app_handle.emit(
+        EVENT_SEARCH_SOURCE_BACKED_OFF,
+        serde_json::json!({
+            "id": source_id,
+            "backoff_ms": backoff.as_millis() as u64,
+        }),
+    );
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchSourceLatencyStats {
+    pub id: String,
+    pub samples: usize,
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub timeout_override_ms: Option<u64>,
+    pub consecutive_timeouts: u32,
+    pub total_timeouts: u64,
+    pub backoff_remaining_ms: Option<u64>,
+}
+
+#[tauri::command]
+pub async fn get_search_source_latency_stats() -> Vec<SearchSourceLatencyStats> {
+    let latency = SOURCE_LATENCY.read().unwrap();
+    let overrides = TIMEOUT_OVERRIDES.read().unwrap();
+    let now = Instant::now();
+
+    latency
+        .iter()
+        .map(|(id, source)| SearchSourceLatencyStats {
+            id: id.clone(),
+            samples: source.samples.len(),
+            p50_ms: percentile(&source.samples, 0.5),
+            p95_ms: percentile(&source.samples, LATENCY_PERCENTILE),
+            timeout_override_ms: overrides.get(id).copied(),
+            consecutive_timeouts: source.consecutive_timeouts,
+            total_timeouts: source.total_timeouts,
+            backoff_remaining_ms: source
+                .backoff_until
+                .and_then(|until| until.checked_duration_since(now))
+                .map(|remaining| remaining.as_millis() as u64),
+        })
+        .collect()
+}
+
+/// Override the timeout of the search source specified by `id`, pass `None`
+/// to go back to the adaptive timeout.
+#[tauri::command]
+pub async fn set_search_source_timeout<R: Runtime>(
+    app_handle: AppHandle<R>,
+    id: String,
+    timeout_ms: Option<u64>,
+) -> Result<(), String> {
+    if timeout_ms == Some(0) {
+        return Err("timeout should be greater than 0".into());
+    }
+
+    let overrides = {
+        let mut overrides = TIMEOUT_OVERRIDES.write().unwrap();
+        match timeout_ms {
+            Some(timeout) => overrides.insert(id.clone(), timeout),
+            None => overrides.remove(&id),
+        };
+
+        overrides
+            .iter()
+            .map(|(id, timeout)| (id.clone(), JsonValue::from(*timeout)))
+            .collect::<serde_json::Map<String, JsonValue>>()
+    };
+
+    // Let the user retry a backed off source right away
+    if let Some(source) = SOURCE_LATENCY.write().unwrap().get_mut(&id) {
+        source.consecutive_timeouts = 0;
+        source.backoff_until = None;
+    }
+
+    app_handle
+        .store(COCO_TAURI_STORE)
+        .map_err(|e| e.to_string())?
+        .set(
+            SEARCH_SOURCE_TIMEOUT_OVERRIDES,
+            JsonValue::Object(overrides),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn test_percentile() {
+    let samples: VecDeque<u64> = (1..=100).collect();
+
+    assert_eq!(percentile(&samples, 0.5), Some(50));
+    assert_eq!(percentile(&samples, 0.95), Some(95));
+    assert_eq!(percentile(&samples, 1.0), Some(100));
+    assert_eq!(percentile(&samples, 0.0), Some(1));
+    assert_eq!(percentile(&VecDeque::new(), 0.95), None);
+}
+
+#[test]
+fn test_timeout_grows_after_timeouts() {
+    let source_id = "test_timeout_grows_after_timeouts";
+    let ceiling = Duration::from_secs(60);
+    for _ in 0..MAX_LATENCY_SAMPLES {
+        record_success(source_id, Duration::from_millis(200));
+    }
+    let timeout = timeout_for(source_id, ceiling);
+    assert_eq!(timeout, Duration::from_millis(300));
+
+    // A single timeout does not move the percentile of a full window
+    assert_eq!(timeout_backoff(source_id, timeout, timeout), None);
+    let grown = timeout_for(source_id, ceiling);
+    assert_eq!(grown, Duration::from_millis(450));
+
+    timeout_backoff(source_id, grown, grown);
+    assert_eq!(timeout_for(source_id, ceiling), Duration::from_millis(675));
+    assert_eq!(
+        timeout_for(source_id, Duration::from_millis(500)),
+        Duration::from_millis(500)
+    );
+
+    record_success(source_id, Duration::from_millis(200));
+    assert_eq!(timeout_for(source_id, ceiling), timeout);
+}
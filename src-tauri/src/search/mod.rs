@@ -1,14 +1,14 @@
+pub(crate) mod adaptive_timeout;
+
 use crate::common::error::SearchError;
 use crate::common::register::SearchSourceRegistry;
-use crate::common::search::{
-    FailedRequest, MultiSourceQueryResponse, QueryHits, QuerySource, SearchQuery,
-};
+use crate::common::search::{FailedRequest, MultiSourceQueryResponse, QueryHits, SearchQuery};
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use tauri::{AppHandle, Manager, Runtime};
-use tokio::time::{timeout, Duration};
+use tokio::time::{timeout, Duration, Instant};
 
 #[tauri::command]
 pub async fn query_coco_fusion<R: Runtime>(
@@ -28,15 +28,18 @@ pub async fn query_coco_fusion<R: Runtime>(
 
     let sources_list = sources_future.await;
 
-    // Time limit for each query
-    let timeout_duration = Duration::from_millis(query_timeout);
+    // Time limit for each query, sources get their own adaptive timeouts
+    // that never exceed it.
+    let timeout_ceiling = Duration::from_millis(query_timeout);
 
     log::debug!(
         "query_coco_fusion: {:?}, timeout: {:?}",
         query_strings,
-        timeout_duration
+        timeout_ceiling
     );
 
+    let mut failed_requests = Vec::new();
+
     // Push all queries into futures
     for query_source in sources_list {
         let query_source_type = query_source.get_type().clone();
@@ -48,28 +51,52 @@ pub async fn query_coco_fusion<R: Runtime>(
             }
         }
 
-        sources.insert(query_source_type.id.clone(), query_source_type);
+        if let Some(remaining) = adaptive_timeout::backoff_remaining(&query_source_type.id) {
+            log::debug!(
+                "skip search source [{}], it is backed off for {:?}",
+                query_source_type.id,
+                remaining
+            );
+            failed_requests.push(FailedRequest {
+                source: query_source_type,
+                status: 0,
+                error: None,
+                reason: Some(format!(
+                    "backed off for {}ms after repeated timeouts",
+                    remaining.as_millis()
+                )),
+            });
+            continue;
+        }
+
+        sources.insert(query_source_type.id.clone(), query_source_type.clone());
 
         let query = SearchQuery::new(from, size, query_strings.clone());
         let query_source_clone = query_source.clone(); // Clone Arc to avoid ownership issues
+        let timeout_duration =
+            adaptive_timeout::timeout_for(&query_source_type.id, timeout_ceiling);
 
         futures.push(tokio::spawn(async move {
+            let start = Instant::now();
             // Timeout each query execution
-            timeout(timeout_duration, async {
+            let result = timeout(timeout_duration, async {
                 query_source_clone.search(query).await
             })
-            .await
+            .await;
+
+            (query_source_type, timeout_duration, start.elapsed(), result)
         }));
     }
 
     let mut total_hits = 0;
-    let mut failed_requests = Vec::new();
     let mut all_hits: Vec<(String, QueryHits, f64)> = Vec::new();
     let mut hits_per_source: HashMap<String, Vec<(QueryHits, f64)>> = HashMap::new();
 
     while let Some(result) = futures.next().await {
         match result {
-            Ok(Ok(Ok(response))) => {
+            Ok((source, _, elapsed, Ok(Ok(response)))) => {
+                adaptive_timeout::record_success(&source.id, elapsed);
+
                 total_hits += response.total_hits;
                 let source_id = response.source.id.clone();
 
@@ -90,25 +117,48 @@ pub async fn query_coco_fusion<R: Runtime>(
                         .push((query_hit, score));
                 }
             }
-            Ok(Ok(Err(err))) => {
+            Ok((source, timeout_duration, elapsed, Ok(Err(err)))) => {
                 log::error!("{}", err);
+                // Failures say nothing about the latency of the source
+                if matches!(err, SearchError::Timeout) {
+                    adaptive_timeout::record_timeout(
+                        &app_handle,
+                        &source.id,
+                        elapsed,
+                        timeout_duration,
+                    );
+                }
+
                 failed_requests.push(FailedRequest {
-                    source: QuerySource {
-                        r#type: "N/A".into(),
-                        name: "N/A".into(),
-                        id: "N/A".into(),
-                    },
+                    source,
                     status: 0,
                     error: Some(err.to_string()),
                     reason: None,
                 });
             }
-            Ok(Err(err)) => {
-                log::error!("{}", err);
-            }
             // Timeout reached, skip this request
-            _ => {
-                log::debug!("timeout reached, skip this request");
+            Ok((source, timeout_duration, elapsed, Err(_))) => {
+                log::debug!(
+                    "timeout reached after {:?}, skip search source [{}]",
+                    elapsed,
+                    source.id
+                );
+                adaptive_timeout::record_timeout(
+                    &app_handle,
+                    &source.id,
+                    elapsed,
+                    timeout_duration,
+                );
+
+                failed_requests.push(FailedRequest {
+                    source,
+                    status: 0,
+                    error: Some(SearchError::Timeout.to_string()),
+                    reason: Some(format!("timed out after {}ms", elapsed.as_millis())),
+                });
+            }
+            Err(err) => {
+                log::error!("{}", err);
             }
         }
     }
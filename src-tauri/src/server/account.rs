@@ -0,0 +1,172 @@
+//! Several accounts per Coco server, e.g., a personal and a client one.
+//!
+//! Every account has its own token and profile, the requests sent to a server
+//! (search, chat, ...) use the token of its active account. Logging in, with
+//! SSO or an access token, logs in to the active account, so adding an account
+//! creates it, makes it active, and lets the user log in.
+
+use crate::common::register::SearchSourceRegistry;
+use crate::common::server::{Server, ServerAccount, DEFAULT_ACCOUNT_ID};
+use crate::server::offline_cache;
+use crate::server::servers::{
+    get_account_token, get_server_by_id, persist_servers, remove_account_token, save_server,
+    try_register_server_to_search_source,
+};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+
+/// Emitted when the active account of a server changes.
+const EVENT_SERVER_ACCOUNT_CHANGED: &str = "server-account-changed";
+
+#[derive(Debug, Serialize)]
+pub struct ServerAccountStatus {
+    #[serde(flatten)]
+    pub account: ServerAccount,
+    pub active: bool,
+    pub logged_in: bool,
+}
+
+fn server_or_err(server_id: &str) -> Result<Server, String> {
+    get_server_by_id(server_id).ok_or_else(|| format!("Server not found for ID: {}", server_id))
+}
+
+/// Make the account specified by `account_id` the active one of `server`, and
+/// route the search through it.
+async fn activate_account<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    mut server: Server,
+    account_id: Option<String>,
+) -> Result<Server, String> {
+    server.active_account = account_id;
+    server.profile = server
+        .accounts
+        .iter()
+        .find(|account| account.id == server.active_account_id())
+        .and_then(|account| account.profile.clone());
+    // Whether the token of this account is still valid is checked on use
+    server.needs_relogin = false;
+    save_server(&server);
+    persist_servers(app_handle).await?;
+
+    // The search source and the cached documents belong to the previous
+    // account
+    let registry = app_handle.state::<SearchSourceRegistry>();
+    registry.remove_source(&server.id).await;
+    offline_cache::forget_server_documents(&server.id);
+    try_register_server_to_search_source(app_handle.clone(), &server).await;
+
+    let _ = app_handle.emit(
+        EVENT_SERVER_ACCOUNT_CHANGED,
+        serde_json::json!({ "id": server.id, "account_id": server.active_account_id() }),
+    );
+
+    Ok(server)
+}
+
+#[tauri::command]
+pub async fn list_server_accounts(server_id: String) -> Result<Vec<ServerAccountStatus>, String> {
+    let server = server_or_err(&server_id)?;
+
+    server
+        .accounts
+        .iter()
+        .map(|account| {
+            Ok(ServerAccountStatus {
+                account: account.clone(),
+                active: account.id == server.active_account_id(),
+                logged_in: get_account_token(&server_id, &account.id)?.is_some(),
+            })
+        })
+        .collect()
+}
+
+/// Add an account named `name` to the server specified by `server_id` and make
+/// it the active one, the user should log in next.
+#[tauri::command]
+pub async fn add_server_account<R: Runtime>(
+    app_handle: AppHandle<R>,
+    server_id: String,
+    name: String,
+) -> Result<Server, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The account name is empty".to_string());
+    }
+
+    let mut server = server_or_err(&server_id)?;
+    if server.accounts.iter().any(|account| account.name == name) {
+        return Err(format!("An account named [{}] already exists", name));
+    }
+
+    // Keep the account of the previous versions, whose token is stored
+    // without an account ID
+    if server.accounts.is_empty() && get_account_token(&server_id, DEFAULT_ACCOUNT_ID)?.is_some() {
+        let profile = server.profile.clone();
+        server.set_active_profile(profile);
+    }
+
+    let account_id = pizza_common::utils::uuid::Uuid::new().to_string();
+    server.accounts.push(ServerAccount {
+        id: account_id.clone(),
+        name: name.to_string(),
+        profile: None,
+    });
+
+    activate_account(&app_handle, server, Some(account_id)).await
+}
+
+#[tauri::command]
+pub async fn switch_server_account<R: Runtime>(
+    app_handle: AppHandle<R>,
+    server_id: String,
+    account_id: String,
+) -> Result<Server, String> {
+    let server = server_or_err(&server_id)?;
+    if !server
+        .accounts
+        .iter()
+        .any(|account| account.id == account_id)
+    {
+        return Err(format!("Account not found for ID: {}", account_id));
+    }
+    if server.active_account_id() == account_id {
+        return Ok(server);
+    }
+
+    log::debug!(
+        "switching server [{}] to account [{}]",
+        server_id,
+        account_id
+    );
+    activate_account(&app_handle, server, Some(account_id)).await
+}
+
+/// Log out of and forget an account, another account becomes active if it was
+/// the active one.
+#[tauri::command]
+pub async fn remove_server_account<R: Runtime>(
+    app_handle: AppHandle<R>,
+    server_id: String,
+    account_id: String,
+) -> Result<Server, String> {
+    let mut server = server_or_err(&server_id)?;
+    let Some(index) = server
+        .accounts
+        .iter()
+        .position(|account| account.id == account_id)
+    else {
+        return Err(format!("Account not found for ID: {}", account_id));
+    };
+
+    server.accounts.remove(index);
+    remove_account_token(&server_id, &account_id);
+
+    if server.active_account_id() != account_id {
+        save_server(&server);
+        persist_servers(&app_handle).await?;
+        return Ok(server);
+    }
+
+    let next_account = server.accounts.first().map(|account| account.id.clone());
+    activate_account(&app_handle, server, next_account).await
+}
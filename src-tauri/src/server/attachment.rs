@@ -1,6 +1,7 @@
-use super::servers::{get_server_by_id, get_server_token};
+use super::servers::get_server_by_id;
 use crate::common::http::get_response_body_text;
-use crate::server::http_client::HttpClient;
+use crate::server::auth;
+use crate::server::http_client::{HttpClient, RequestProfile};
 use reqwest::multipart::{Form, Part};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -85,13 +86,13 @@ pub async fn upload_attachment(
     let server = get_server_by_id(&server_id).ok_or("Server not found")?;
     let url = HttpClient::join_url(&server.endpoint, &format!("chat/{}/_upload", session_id));
 
-    let token = get_server_token(&server_id).await?;
+    let token = auth::valid_access_token(&server).await?;
     let mut headers = HashMap::new();
     if let Some(token) = token {
         headers.insert("X-API-TOKEN".to_string(), token.access_token);
     }
 
-    let client = reqwest::Client::new();
+    let client = HttpClient::client_for_server(&server, RequestProfile::Streaming)?;
     let response = client
         .post(url)
         .multipart(form)
@@ -99,6 +100,9 @@ pub async fn upload_attachment(
         .send()
         .await
         .map_err(|err| err.to_string())?;
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        auth::mark_server_needs_relogin(&server_id, "the server rejected the access token");
+    }
 
     let body = get_response_body_text(response).await?;
 
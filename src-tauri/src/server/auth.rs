@@ -1,56 +1,515 @@
-use crate::common::server::ServerAccessToken;
-use crate::server::profile::get_user_profiles;
+use crate::common::http::get_response_body_text;
+use crate::common::server::{Server, ServerAccessToken};
+use crate::server::http_client::HttpClient;
+use crate::server::profile::get_user_profile_with_token;
 use crate::server::servers::{
-    get_server_by_id, persist_servers, persist_servers_token, save_access_token, save_server,
+    active_account_id, get_account_token, get_all_servers, get_server_by_id, persist_servers,
+    persist_servers_token, save_access_token, save_account_token, save_server,
     try_register_server_to_search_source,
 };
-use tauri::{AppHandle, Runtime};
+use crate::GLOBAL_TAURI_APP_HANDLE;
+use lazy_static::lazy_static;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+use tokio::sync::Mutex;
+use url::Url;
+
+/// Event emitted when a server needs the user to log in again.
+const EVENT_SERVER_LOGIN_REQUIRED: &str = "server-login-required";
+
+/// Tokens are refreshed when they expire in less than this many seconds.
+const TOKEN_REFRESH_MARGIN_SECS: u32 = 300;
+/// How often the tokens are checked in the background.
+const TOKEN_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Serialize the refreshes of each server, so that concurrent requests
+    /// don't all refresh the same token, keyed by server ID.
+    static ref TOKEN_REFRESH_LOCKS: StdMutex<HashMap<String, Arc<Mutex<()>>>> =
+        StdMutex::new(HashMap::new());
+}
+
+/// Return the lock serializing the token refreshes of the server `server_id`.
+fn token_refresh_lock(server_id: &str) -> Arc<Mutex<()>> {
+    TOKEN_REFRESH_LOCKS
+        .lock()
+        .unwrap()
+        .entry(server_id.to_string())
+        .or_default()
+        .clone()
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessTokenResponse {
+    access_token: String,
+    /// Lifetime of the token in seconds.
+    expire_in: Option<u64>,
+    refresh_token: Option<String>,
+}
+
+fn now_secs() -> u32 {
+    chrono::Utc::now().timestamp() as u32
+}
+
+/// Convert the lifetime of a token into its `expired_at`, 0 if unknown.
+pub(crate) fn expired_at_from_expire_in(expire_in: Option<u64>) -> u32 {
+    match expire_in {
+        Some(expire_in) => now_secs().saturating_add(expire_in.min(u32::MAX as u64) as u32),
+        None => 0,
+    }
+}
+
+/// Mark the server specified by `server_id` as needing the user to log in
+/// again, and notify the frontend.
+pub(crate) fn mark_server_needs_relogin(server_id: &str, reason: &str) {
+    let Some(mut server) = get_server_by_id(server_id) else {
+        return;
+    };
+    if server.needs_relogin {
+        return;
+    }
+
+    log::warn!("server [{}] needs a new login: {}", server_id, reason);
+
+    server.needs_relogin = true;
+    save_server(&server);
+
+    if let Some(app_handle) = GLOBAL_TAURI_APP_HANDLE.get() {
+        let _ = app_handle.emit(
+            EVENT_SERVER_LOGIN_REQUIRED,
+            serde_json::json!({ "id": server_id, "reason": reason }),
+        );
+
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = persist_servers(&app_handle).await {
+                log::error!("failed to persist servers: {}", e);
+            }
+        });
+    }
+}
+
+/// Clear the flag set by [`mark_server_needs_relogin()`] once the server has a
+/// valid token again.
+fn clear_server_needs_relogin(server_id: &str) {
+    let Some(mut server) = get_server_by_id(server_id) else {
+        return;
+    };
+    if !server.needs_relogin {
+        return;
+    }
+
+    log::info!("server [{}] no longer needs a new login", server_id);
+
+    server.needs_relogin = false;
+    save_server(&server);
+
+    if let Some(app_handle) = GLOBAL_TAURI_APP_HANDLE.get() {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = persist_servers(&app_handle).await {
+                log::error!("failed to persist servers: {}", e);
+            }
+        });
+    }
+}
+
+/// Exchange the refresh token of `token` for a new access token.
+async fn refresh_access_token(
+    server: &Server,
+    token: &ServerAccessToken,
+) -> Result<ServerAccessToken, String> {
+    let refresh_token = token
+        .refresh_token
+        .as_deref()
+        .ok_or("no refresh token available")?;
+
+    let url = HttpClient::join_url(&server.endpoint, "/auth/refresh_token");
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+    headers.insert("X-API-TOKEN".to_string(), token.access_token.clone());
+    let body = serde_json::json!({ "refresh_token": refresh_token });
+
+    // Not through `HttpClient::send_request()`, which refreshes tokens itself
+    let response = HttpClient::send_raw_request_with_settings(
+        server.tls.as_ref(),
+        server.proxy.as_ref(),
+        Method::POST,
+        &url,
+        None,
+        Some(headers),
+        Some(reqwest::Body::from(body.to_string())),
+    )
+    .await?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => {
+            return Err("the server does not support refreshing tokens".into())
+        }
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err("the server rejected the refresh token".into())
+        }
+        _ => {}
+    }
+
+    let body = get_response_body_text(response).await?;
+    let refreshed: AccessTokenResponse = serde_json::from_str(&body)
+        .map_err(|e| format!("Failed to parse the refreshed token: {}", e))?;
+
+    Ok(ServerAccessToken {
+        id: token.id.clone(),
+        access_token: refreshed.access_token,
+        expired_at: expired_at_from_expire_in(refreshed.expire_in),
+        // Keep using the same refresh token if the server didn't rotate it
+        refresh_token: refreshed
+            .refresh_token
+            .or_else(|| token.refresh_token.clone()),
+    })
+}
+
+/// Return the token to use for the requests sent to `server`, refreshing it
+/// first if it is about to expire and the server supports it.
+pub(crate) async fn valid_access_token(
+    server: &Server,
+) -> Result<Option<ServerAccessToken>, String> {
+    let needs_refresh =
+        |token: &ServerAccessToken| token.expires_within(now_secs(), TOKEN_REFRESH_MARGIN_SECS);
+
+    // The refreshed token belongs to this account even if the user switches
+    // to another one meanwhile
+    let account_id = active_account_id(&server.id);
+
+    match get_account_token(&server.id, &account_id)? {
+        Some(token) if needs_refresh(&token) => {}
+        token => return Ok(token),
+    }
+
+    let lock = token_refresh_lock(&server.id);
+    let _guard = lock.lock().await;
+
+    // It may have been refreshed while we were waiting for the lock
+    let Some(token) = get_account_token(&server.id, &account_id)? else {
+        return Ok(None);
+    };
+    if !needs_refresh(&token) {
+        return Ok(Some(token));
+    }
+
+    if token.refresh_token.is_some() {
+        match refresh_access_token(server, &token).await {
+            Ok(refreshed) => {
+                log::debug!("refreshed the token of server [{}]", server.id);
+                save_account_token(server.id.clone(), account_id, refreshed.clone());
+                if let Some(app_handle) = GLOBAL_TAURI_APP_HANDLE.get() {
+                    persist_servers_token(app_handle)?;
+                }
+                clear_server_needs_relogin(&server.id);
+                return Ok(Some(refreshed));
+            }
+            Err(e) => log::warn!(
+                "failed to refresh the token of server [{}]: {}",
+                server.id,
+                e
+            ),
+        }
+    }
+
+    if token.expires_within(now_secs(), 0) {
+        mark_server_needs_relogin(&server.id, "the access token has expired");
+    }
+
+    // Let the server decide, it may still accept it
+    Ok(Some(token))
+}
+
+/// Check the tokens of all the servers periodically, so that they get
+/// refreshed before they expire even if no request is sent.
+pub(crate) fn start_token_refresh_task() {
+    tauri::async_runtime::spawn(async {
+        loop {
+            tokio::time::sleep(TOKEN_CHECK_INTERVAL).await;
+
+            for server in get_all_servers() {
+                if !server.enabled || server.needs_relogin {
+                    continue;
+                }
+                if let Err(e) = valid_access_token(&server).await {
+                    log::warn!("failed to check the token of server [{}]: {}", server.id, e);
+                }
+            }
+        }
+    });
+}
 
-#[allow(dead_code)]
 fn request_access_token_url(request_id: &str) -> String {
     // Remove the endpoint part and keep just the path for the request
     format!("/auth/request_access_token?request_id={}", request_id)
 }
 
+/// SSO logins we started and whose callback we are waiting for, keyed by
+/// request ID.
+struct PendingSsoLogin {
+    server_id: String,
+    /// Account being logged in to, the active one when the login started.
+    account_id: String,
+    state: String,
+    code_verifier: String,
+    started_at: Instant,
+}
+
+/// A login that is not completed within this delay is rejected.
+const SSO_LOGIN_TTL: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    static ref PENDING_SSO_LOGINS: StdMutex<HashMap<String, PendingSsoLogin>> =
+        StdMutex::new(HashMap::new());
+}
+
+pub(crate) fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// PKCE `S256` code challenge of `code_verifier`, see RFC 7636.
+fn pkce_code_challenge(code_verifier: &str) -> String {
+    let digest = Sha256::digest(code_verifier.as_bytes());
+    base64::encode_config(digest, base64::URL_SAFE_NO_PAD)
+}
+
+#[derive(Debug, Serialize)]
+pub struct SsoLoginRequest {
+    pub request_id: String,
+    /// URL to open in the browser.
+    pub url: String,
+}
+
+/// Start an SSO login to the server specified by `server_id`, the callback is
+/// only accepted by [`handle_sso_callback()`] if it matches this attempt.
+#[tauri::command]
+pub async fn start_sso_login(server_id: String) -> Result<SsoLoginRequest, String> {
+    let server = get_server_by_id(&server_id)
+        .ok_or_else(|| format!("Server not found for ID: {}", server_id))?;
+
+    let request_id = pizza_common::utils::uuid::Uuid::new().to_string();
+    let state = random_string(32);
+    let code_verifier = random_string(64);
+
+    let mut url = Url::parse(&server.auth_provider.sso.url)
+        .map_err(|e| format!("Invalid SSO URL [{}]: {}", server.auth_provider.sso.url, e))?;
+    // The login page is the directory of the SSO URL
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url.query_pairs_mut()
+        .append_pair("provider", &server.id)
+        .append_pair("product", "coco")
+        .append_pair("request_id", &request_id)
+        .append_pair("state", &state)
+        .append_pair("code_challenge", &pkce_code_challenge(&code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    let mut pending = PENDING_SSO_LOGINS.lock().unwrap();
+    pending.retain(|_, login| login.started_at.elapsed() < SSO_LOGIN_TTL);
+    pending.insert(
+        request_id.clone(),
+        PendingSsoLogin {
+            account_id: server.active_account_id().to_string(),
+            server_id,
+            state,
+            code_verifier,
+            started_at: Instant::now(),
+        },
+    );
+
+    Ok(SsoLoginRequest {
+        request_id,
+        url: url.to_string(),
+    })
+}
+
+/// Return the ID of the server that the pending login `request_id` is for.
+pub(crate) fn pending_sso_login_server(request_id: &str) -> Option<String> {
+    PENDING_SSO_LOGINS
+        .lock()
+        .unwrap()
+        .get(request_id)
+        .map(|login| login.server_id.clone())
+}
+
+/// Exchange the authorization `code` of the login `request_id` for a token.
+async fn exchange_sso_code(
+    server: &Server,
+    request_id: &str,
+    code: &str,
+    code_verifier: &str,
+) -> Result<AccessTokenResponse, String> {
+    let url = HttpClient::join_url(&server.endpoint, &request_access_token_url(request_id));
+    let mut headers = HashMap::new();
+    headers.insert("X-API-TOKEN".to_string(), code.to_string());
+    let mut query_params = HashMap::new();
+    query_params.insert(
+        "code_verifier".to_string(),
+        JsonValue::String(code_verifier.to_string()),
+    );
+
+    let response = HttpClient::send_raw_request_with_settings(
+        server.tls.as_ref(),
+        server.proxy.as_ref(),
+        Method::GET,
+        &url,
+        Some(query_params),
+        Some(headers),
+        None,
+    )
+    .await?;
+
+    let status = response.status();
+    let body = get_response_body_text(response).await?;
+    if !status.is_success() {
+        return Err(format!("the server answered {}: {}", status, body.trim()));
+    }
+    serde_json::from_str(&body).map_err(|e| format!("Failed to parse the access token: {}", e))
+}
+
 #[tauri::command]
 pub async fn handle_sso_callback<R: Runtime>(
     app_handle: AppHandle<R>,
     server_id: String,
     request_id: String,
     code: String,
+    state: Option<String>,
+    expire_in: Option<u64>,
 ) -> Result<(), String> {
-    // Retrieve the server details using the server ID
-    let server = get_server_by_id(&server_id);
-
-    let expire_in = 3600; // TODO, need to update to actual expire_in value
-    if let Some(mut server) = server {
-        // Save the access token for the server
-        let access_token = ServerAccessToken::new(server_id.clone(), code.clone(), expire_in);
-        // dbg!(&server_id, &request_id, &code, &token);
-        save_access_token(server_id.clone(), access_token);
-        persist_servers_token(&app_handle)?;
-
-        // Register the server to the search source
-        try_register_server_to_search_source(app_handle.clone(), &server).await;
-
-        // Update the server's profile using the util::http::HttpClient::get method
-        let profile = get_user_profiles(app_handle.clone(), server_id.clone()).await;
-        dbg!(&profile);
-
-        match profile {
-            Ok(p) => {
-                server.profile = Some(p);
-                server.available = true;
-                save_server(&server);
-                persist_servers(&app_handle).await?;
-                Ok(())
-            }
-            Err(e) => Err(format!("Failed to get user profile: {}", e)),
+    let pending = {
+        let mut pending_logins = PENDING_SSO_LOGINS.lock().unwrap();
+        let Some(pending) = pending_logins.get(&request_id) else {
+            return Err(format!(
+                "Unknown or already used login request: {}",
+                request_id
+            ));
+        };
+
+        // A forged callback must not cancel the login, only remove it once the
+        // callback is known to be genuine
+        if state.as_deref() != Some(pending.state.as_str()) {
+            log::warn!(
+                "rejecting the SSO callback of request [{}] with a mismatched state",
+                request_id
+            );
+            return Err("The login callback state does not match, please sign in again".into());
+        }
+        if pending.server_id != server_id {
+            return Err("The login callback does not match the server being logged in to".into());
         }
-    } else {
-        Err(format!(
-            "Server not found for ID: {}, Request ID: {}, Code: {}",
-            server_id, request_id, code
-        ))
+
+        // A callback can only be used once
+        pending_logins
+            .remove(&request_id)
+            .expect("the pending login exists")
+    };
+    if pending.started_at.elapsed() >= SSO_LOGIN_TTL {
+        return Err("The login request has expired, please sign in again".into());
+    }
+
+    // Retrieve the server details using the server ID
+    let server = get_server_by_id(&server_id).ok_or_else(|| {
+        format!(
+            "Server not found for ID: {}, Request ID: {}",
+            server_id, request_id
+        )
+    })?;
+
+    let token = exchange_sso_code(&server, &request_id, &code, &pending.code_verifier)
+        .await
+        .map_err(|e| format!("Failed to get the access token: {}", e))?;
+
+    // Save the access token for the account that started the login, which
+    // may no longer be the active one
+    let access_token = ServerAccessToken {
+        id: server_id.clone(),
+        access_token: token.access_token,
+        expired_at: expired_at_from_expire_in(token.expire_in.or(expire_in)),
+        refresh_token: token.refresh_token,
+    };
+    let profile = get_user_profile_with_token(&server, &access_token.access_token).await;
+    save_account_token(server_id.clone(), pending.account_id.clone(), access_token);
+    persist_servers_token(&app_handle)?;
+
+    // Register the server to the search source
+    try_register_server_to_search_source(app_handle.clone(), &server).await;
+
+    let profile = profile.map_err(|e| format!("Failed to get user profile: {}", e))?;
+    let mut server = get_server_by_id(&server_id)
+        .ok_or_else(|| format!("Server not found for ID: {}", server_id))?;
+    server.set_account_profile(&pending.account_id, Some(profile));
+    server.available = true;
+    if server.active_account_id() == pending.account_id {
+        server.needs_relogin = false;
     }
+    save_server(&server);
+    persist_servers(&app_handle).await?;
+    Ok(())
+}
+
+/// Log in to the server specified by `server_id` with a personal access token
+/// (API token) instead of the SSO flow, e.g., on headless setups.
+///
+/// The token is validated by fetching the user profile, and only stored if it
+/// is accepted.
+#[tauri::command]
+pub async fn login_with_access_token<R: Runtime>(
+    app_handle: AppHandle<R>,
+    server_id: String,
+    access_token: String,
+) -> Result<Server, String> {
+    let access_token = access_token.trim();
+    if access_token.is_empty() {
+        return Err("The access token is empty".to_string());
+    }
+
+    let mut server = get_server_by_id(&server_id)
+        .ok_or_else(|| format!("Server not found for ID: {}", server_id))?;
+
+    // Validate the token before it is used for anything else
+    let profile = get_user_profile_with_token(&server, access_token)
+        .await
+        .map_err(|e| format!("Failed to log in with the access token: {}", e))?;
+
+    // Personal access tokens don't tell when they expire
+    save_access_token(
+        server_id.clone(),
+        ServerAccessToken::new(server_id.clone(), access_token.to_string(), 0),
+    );
+    persist_servers_token(&app_handle)?;
+
+    server.set_active_profile(Some(profile));
+    server.available = true;
+    server.needs_relogin = false;
+    save_server(&server);
+    persist_servers(&app_handle).await?;
+
+    try_register_server_to_search_source(app_handle.clone(), &server).await;
+
+    Ok(server)
+}
+
+#[test]
+fn test_pkce_code_challenge() {
+    // Example of RFC 7636, appendix B
+    assert_eq!(
+        pkce_code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+        "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    );
 }
@@ -0,0 +1,372 @@
+//! Per-server circuit breakers.
+//!
+//! After [`FAILURE_THRESHOLD`] consecutive failures, the circuit of a server
+//! opens: requests to it fail right away instead of waiting for their
+//! timeouts. Once its cooldown expires, a single request is let through to
+//! probe the server (half-open), the circuit closes if it succeeds and opens
+//! again with a longer cooldown otherwise. If the probe is cancelled, e.g., by
+//! a timeout, the circuit goes back to open so that another request probes the
+//! server. A background task probes the server, bypassing the circuit, while
+//! it is open, so that it recovers even if nothing else sends it requests.
+
+use crate::server::http_client::HttpClient;
+use crate::server::servers::{get_server_by_id, refresh_coco_server_info};
+use crate::GLOBAL_TAURI_APP_HANDLE;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+
+/// Number of consecutive failures that opens the circuit.
+const FAILURE_THRESHOLD: u32 = 5;
+const INITIAL_COOLDOWN: Duration = Duration::from_secs(10);
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+/// How often the probe task probes a half-open circuit whose probe is sent by
+/// someone else.
+const HALF_OPEN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Event emitted when the state of a circuit changes.
+const EVENT_SERVER_CIRCUIT_CHANGED: &str = "server-circuit-changed";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+struct CircuitBreaker {
+    state: CircuitState,
+    consecutive_failures: u32,
+    cooldown: Duration,
+    open_until: Option<Instant>,
+    /// Unix timestamp in milliseconds.
+    opened_at: Option<i64>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            cooldown: INITIAL_COOLDOWN,
+            open_until: None,
+            opened_at: None,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    fn open(&mut self) {
+        self.state = CircuitState::Open;
+        self.open_until = Some(Instant::now() + self.cooldown);
+        self.opened_at
+            .get_or_insert_with(|| chrono::Utc::now().timestamp_millis());
+    }
+}
+
+lazy_static! {
+    static ref CIRCUIT_BREAKERS: RwLock<HashMap<String, CircuitBreaker>> =
+        RwLock::new(HashMap::new());
+}
+
+fn notify_state_change(server_id: &str, state: CircuitState) {
+    log::debug!("circuit of server [{}] is now {:?}", server_id, state);
+
+    if let Some(app_handle) = GLOBAL_TAURI_APP_HANDLE.get() {
+        let _ = app_handle.emit(
+            EVENT_SERVER_CIRCUIT_CHANGED,
+            serde_json::json!({ "id": server_id, "state": state }),
+        );
+    }
+}
+
+/// Permission to send a request, given by [`before_request()`].
+///
+/// The outcome of the request is recorded with [`Self::record_success()`] or
+/// [`Self::record_failure()`]. If it is dropped before, e.g., because the
+/// request was cancelled, the probe it may be is given up.
+pub(crate) struct RequestPermit {
+    server_id: String,
+    /// Whether the request probes a half-open circuit.
+    probe: bool,
+}
+
+impl RequestPermit {
+    pub(crate) fn record_success(mut self) {
+        self.probe = false;
+        record_success(&self.server_id);
+    }
+
+    pub(crate) fn record_failure(mut self) {
+        self.probe = false;
+        record_failure(&self.server_id);
+    }
+}
+
+impl Drop for RequestPermit {
+    fn drop(&mut self) {
+        if self.probe {
+            give_up_probe(&self.server_id);
+        }
+    }
+}
+
+/// Open the half-open circuit of the server specified by `server_id` again,
+/// without waiting for a cooldown, so that the next request probes it.
+fn give_up_probe(server_id: &str) {
+    {
+        let mut breakers = CIRCUIT_BREAKERS.write().unwrap();
+        let Some(breaker) = breakers.get_mut(server_id) else {
+            return;
+        };
+        if breaker.state != CircuitState::HalfOpen {
+            return;
+        }
+        breaker.state = CircuitState::Open;
+        breaker.open_until = Some(Instant::now());
+    }
+
+    notify_state_change(server_id, CircuitState::Open);
+}
+
+/// Check whether a request can be sent to the server specified by
+/// `server_id`, returning an error if its circuit is open.
+pub(crate) fn before_request(server_id: &str) -> Result<RequestPermit, String> {
+    let mut permit = RequestPermit {
+        server_id: server_id.to_string(),
+        probe: false,
+    };
+    let mut breakers = CIRCUIT_BREAKERS.write().unwrap();
+    let Some(breaker) = breakers.get_mut(server_id) else {
+        return Ok(permit);
+    };
+
+    match breaker.state {
+        CircuitState::Closed => Ok(permit),
+        CircuitState::Open => {
+            let now = Instant::now();
+            match breaker.open_until {
+                Some(until) if until > now => Err(format!(
+                    "Server is unreachable, retrying in {}s",
+                    (until - now).as_secs().max(1)
+                )),
+                // Let this request probe the server
+                _ => {
+                    breaker.state = CircuitState::HalfOpen;
+                    drop(breakers);
+                    notify_state_change(server_id, CircuitState::HalfOpen);
+                    permit.probe = true;
+                    Ok(permit)
+                }
+            }
+        }
+        CircuitState::HalfOpen => {
+            Err("Server is unreachable, waiting for it to answer a probe".to_string())
+        }
+    }
+}
+
+/// Record a request to the server specified by `server_id` that reached it.
+fn record_success(server_id: &str) {
+    let previous = {
+        let mut breakers = CIRCUIT_BREAKERS.write().unwrap();
+        let Some(breaker) = breakers.get_mut(server_id) else {
+            return;
+        };
+
+        let previous = breaker.state;
+        *breaker = CircuitBreaker::default();
+        previous
+    };
+
+    if previous != CircuitState::Closed {
+        log::info!("server [{}] is reachable again", server_id);
+        notify_state_change(server_id, CircuitState::Closed);
+    }
+}
+
+/// Record a request to the server specified by `server_id` that failed because
+/// of the server or the network.
+///
+/// A failed probe opens the circuit again with a longer cooldown.
+fn record_failure(server_id: &str) {
+    let opened = {
+        let mut breakers = CIRCUIT_BREAKERS.write().unwrap();
+        let breaker = breakers.entry(server_id.to_string()).or_default();
+        breaker.consecutive_failures += 1;
+        // A failed probe, either let through by the circuit or sent by the probe
+        // task, which bypasses it once the cooldown has expired
+        let probe_failed = match breaker.state {
+            CircuitState::Closed => false,
+            CircuitState::Open => breaker
+                .open_until
+                .map_or(true, |until| until <= Instant::now()),
+            CircuitState::HalfOpen => true,
+        };
+
+        match breaker.state {
+            CircuitState::Closed if breaker.consecutive_failures >= FAILURE_THRESHOLD => {
+                breaker.open();
+                Some(true)
+            }
+            _ if probe_failed => {
+                breaker.cooldown = breaker.cooldown.saturating_mul(2).min(MAX_COOLDOWN);
+                breaker.open();
+                Some(false)
+            }
+            _ => None,
+        }
+    };
+
+    let Some(newly_opened) = opened else {
+        return;
+    };
+
+    log::warn!(
+        "server [{}] keeps failing, its circuit is now open",
+        server_id
+    );
+    notify_state_change(server_id, CircuitState::Open);
+
+    if newly_opened {
+        let server_id = server_id.to_string();
+        tauri::async_runtime::spawn(async move {
+            probe_until_closed(server_id).await;
+        });
+    }
+}
+
+/// Whether the circuit of the server specified by `server_id` is not closed.
+pub(crate) fn is_open(server_id: &str) -> bool {
+    CIRCUIT_BREAKERS
+        .read()
+        .unwrap()
+        .get(server_id)
+        .is_some_and(|breaker| breaker.state != CircuitState::Closed)
+}
+
+/// Forget the circuit of the server specified by `server_id`, e.g., when it
+/// is removed.
+pub(crate) fn forget_server(server_id: &str) {
+    CIRCUIT_BREAKERS.write().unwrap().remove(server_id);
+}
+
+/// How long to wait before probing the server specified by `server_id`,
+/// `None` if its circuit is closed.
+fn next_probe_in(server_id: &str) -> Option<Duration> {
+    let breakers = CIRCUIT_BREAKERS.read().unwrap();
+    let breaker = breakers.get(server_id)?;
+
+    match breaker.state {
+        CircuitState::Closed => None,
+        CircuitState::Open => Some(
+            breaker
+                .open_until
+                .map(|until| until.saturating_duration_since(Instant::now()))
+                .unwrap_or_default(),
+        ),
+        CircuitState::HalfOpen => Some(HALF_OPEN_POLL_INTERVAL),
+    }
+}
+
+async fn probe_until_closed(server_id: String) {
+    while let Some(wait) = next_probe_in(&server_id) {
+        tokio::time::sleep(wait).await;
+
+        let Some(server) = get_server_by_id(&server_id) else {
+            forget_server(&server_id);
+            return;
+        };
+
+        match HttpClient::probe_server(&server).await {
+            Ok(response) if !response.status().is_server_error() => record_success(&server_id),
+            _ => record_failure(&server_id),
+        }
+    }
+
+    // Bring the server back if it was marked as offline in the meantime
+    let offline = get_server_by_id(&server_id).is_some_and(|server| !server.available);
+    if let (true, Some(app_handle)) = (offline, GLOBAL_TAURI_APP_HANDLE.get()) {
+        let _ = refresh_coco_server_info(app_handle.clone(), server_id).await;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CircuitBreakerStatus {
+    pub id: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub opened_at: Option<i64>,
+    pub next_probe_in_ms: Option<u64>,
+}
+
+/// Return the state of the circuits of the servers that had failures.
+#[tauri::command]
+pub async fn get_server_circuit_states() -> Vec<CircuitBreakerStatus> {
+    let breakers = CIRCUIT_BREAKERS.read().unwrap();
+    let now = Instant::now();
+
+    breakers
+        .iter()
+        .map(|(id, breaker)| CircuitBreakerStatus {
+            id: id.clone(),
+            state: breaker.state,
+            consecutive_failures: breaker.consecutive_failures,
+            opened_at: breaker.opened_at,
+            next_probe_in_ms: match breaker.state {
+                CircuitState::Open => breaker
+                    .open_until
+                    .map(|until| until.saturating_duration_since(now).as_millis() as u64),
+                _ => None,
+            },
+        })
+        .collect()
+}
+
+#[test]
+fn test_cancelled_probe() {
+    let server_id = "test_cancelled_probe";
+    CIRCUIT_BREAKERS.write().unwrap().insert(
+        server_id.to_string(),
+        CircuitBreaker {
+            state: CircuitState::Open,
+            consecutive_failures: FAILURE_THRESHOLD,
+            open_until: Some(Instant::now()),
+            ..Default::default()
+        },
+    );
+
+    let probe = before_request(server_id).unwrap();
+    assert!(before_request(server_id).is_err());
+
+    // The request timed out
+    drop(probe);
+    before_request(server_id).unwrap().record_success();
+    assert!(!is_open(server_id));
+}
+
+#[test]
+fn test_failed_probe_while_open() {
+    let server_id = "test_failed_probe_while_open";
+    CIRCUIT_BREAKERS.write().unwrap().insert(
+        server_id.to_string(),
+        CircuitBreaker {
+            state: CircuitState::Open,
+            consecutive_failures: FAILURE_THRESHOLD,
+            open_until: Some(Instant::now()),
+            ..Default::default()
+        },
+    );
+
+    // The probe task probed the server without going through the circuit
+    record_failure(server_id);
+    assert!(next_probe_in(server_id).unwrap() > Duration::ZERO);
+    assert_eq!(
+        CIRCUIT_BREAKERS.read().unwrap()[server_id].cooldown,
+        INITIAL_COOLDOWN * 2
+    );
+}
@@ -0,0 +1,244 @@
+//! Background monitor of the health of the Coco servers.
+//!
+//! Every [`HEALTH_CHECK_INTERVAL`], the `/provider/_info` endpoint of each
+//! enabled server is requested, and the health of its services is checked. A
+//! server that stops answering, or whose health turns red, is marked as
+//! offline (its search source is removed) after [`FAILURES_BEFORE_OFFLINE`]
+//! consecutive failed checks, and it is brought back (its search source is
+//! registered again) as soon as a check succeeds.
+
+use crate::common::health::{Health, Status};
+use crate::common::http::get_response_body_text;
+use crate::server::http_client::HttpClient;
+use crate::server::servers::{
+    get_all_servers, get_server_by_id, mark_server_as_offline, refresh_coco_server_info,
+    save_server,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Number of consecutive failed checks after which a server is marked as
+/// offline, so that a single hiccup does not remove its search source.
+const FAILURES_BEFORE_OFFLINE: u32 = 2;
+/// Number of checks kept per server.
+const LATENCY_HISTORY_LEN: usize = 20;
+
+/// Event emitted when the availability or the health of a server changes.
+const EVENT_SERVER_HEALTH_CHANGED: &str = "server-health-changed";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthSample {
+    /// Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    /// `None` if the server could not be reached.
+    pub latency_ms: Option<u64>,
+    pub healthy: bool,
+}
+
+#[derive(Default)]
+struct ServerHealthState {
+    samples: VecDeque<HealthSample>,
+    consecutive_failures: u32,
+}
+
+lazy_static! {
+    static ref SERVER_HEALTH_STATES: RwLock<HashMap<String, ServerHealthState>> =
+        RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerHealthReport {
+    pub id: String,
+    pub available: bool,
+    pub health: Option<Health>,
+    pub latency_history: Vec<HealthSample>,
+}
+
+/// The part of the `/provider/_info` response we need.
+#[derive(Deserialize)]
+struct ProviderHealth {
+    health: Option<Health>,
+}
+
+enum CheckOutcome {
+    Healthy(Option<Health>),
+    /// The server answered, but some of its services are down.
+    Unhealthy(Option<Health>),
+    Unreachable(String),
+}
+
+fn is_healthy(health: Option<&Health>) -> bool {
+    health.map_or(true, |health| health.status != Status::Red)
+}
+
+async fn check_server(server_id: &str) -> (CheckOutcome, Duration) {
+    let started_at = Instant::now();
+    let response = HttpClient::get(server_id, "/provider/_info", None).await;
+    let latency = started_at.elapsed();
+
+    let response = match response {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            return (
+                CheckOutcome::Unreachable(format!("status {}", response.status())),
+                latency,
+            )
+        }
+        Err(e) => return (CheckOutcome::Unreachable(e), latency),
+    };
+
+    let outcome = match get_response_body_text(response)
+        .await
+        .and_then(|body| serde_json::from_str::<ProviderHealth>(&body).map_err(|e| e.to_string()))
+    {
+        Ok(ProviderHealth { health }) if is_healthy(health.as_ref()) => {
+            CheckOutcome::Healthy(health)
+        }
+        Ok(ProviderHealth { health }) => CheckOutcome::Unhealthy(health),
+        Err(e) => CheckOutcome::Unreachable(format!("invalid response: {}", e)),
+    };
+
+    (outcome, latency)
+}
+
+/// Record a check of the server specified by `server_id`, return its number of
+/// consecutive failures.
+fn record_sample(server_id: &str, latency_ms: Option<u64>, healthy: bool) -> u32 {
+    let mut states = SERVER_HEALTH_STATES.write().unwrap();
+    let state = states.entry(server_id.to_string()).or_default();
+
+    if state.samples.len() == LATENCY_HISTORY_LEN {
+        state.samples.pop_front();
+    }
+    state.samples.push_back(HealthSample {
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        latency_ms,
+        healthy,
+    });
+
+    if healthy {
+        state.consecutive_failures = 0;
+    } else {
+        state.consecutive_failures += 1;
+    }
+    state.consecutive_failures
+}
+
+fn health_report(server_id: &str) -> Option<ServerHealthReport> {
+    let server = get_server_by_id(server_id)?;
+    let latency_history = SERVER_HEALTH_STATES
+        .read()
+        .unwrap()
+        .get(server_id)
+        .map(|state| state.samples.iter().cloned().collect())
+        .unwrap_or_default();
+
+    Some(ServerHealthReport {
+        id: server.id,
+        available: server.available,
+        health: server.health,
+        latency_history,
+    })
+}
+
+fn notify_health_change<R: Runtime>(app_handle: &AppHandle<R>, server_id: &str) {
+    if let Some(report) = health_report(server_id) {
+        let _ = app_handle.emit(EVENT_SERVER_HEALTH_CHANGED, report);
+    }
+}
+
+async fn monitor_server<R: Runtime>(app_handle: &AppHandle<R>, server_id: &str) {
+    let Some(server) = get_server_by_id(server_id) else {
+        return;
+    };
+
+    let (outcome, latency) = check_server(server_id).await;
+    let latency_ms = latency.as_millis() as u64;
+
+    match outcome {
+        CheckOutcome::Healthy(health) => {
+            record_sample(server_id, Some(latency_ms), true);
+
+            if !server.available {
+                // Fetch the server info again and register its search source
+                match refresh_coco_server_info(app_handle.clone(), server_id.to_string()).await {
+                    Ok(_) => log::info!("server [{}] is available again", server_id),
+                    Err(e) => {
+                        log::warn!("failed to bring server [{}] back: {}", server_id, e);
+                        return;
+                    }
+                }
+            } else if server.health.as_ref() != health.as_ref() {
+                let mut server = server;
+                server.health = health;
+                save_server(&server);
+            } else {
+                return;
+            }
+        }
+        CheckOutcome::Unhealthy(health) => {
+            let failures = record_sample(server_id, Some(latency_ms), false);
+            log::warn!("server [{}] is unhealthy: {:?}", server_id, health);
+
+            if server.available && failures >= FAILURES_BEFORE_OFFLINE {
+                let _ = mark_server_as_offline(app_handle.clone(), server_id).await;
+            } else if server.health.as_ref() != health.as_ref() {
+                let mut server = server;
+                server.health = health;
+                save_server(&server);
+            } else {
+                return;
+            }
+        }
+        CheckOutcome::Unreachable(e) => {
+            let failures = record_sample(server_id, None, false);
+            log::debug!("server [{}] is unreachable: {}", server_id, e);
+
+            if !server.available || failures < FAILURES_BEFORE_OFFLINE {
+                return;
+            }
+            log::warn!("server [{}] is offline: {}", server_id, e);
+            let _ = mark_server_as_offline(app_handle.clone(), server_id).await;
+        }
+    }
+
+    notify_health_change(app_handle, server_id);
+}
+
+/// Start the background task checking the health of the enabled servers.
+pub(crate) fn start_health_monitor<R: Runtime>(app_handle: AppHandle<R>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+
+            let checks = get_all_servers()
+                .into_iter()
+                .filter(|server| server.enabled)
+                .map(|server| {
+                    let app_handle = app_handle.clone();
+                    async move { monitor_server(&app_handle, &server.id).await }
+                });
+            futures::future::join_all(checks).await;
+        }
+    });
+}
+
+/// Forget the health history of the server specified by `server_id`, e.g.,
+/// when it is removed.
+pub(crate) fn forget_server(server_id: &str) {
+    SERVER_HEALTH_STATES.write().unwrap().remove(server_id);
+}
+
+/// Return the availability, health and latency history of the servers.
+#[tauri::command]
+pub async fn get_server_health_reports() -> Vec<ServerHealthReport> {
+    get_all_servers()
+        .iter()
+        .filter_map(|server| health_report(&server.id))
+        .collect()
+}
@@ -1,31 +1,136 @@
-use crate::server::servers::{get_server_by_id, get_server_token};
+use crate::common::server::{ProxyConfig, Server, ServerTimeouts, ServerTlsConfig, TimeoutProfile};
+use crate::server::retry::{self, RetryPolicy};
+use crate::server::servers::get_server_by_id;
+use crate::server::{auth, circuit_breaker, proxy, tls};
+use arc_swap::ArcSwap;
 use http::{HeaderName, HeaderValue};
 use once_cell::sync::Lazy;
-use reqwest::{Client, Method, RequestBuilder};
+use reqwest::{Client, ClientBuilder, Method, RequestBuilder};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tauri_plugin_store::JsonValue;
-use tokio::sync::Mutex;
-
-pub(crate) fn new_reqwest_http_client(accept_invalid_certs: bool) -> Client {
-    Client::builder()
-        .read_timeout(Duration::from_secs(3)) // Set a timeout of 3 second
-        .connect_timeout(Duration::from_secs(3)) // Set a timeout of 3 second
-        .timeout(Duration::from_secs(10)) // Set a timeout of 10 seconds
-        .danger_accept_invalid_certs(accept_invalid_certs) // allow self-signed certificates
+
+/// Whether a request streams its response or its body, which needs much longer
+/// timeouts than the usual request/response round trip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestProfile {
+    Normal,
+    Streaming,
+}
+
+fn reqwest_http_client_builder(timeouts: &TimeoutProfile) -> ClientBuilder {
+    let mut builder =
+        Client::builder().connect_timeout(Duration::from_millis(timeouts.connect_timeout_ms));
+    if let Some(read_timeout) = timeouts.read_timeout_ms {
+        builder = builder.read_timeout(Duration::from_millis(read_timeout));
+    }
+    if let Some(total_timeout) = timeouts.total_timeout_ms {
+        builder = builder.timeout(Duration::from_millis(total_timeout));
+    }
+
+    builder
+}
+
+/// Build a client for the requests of `profile` sent to a server whose own
+/// settings are `tls`, `proxy` and `timeouts`, the global settings and the
+/// default timeouts are used for those that are `None`.
+fn new_reqwest_http_client(
+    tls: Option<&ServerTlsConfig>,
+    proxy: Option<&ProxyConfig>,
+    timeouts: Option<&ServerTimeouts>,
+    profile: RequestProfile,
+) -> Result<Client, String> {
+    let timeouts = timeouts.copied().unwrap_or_default();
+    let timeouts = match profile {
+        RequestProfile::Normal => timeouts.normal,
+        RequestProfile::Streaming => timeouts.streaming,
+    };
+
+    let proxy = proxy::effective_proxy_config(proxy);
+    let builder = proxy::apply_to_reqwest(reqwest_http_client_builder(&timeouts), &proxy)?;
+
+    let builder = match tls {
+        Some(tls) => tls::apply_to_reqwest(builder, tls)?,
+        // allow self-signed certificates
+        None => builder.danger_accept_invalid_certs(tls::allow_self_signature(None)),
+    };
+
+    builder
         .build()
-        .expect("Failed to build client")
+        .map_err(|e| format!("Failed to build client: {}", e))
+}
+
+/// Key of [`HTTP_CLIENTS`], `None` is for the requests not sent to a known
+/// server.
+type ClientKey = (Option<String>, RequestProfile);
+
+/// Clients are cheap to clone but expensive to build, and every server may
+/// have its own settings, so we keep one per server and request profile.
+///
+/// Requests only read this map, it gets replaced when a client is added or
+/// removed, so it never blocks them.
+static HTTP_CLIENTS: Lazy<ArcSwap<HashMap<ClientKey, Client>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+/// Bumped whenever clients are invalidated, so that a client built with the
+/// previous settings won't be added to [`HTTP_CLIENTS`].
+static HTTP_CLIENTS_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn pooled_client(server: Option<&Server>, profile: RequestProfile) -> Result<Client, String> {
+    let key: ClientKey = (server.map(|s| s.id.clone()), profile);
+    if let Some(client) = HTTP_CLIENTS.load().get(&key) {
+        return Ok(client.clone());
+    }
+
+    let generation = HTTP_CLIENTS_GENERATION.load(Ordering::Acquire);
+    let client = new_reqwest_http_client(
+        server.and_then(|s| s.tls.as_ref()),
+        server.and_then(|s| s.proxy.as_ref()),
+        server.and_then(|s| s.timeouts.as_ref()),
+        profile,
+    )?;
+
+    HTTP_CLIENTS.rcu(|clients| {
+        let mut clients = HashMap::clone(clients);
+        if HTTP_CLIENTS_GENERATION.load(Ordering::Acquire) == generation {
+            clients.entry(key.clone()).or_insert_with(|| client.clone());
+        }
+        clients
+    });
+
+    Ok(client)
+}
+
+/// Drop the clients of the server specified by `server_id`, they will be
+/// rebuilt with the latest settings upon the next request.
+pub(crate) fn invalidate_server_http_client(server_id: &str) {
+    HTTP_CLIENTS_GENERATION.fetch_add(1, Ordering::AcqRel);
+    HTTP_CLIENTS.rcu(|clients| {
+        let mut clients = HashMap::clone(clients);
+        clients.retain(|(id, _), _| id.as_deref() != Some(server_id));
+        clients
+    });
 }
 
-pub static HTTP_CLIENT: Lazy<Mutex<Client>> = Lazy::new(|| {
-    let allow_self_signature = crate::settings::_get_allow_self_signature(
-        crate::GLOBAL_TAURI_APP_HANDLE
-            .get()
-            .expect("global tauri app store not set")
-            .clone(),
-    );
-    Mutex::new(new_reqwest_http_client(allow_self_signature))
-});
+/// Drop all the clients, e.g., when a global setting changes.
+pub(crate) fn invalidate_http_clients() {
+    HTTP_CLIENTS_GENERATION.fetch_add(1, Ordering::AcqRel);
+    HTTP_CLIENTS.store(Arc::new(HashMap::new()));
+}
+
+/// Rebuild the clients upon the next request when a global setting they use
+/// changes, called upon app start.
+pub(crate) fn watch_settings() {
+    crate::settings::subscribe(|previous, current| {
+        if previous.allow_self_signature != current.allow_self_signature
+            || previous.proxy != current.proxy
+        {
+            invalidate_http_clients();
+        }
+    });
+}
 
 pub struct HttpClient;
 
@@ -43,6 +148,45 @@ impl HttpClient {
         query_params: Option<HashMap<String, JsonValue>>,
         headers: Option<HashMap<String, String>>,
         body: Option<reqwest::Body>,
+    ) -> Result<reqwest::Response, String> {
+        let client = pooled_client(None, RequestProfile::Normal)?;
+
+        Self::send_raw_request_with_client(&client, None, method, url, query_params, headers, body)
+            .await
+    }
+
+    /// Same as [`Self::send_raw_request()`], except that the TLS settings `tls`
+    /// and the proxy settings `proxy` are used, for the requests sent to a
+    /// server that is not added yet.
+    pub async fn send_raw_request_with_settings(
+        tls: Option<&ServerTlsConfig>,
+        proxy: Option<&ProxyConfig>,
+        method: Method,
+        url: &str,
+        query_params: Option<HashMap<String, JsonValue>>,
+        headers: Option<HashMap<String, String>>,
+        body: Option<reqwest::Body>,
+    ) -> Result<reqwest::Response, String> {
+        let client = new_reqwest_http_client(tls, proxy, None, RequestProfile::Normal)?;
+
+        Self::send_raw_request_with_client(&client, None, method, url, query_params, headers, body)
+            .await
+    }
+
+    /// Send a request with `client`, retrying it if it is idempotent and fails
+    /// transiently, see [`retry`].
+    ///
+    /// If `server_id` is set, the request goes through the circuit breaker of
+    /// that server, its outcome is recorded once, whatever the number of
+    /// attempts.
+    async fn send_raw_request_with_client(
+        client: &Client,
+        server_id: Option<&str>,
+        method: Method,
+        url: &str,
+        query_params: Option<HashMap<String, JsonValue>>,
+        headers: Option<HashMap<String, String>>,
+        body: Option<reqwest::Body>,
     ) -> Result<reqwest::Response, String> {
         log::debug!(
             "Sending Request: {}, query_params: {:?}, header: {:?}, body: {:?}",
@@ -52,33 +196,133 @@ impl HttpClient {
             &body
         );
 
-        let request_builder =
-            Self::get_request_builder(method, url, headers, query_params, body).await;
+        // Only the bodies held in memory can be sent again, `None` if it cannot
+        let replay_body: Option<Option<Vec<u8>>> = match &body {
+            None => Some(None),
+            Some(body) => body.as_bytes().map(|bytes| Some(bytes.to_vec())),
+        };
+        let policy = if retry::is_idempotent(&method) && replay_body.is_some() {
+            RetryPolicy::default()
+        } else {
+            RetryPolicy::no_retry()
+        };
 
-        let response = request_builder.send().await.map_err(|e| {
-            //dbg!("Failed to send request: {}", &e);
-            format!("Failed to send request: {}", e)
-        })?;
+        let mut permit = match server_id {
+            Some(server_id) => Some(circuit_breaker::before_request(server_id)?),
+            None => None,
+        };
+        let mut body = body;
+        let mut attempt = 1;
+        loop {
+            let attempt_body = if attempt == 1 {
+                body.take()
+            } else {
+                replay_body.clone().flatten().map(reqwest::Body::from)
+            };
+            let request_builder = Self::build_request(
+                client,
+                method.clone(),
+                url,
+                headers.clone(),
+                query_params.clone(),
+                attempt_body,
+            );
 
-        log::debug!(
-            "Request: {}, Response status: {:?}, header: {:?}",
-            &url,
-            &response.status(),
-            &response.headers()
-        );
+            let response = match request_builder.send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    if attempt < policy.max_attempts && retry::is_retryable_error(&e) {
+                        let delay = policy.backoff(attempt);
+                        log::debug!("Request: {} failed: {}, retrying in {:?}", &url, e, delay);
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                        continue;
+                    }
 
-        Ok(response)
+                    //dbg!("Failed to send request: {}", &e);
+                    if let Some(permit) = permit.take() {
+                        permit.record_failure();
+                    }
+                    return Err(format!("Failed to send request: {}", e));
+                }
+            };
+
+            log::debug!(
+                "Request: {}, Response status: {:?}, header: {:?}",
+                &url,
+                &response.status(),
+                &response.headers()
+            );
+
+            if attempt < policy.max_attempts {
+                if let Some(delay) = policy.delay_after_response(&response, attempt) {
+                    log::debug!(
+                        "Request: {} got status {}, retrying in {:?}",
+                        &url,
+                        response.status(),
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+            }
+
+            if let Some(permit) = permit.take() {
+                if response.status().is_server_error() {
+                    permit.record_failure();
+                } else {
+                    permit.record_success();
+                }
+            }
+            return Ok(response);
+        }
+    }
+
+    /// Request the info of `server`, bypassing its circuit breaker, to probe it.
+    pub(crate) async fn probe_server(server: &Server) -> Result<reqwest::Response, String> {
+        let client = Self::client_for_server(server, RequestProfile::Normal)?;
+        let url = Self::join_url(&server.endpoint, "/provider/_info");
+
+        Self::send_raw_request_with_client(&client, None, Method::GET, &url, None, None, None).await
+    }
+
+    /// Return the client to use for the requests of `profile` sent to `server`.
+    pub(crate) fn client_for_server(
+        server: &Server,
+        profile: RequestProfile,
+    ) -> Result<Client, String> {
+        pooled_client(Some(server), profile)
     }
 
+    #[allow(dead_code)]
     pub async fn get_request_builder(
         method: Method,
         url: &str,
         headers: Option<HashMap<String, String>>,
         query_params: Option<HashMap<String, JsonValue>>, // Add query parameters
         body: Option<reqwest::Body>,
-    ) -> RequestBuilder {
-        let client = HTTP_CLIENT.lock().await; // Acquire the lock on HTTP_CLIENT
+    ) -> Result<RequestBuilder, String> {
+        let client = pooled_client(None, RequestProfile::Normal)?;
 
+        Ok(Self::build_request(
+            &client,
+            method,
+            url,
+            headers,
+            query_params,
+            body,
+        ))
+    }
+
+    fn build_request(
+        client: &Client,
+        method: Method,
+        url: &str,
+        headers: Option<HashMap<String, String>>,
+        query_params: Option<HashMap<String, JsonValue>>, // Add query parameters
+        body: Option<reqwest::Body>,
+    ) -> RequestBuilder {
         // Build the request
         let mut request_builder = client.request(method.clone(), url);
 
@@ -142,15 +386,47 @@ impl HttpClient {
         custom_headers: Option<HashMap<String, String>>,
         query_params: Option<HashMap<String, JsonValue>>,
         body: Option<reqwest::Body>,
+    ) -> Result<reqwest::Response, String> {
+        Self::send_request_with_profile(
+            server_id,
+            RequestProfile::Normal,
+            method,
+            path,
+            custom_headers,
+            query_params,
+            body,
+        )
+        .await
+    }
+
+    /// Same as [`Self::send_request()`], except that the timeouts of `profile`
+    /// are used.
+    pub async fn send_request_with_profile(
+        server_id: &str,
+        profile: RequestProfile,
+        method: Method,
+        path: &str,
+        custom_headers: Option<HashMap<String, String>>,
+        query_params: Option<HashMap<String, JsonValue>>,
+        body: Option<reqwest::Body>,
     ) -> Result<reqwest::Response, String> {
         // Fetch the server using the server_id
         let server = get_server_by_id(server_id);
         if let Some(s) = server {
+            // Only the server info is fetched from servers requiring a newer
+            // client, to notice when they accept us again
+            if let Some(reason) = &s.incompatible_reason {
+                if path != "/provider/_info" {
+                    return Err(reason.clone());
+                }
+            }
+
             // Construct the URL
             let url = HttpClient::join_url(&s.endpoint, path);
 
-            // Retrieve the token for the server (token is optional)
-            let token = get_server_token(server_id)
+            // Retrieve the token for the server (token is optional), it gets
+            // refreshed if it is about to expire
+            let token = auth::valid_access_token(&s)
                 .await?
                 .map(|t| t.access_token.clone());
 
@@ -172,7 +448,24 @@ impl HttpClient {
             //     &headers
             // );
 
-            Self::send_raw_request(method, &url, query_params, Some(headers), body).await
+            let client = Self::client_for_server(&s, profile)?;
+
+            let response = Self::send_raw_request_with_client(
+                &client,
+                Some(server_id),
+                method,
+                &url,
+                query_params,
+                Some(headers),
+                body,
+            )
+            .await?;
+
+            if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+                auth::mark_server_needs_relogin(server_id, "the server rejected the access token");
+            }
+
+            Ok(response)
         } else {
             Err("Server not found".to_string())
         }
@@ -216,7 +509,6 @@ impl HttpClient {
     }
 
     // Convenience method for PUT requests
-    #[allow(dead_code)]
     pub async fn put(
         server_id: &str,
         path: &str,
@@ -1,13 +1,21 @@
 //! This file contains Rust APIs related to Coco Server management.
 
+pub mod account;
 pub mod attachment;
 pub mod auth;
+pub mod circuit_breaker;
 pub mod connector;
 pub mod datasource;
+pub mod health_monitor;
 pub mod http_client;
+pub mod offline_cache;
 pub mod profile;
+pub mod proxy;
+pub mod retry;
 pub mod search;
+pub mod secret_store;
 pub mod servers;
 pub mod system_settings;
+pub mod tls;
 pub mod transcription;
 pub mod websocket;
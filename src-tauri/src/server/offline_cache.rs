@@ -0,0 +1,484 @@
+//! Offline mirror of the documents returned by Coco servers.
+//!
+//! When enabled, the documents we see in a server's search results, or that
+//! the user opens, are remembered in a local store. If that server goes offline,
+//! [`OfflineCacheSearchSource`] takes its place in the search source registry so
+//! that those documents stay searchable. Hits from it are marked as cached.
+
+use crate::common::document::Document;
+use crate::common::error::SearchError;
+use crate::common::register::SearchSourceRegistry;
+use crate::common::search::{QueryResponse, QuerySource, SearchQuery};
+use crate::common::server::Server;
+use crate::common::traits::SearchSource;
+use crate::server::servers::get_server_by_id;
+use crate::GLOBAL_TAURI_APP_HANDLE;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Runtime};
+use tauri_plugin_store::StoreExt;
+
+/// Tauri store that holds the cached documents, keyed by server ID.
+const TAURI_STORE_OFFLINE_DOCUMENT_CACHE: &str = "offline_document_cache";
+
+/// Search source type of [`OfflineCacheSearchSource`], so that the frontend
+/// can tell cached hits from the live ones.
+pub const COCO_SERVERS_OFFLINE_CACHE: &str = "coco-servers-cache";
+
+/// Maximum number of documents we keep for a single server, the least
+/// recently seen ones are evicted first.
+const MAX_CACHED_DOCUMENTS_PER_SERVER: usize = 500;
+
+/// Base score of a cached hit, the live sources should win when both exist.
+const CACHED_HIT_BASE_SCORE: f64 = 1.0;
+
+/// The changes are written to the store at most once per this delay, searches
+/// run on every keystroke.
+const PERSIST_DELAY: Duration = Duration::from_secs(5);
+
+static OFFLINE_CACHE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The cached documents of a server, keyed by document ID.
+type ServerCache = HashMap<String, CachedDocument>;
+
+lazy_static! {
+    static ref OFFLINE_CACHE: RwLock<HashMap<String, ServerCache>> = RwLock::new(HashMap::new());
+    /// The servers whose cache changed since it was last written, `None` if no
+    /// write is scheduled.
+    static ref DIRTY_SERVERS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedDocument {
+    document: Document,
+    /// Unix timestamp in milliseconds.
+    last_seen: i64,
+}
+
+/// Only keep the fields we need to render and open a hit, the content of a
+/// document can be huge and we don't search it.
+fn strip_document(doc: &Document) -> Document {
+    Document {
+        id: doc.id.clone(),
+        created: doc.created.clone(),
+        updated: doc.updated.clone(),
+        source: doc.source.clone(),
+        r#type: doc.r#type.clone(),
+        category: doc.category.clone(),
+        subcategory: doc.subcategory.clone(),
+        categories: doc.categories.clone(),
+        title: doc.title.clone(),
+        summary: doc.summary.clone(),
+        icon: doc.icon.clone(),
+        thumbnail: doc.thumbnail.clone(),
+        tags: doc.tags.clone(),
+        url: doc.url.clone(),
+        ..Default::default()
+    }
+}
+
+pub fn is_offline_cache_enabled() -> bool {
+    OFFLINE_CACHE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Load the cached documents from the Tauri store, called upon app start.
+pub fn load_offline_cache<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
+    OFFLINE_CACHE_ENABLED.store(
+        crate::settings::settings().offline_cache_enabled,
+        Ordering::Relaxed,
+    );
+
+    let store = app_handle
+        .store(TAURI_STORE_OFFLINE_DOCUMENT_CACHE)
+        .map_err(|e| e.to_string())?;
+
+    let mut cache = OFFLINE_CACHE.write().unwrap();
+    for (server_id, documents) in store.entries() {
+        match serde_json::from_value::<Vec<CachedDocument>>(documents) {
+            Ok(documents) => {
+                let documents = documents
+                    .into_iter()
+                    .map(|cached| (cached.document.id.clone(), cached))
+                    .collect();
+                cache.insert(server_id, documents);
+            }
+            Err(e) => {
+                log::warn!(
+                    "dropping the offline cache of server [{}] as it cannot be deserialized: {}",
+                    server_id,
+                    e
+                );
+                store.delete(&server_id);
+            }
+        }
+    }
+
+    log::debug!("loaded offline cache of {} servers", cache.len());
+
+    Ok(())
+}
+
+/// Write the cache of the servers in `server_ids` to the store.
+fn persist_server_caches(server_ids: HashSet<String>) {
+    let Some(app_handle) = GLOBAL_TAURI_APP_HANDLE.get() else {
+        return;
+    };
+    let store = match app_handle.store(TAURI_STORE_OFFLINE_DOCUMENT_CACHE) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!(
+                "failed to open store [{}], error [{}]",
+                TAURI_STORE_OFFLINE_DOCUMENT_CACHE,
+                e
+            );
+            return;
+        }
+    };
+
+    let cache = OFFLINE_CACHE.read().unwrap();
+    for server_id in server_ids {
+        // Forgotten meanwhile
+        let Some(documents) = cache.get(&server_id) else {
+            continue;
+        };
+
+        match serde_json::to_value(documents.values().collect::<Vec<_>>()) {
+            Ok(json) => store.set(server_id, json),
+            Err(e) => log::error!(
+                "failed to serialize the offline cache of server [{}]: {}",
+                server_id,
+                e
+            ),
+        }
+    }
+}
+
+/// Write the pending changes to the store, called upon exit.
+pub(crate) fn flush_offline_cache() {
+    let dirty = DIRTY_SERVERS.lock().unwrap().take();
+    if let Some(server_ids) = dirty {
+        persist_server_caches(server_ids);
+    }
+}
+
+/// Schedule writing the cache of `server_id` to the store.
+fn schedule_persist(server_id: &str) {
+    let mut dirty = DIRTY_SERVERS.lock().unwrap();
+    match dirty.as_mut() {
+        Some(server_ids) => {
+            server_ids.insert(server_id.to_string());
+        }
+        None => {
+            *dirty = Some(HashSet::from([server_id.to_string()]));
+            tauri::async_runtime::spawn(async {
+                tokio::time::sleep(PERSIST_DELAY).await;
+                flush_offline_cache();
+            });
+        }
+    }
+}
+
+/// Merge `documents`, seen at `now`, into `cached`, then evict the least
+/// recently seen documents beyond [`MAX_CACHED_DOCUMENTS_PER_SERVER`].
+fn merge_documents<'a>(
+    cached: &mut ServerCache,
+    documents: impl IntoIterator<Item = &'a Document>,
+    now: i64,
+) {
+    for doc in documents {
+        if doc.id.is_empty() {
+            continue;
+        }
+
+        cached.insert(
+            doc.id.clone(),
+            CachedDocument {
+                document: strip_document(doc),
+                last_seen: now,
+            },
+        );
+    }
+
+    if cached.len() > MAX_CACHED_DOCUMENTS_PER_SERVER {
+        let mut last_seen: Vec<i64> = cached.values().map(|c| c.last_seen).collect();
+        last_seen.sort_unstable_by(|a, b| b.cmp(a));
+        let oldest_kept = last_seen[MAX_CACHED_DOCUMENTS_PER_SERVER - 1];
+        cached.retain(|_, c| c.last_seen >= oldest_kept);
+
+        // Documents seen at the same time as the oldest one we keep
+        if cached.len() > MAX_CACHED_DOCUMENTS_PER_SERVER {
+            let mut excess = cached.len() - MAX_CACHED_DOCUMENTS_PER_SERVER;
+            cached.retain(|_, c| {
+                if excess > 0 && c.last_seen == oldest_kept {
+                    excess -= 1;
+                    return false;
+                }
+                true
+            });
+        }
+    }
+}
+
+/// Remember `documents` as seen from the server specified by `server_id`.
+///
+/// No-op if the offline cache is disabled.
+pub fn remember_documents<'a>(server_id: &str, documents: impl IntoIterator<Item = &'a Document>) {
+    if !is_offline_cache_enabled() {
+        return;
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    {
+        let mut cache = OFFLINE_CACHE.write().unwrap();
+        let cached = cache.entry(server_id.to_string()).or_default();
+        merge_documents(cached, documents, now);
+    }
+
+    schedule_persist(server_id);
+}
+
+/// Remove the cached documents of the server specified by `server_id`.
+pub fn forget_server_documents(server_id: &str) {
+    OFFLINE_CACHE.write().unwrap().remove(server_id);
+    if let Some(server_ids) = DIRTY_SERVERS.lock().unwrap().as_mut() {
+        server_ids.remove(server_id);
+    }
+
+    if let Some(app_handle) = GLOBAL_TAURI_APP_HANDLE.get() {
+        if let Ok(store) = app_handle.store(TAURI_STORE_OFFLINE_DOCUMENT_CACHE) {
+            store.delete(server_id);
+        }
+    }
+}
+
+/// If the offline cache is enabled and contains documents of `server`, register
+/// an [`OfflineCacheSearchSource`] in place of the server's search source.
+pub async fn register_offline_fallback<R: Runtime>(app_handle: &AppHandle<R>, server: &Server) {
+    if !is_offline_cache_enabled() || !server.enabled {
+        return;
+    }
+
+    let has_documents = OFFLINE_CACHE
+        .read()
+        .unwrap()
+        .get(&server.id)
+        .is_some_and(|documents| !documents.is_empty());
+    if !has_documents {
+        return;
+    }
+
+    log::debug!(
+        "server [{}] is offline, searching its cached documents instead",
+        server.id
+    );
+
+    let registry = app_handle.state::<SearchSourceRegistry>();
+    registry
+        .register_source(OfflineCacheSearchSource::new(server.clone()))
+        .await;
+}
+
+/// Score `doc` against the lowercased query `terms`, 0 means no match.
+fn score_document(doc: &Document, terms: &[String]) -> f64 {
+    let title = doc.title.as_deref().unwrap_or_default().to_lowercase();
+    let summary = doc.summary.as_deref().unwrap_or_default().to_lowercase();
+    let url = doc.url.as_deref().unwrap_or_default().to_lowercase();
+
+    let mut score = 0.0;
+    for term in terms {
+        let mut matched = false;
+        if title.contains(term.as_str()) {
+            score += 3.0;
+            matched = true;
+        }
+        if summary.contains(term.as_str()) {
+            score += 1.0;
+            matched = true;
+        }
+        if url.contains(term.as_str()) {
+            score += 1.0;
+            matched = true;
+        }
+
+        // Every term has to match, like the server does by default
+        if !matched {
+            return 0.0;
+        }
+    }
+
+    score
+}
+
+/// A read-only search source that searches the cached documents of an offline
+/// server.
+pub struct OfflineCacheSearchSource {
+    server: Server,
+}
+
+impl OfflineCacheSearchSource {
+    pub fn new(server: Server) -> Self {
+        OfflineCacheSearchSource { server }
+    }
+}
+
+#[async_trait]
+impl SearchSource for OfflineCacheSearchSource {
+    fn get_type(&self) -> QuerySource {
+        QuerySource {
+            r#type: COCO_SERVERS_OFFLINE_CACHE.into(),
+            name: format!("{} (cached)", self.server.name),
+            // Use the server's ID so that this source gets replaced once the
+            // server is registered again.
+            id: self.server.id.clone(),
+        }
+    }
+
+    async fn search(&self, query: SearchQuery) -> Result<QueryResponse, SearchError> {
+        let terms: Vec<String> = query
+            .query_strings
+            .get("query")
+            .map(|q| q.split_whitespace().map(|t| t.to_lowercase()).collect())
+            .unwrap_or_default();
+
+        if terms.is_empty() {
+            return Ok(QueryResponse {
+                source: self.get_type(),
+                hits: Vec::new(),
+                total_hits: 0,
+            });
+        }
+
+        let mut hits: Vec<(Document, f64)> = {
+            let cache = OFFLINE_CACHE.read().unwrap();
+            cache
+                .get(&self.server.id)
+                .map(|documents| {
+                    documents
+                        .values()
+                        .filter_map(|cached| {
+                            let score = score_document(&cached.document, &terms);
+                            if score <= 0.0 {
+                                return None;
+                            }
+
+                            let mut doc = cached.document.clone();
+                            let metadata = doc.metadata.get_or_insert_with(HashMap::new);
+                            metadata.insert("cached".into(), JsonValue::Bool(true));
+                            metadata.insert("cached_at".into(), cached.last_seen.into());
+
+                            Some((doc, CACHED_HIT_BASE_SCORE + score))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let total_hits = hits.len();
+        hits.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        let hits = hits
+            .into_iter()
+            .skip(query.from as usize)
+            .take(query.size as usize)
+            .collect();
+
+        Ok(QueryResponse {
+            source: self.get_type(),
+            hits,
+            total_hits,
+        })
+    }
+}
+
+/// Remember a document that the user opened, so that it can be found even if
+/// it never showed up in the search results of this device.
+#[tauri::command]
+pub async fn record_opened_document(server_id: String, document: Document) -> Result<(), String> {
+    if get_server_by_id(&server_id).is_none() {
+        return Err(format!("Server with ID {} not found", server_id));
+    }
+
+    remember_documents(&server_id, std::iter::once(&document));
+
+    Ok(())
+}
+
+/// Clear the offline cache of the server specified by `server_id`, or of all
+/// the servers if it is `None`.
+#[tauri::command]
+pub async fn clear_offline_cache(server_id: Option<String>) -> Result<(), String> {
+    match server_id {
+        Some(id) => forget_server_documents(&id),
+        None => {
+            let server_ids: Vec<String> = OFFLINE_CACHE.read().unwrap().keys().cloned().collect();
+            for id in server_ids {
+                forget_server_documents(&id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Follow the offline cache setting, called upon app start.
+pub(crate) fn watch_settings() {
+    crate::settings::subscribe(|previous, current| {
+        if previous.offline_cache_enabled == current.offline_cache_enabled {
+            return;
+        }
+
+        OFFLINE_CACHE_ENABLED.store(current.offline_cache_enabled, Ordering::Relaxed);
+        // Do not leave the documents on disk once the user turns it off
+        if !current.offline_cache_enabled {
+            tauri::async_runtime::spawn(async {
+                let _ = clear_offline_cache(None).await;
+            });
+        }
+    });
+}
+
+#[test]
+fn test_merge_documents() {
+    let doc = |id: &str, title: &str| Document {
+        id: id.into(),
+        title: Some(title.into()),
+        content: Some("content".into()),
+        ..Default::default()
+    };
+
+    let mut cached = ServerCache::new();
+    merge_documents(&mut cached, &[doc("a", "A"), doc("", "none")], 1);
+    merge_documents(&mut cached, &[doc("a", "A2"), doc("b", "B")], 2);
+    assert_eq!(cached.len(), 2);
+    assert_eq!(cached["a"].last_seen, 2);
+    assert_eq!(cached["a"].document.title.as_deref(), Some("A2"));
+    // The content is not kept
+    assert!(cached["b"].document.content.is_none());
+}
+
+#[test]
+fn test_merge_documents_eviction() {
+    let documents: Vec<Document> = (0..MAX_CACHED_DOCUMENTS_PER_SERVER + 10)
+        .map(|i| Document {
+            id: i.to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+    let mut cached = ServerCache::new();
+    merge_documents(&mut cached, &documents[..20], 1);
+    merge_documents(&mut cached, &documents[20..], 2);
+    assert_eq!(cached.len(), MAX_CACHED_DOCUMENTS_PER_SERVER);
+    // Only the least recently seen documents are evicted
+    assert_eq!(cached.values().filter(|c| c.last_seen == 1).count(), 10);
+
+    // Evicting among documents seen at the same time
+    merge_documents(&mut cached, &documents, 3);
+    assert_eq!(cached.len(), MAX_CACHED_DOCUMENTS_PER_SERVER);
+    assert!(cached.values().all(|c| c.last_seen == 3));
+}
@@ -1,6 +1,9 @@
 use crate::common::http::get_response_body_text;
 use crate::common::profile::UserProfile;
+use crate::common::server::Server;
 use crate::server::http_client::HttpClient;
+use reqwest::Method;
+use std::collections::HashMap;
 use tauri::{AppHandle, Runtime};
 
 #[tauri::command]
@@ -13,6 +16,47 @@ pub async fn get_user_profiles<R: Runtime>(
         .await
         .map_err(|e| format!("Error fetching profile: {}", e))?;
 
+    parse_profile_response(response).await
+}
+
+/// Fetch the profile of the user of `access_token` from `server`, without
+/// using or changing the stored token, e.g., to validate a new token.
+pub(crate) async fn get_user_profile_with_token(
+    server: &Server,
+    access_token: &str,
+) -> Result<UserProfile, String> {
+    let url = HttpClient::join_url(&server.endpoint, "/account/profile");
+    let headers = HashMap::from([("X-API-TOKEN".to_string(), access_token.to_string())]);
+
+    let response = HttpClient::send_raw_request_with_settings(
+        server.tls.as_ref(),
+        server.proxy.as_ref(),
+        Method::GET,
+        &url,
+        None,
+        Some(headers),
+        None,
+    )
+    .await
+    .map_err(|e| format!("Error fetching profile: {}", e))?;
+
+    parse_profile_response(response).await
+}
+
+async fn parse_profile_response(response: reqwest::Response) -> Result<UserProfile, String> {
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED => {
+            return Err("the access token is invalid, expired or has been revoked".to_string())
+        }
+        reqwest::StatusCode::FORBIDDEN => {
+            return Err(
+                "the access token does not have the permission to read the user profile"
+                    .to_string(),
+            )
+        }
+        _ => {}
+    }
+
     // Use get_response_body_text to extract the body content
     let response_body = get_response_body_text(response)
         .await
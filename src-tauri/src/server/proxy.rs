@@ -0,0 +1,335 @@
+//! Proxy settings, at global and per-server level.
+//!
+//! They are honoured by [`HttpClient`](crate::server::http_client::HttpClient),
+//! the WebSocket connections and attachment uploads. reqwest handles proxies
+//! on its own, for the WebSocket connections, we tunnel through the proxy
+//! ourselves, see [`connect_tcp()`].
+
+use crate::common::server::{ProxyConfig, ProxyMode};
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+/// Return the global proxy settings.
+pub(crate) fn global_proxy_config() -> ProxyConfig {
+    crate::settings::settings().proxy
+}
+
+/// Return the proxy settings to use for a server whose own settings are
+/// `server_proxy`, the per-server settings take precedence over the global ones.
+pub(crate) fn effective_proxy_config(server_proxy: Option<&ProxyConfig>) -> ProxyConfig {
+    match server_proxy {
+        Some(proxy) => proxy.clone(),
+        None => global_proxy_config(),
+    }
+}
+
+fn parse_proxy_url(url: &str) -> Result<Url, String> {
+    let parsed =
+        Url::parse(url.trim()).map_err(|e| format!("invalid proxy URL [{}]: {}", url, e))?;
+
+    match parsed.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => {}
+        scheme => {
+            return Err(format!(
+                "unsupported proxy scheme [{}], only http, https and socks5 are supported",
+                scheme
+            ))
+        }
+    }
+    if parsed.host_str().is_none() {
+        return Err(format!("proxy URL [{}] has no host", url));
+    }
+
+    Ok(parsed)
+}
+
+/// Check that `proxy` can be applied, so that invalid settings are rejected
+/// when they are saved rather than when they are used.
+pub(crate) fn validate_proxy_config(proxy: &ProxyConfig) -> Result<(), String> {
+    if proxy.mode != ProxyMode::Manual {
+        return Ok(());
+    }
+
+    let urls = [&proxy.http_proxy, &proxy.https_proxy, &proxy.socks5_proxy];
+    if urls.iter().all(|url| url.is_none()) {
+        return Err("at least one proxy should be set in manual mode".into());
+    }
+    for url in urls.into_iter().flatten() {
+        parse_proxy_url(url)?;
+    }
+    if let Some(url) = &proxy.socks5_proxy {
+        if !parse_proxy_url(url)?.scheme().starts_with("socks5") {
+            return Err(format!("[{}] is not a SOCKS5 proxy", url));
+        }
+    }
+    if proxy.username.is_some() != proxy.password.is_some() {
+        return Err("proxy username and password should be set together".into());
+    }
+
+    apply_to_reqwest(reqwest::Client::builder(), proxy).map(|_| ())
+}
+
+/// Apply `proxy` to a reqwest client builder.
+pub(crate) fn apply_to_reqwest(
+    mut builder: ClientBuilder,
+    proxy: &ProxyConfig,
+) -> Result<ClientBuilder, String> {
+    match proxy.mode {
+        // reqwest picks up the proxy environment variables by default
+        ProxyMode::System => Ok(builder),
+        ProxyMode::None => Ok(builder.no_proxy()),
+        ProxyMode::Manual => {
+            let no_proxy = NoProxy::from_string(&proxy.no_proxy.join(","));
+            let with_auth = |mut p: Proxy| {
+                if let (Some(username), Some(password)) = (&proxy.username, &proxy.password) {
+                    p = p.basic_auth(username, password);
+                }
+                p.no_proxy(no_proxy.clone())
+            };
+
+            if let Some(url) = &proxy.http_proxy {
+                let p = Proxy::http(url.trim()).map_err(|e| e.to_string())?;
+                builder = builder.proxy(with_auth(p));
+            }
+            if let Some(url) = &proxy.https_proxy {
+                let p = Proxy::https(url.trim()).map_err(|e| e.to_string())?;
+                builder = builder.proxy(with_auth(p));
+            }
+            // The SOCKS5 proxy handles everything the others don't
+            if let Some(url) = &proxy.socks5_proxy {
+                let p = Proxy::all(url.trim()).map_err(|e| e.to_string())?;
+                builder = builder.proxy(with_auth(p));
+            }
+
+            Ok(builder)
+        }
+    }
+}
+
+/// Whether `host` matches an entry of a no-proxy list, using the same rules as
+/// the `NO_PROXY` environment variable: `*` matches everything, and a domain
+/// matches itself and its subdomains.
+fn bypasses_proxy(host: &str, no_proxy: &[String]) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().to_lowercase();
+        if entry.is_empty() {
+            return false;
+        }
+        if entry == "*" {
+            return true;
+        }
+
+        let domain = entry.trim_start_matches('.');
+        host == domain || host.ends_with(&format!(".{}", domain))
+    })
+}
+
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// The proxy to go through to reach `target`, `None` means a direct connection.
+fn proxy_for(proxy: &ProxyConfig, target: &Url) -> Result<Option<Url>, String> {
+    let host = target.host_str().ok_or("No host found in URL")?;
+    let secure = matches!(target.scheme(), "https" | "wss");
+
+    let proxy_url = match proxy.mode {
+        ProxyMode::None => return Ok(None),
+        ProxyMode::System => {
+            let no_proxy: Vec<String> = env_var(&["NO_PROXY", "no_proxy"])
+                .map(|list| list.split(',').map(str::to_string).collect())
+                .unwrap_or_default();
+            if bypasses_proxy(host, &no_proxy) {
+                return Ok(None);
+            }
+
+            let scheme_proxy = if secure {
+                env_var(&["HTTPS_PROXY", "https_proxy"])
+            } else {
+                env_var(&["HTTP_PROXY", "http_proxy"])
+            };
+            scheme_proxy.or_else(|| env_var(&["ALL_PROXY", "all_proxy"]))
+        }
+        ProxyMode::Manual => {
+            if bypasses_proxy(host, &proxy.no_proxy) {
+                return Ok(None);
+            }
+
+            let scheme_proxy = if secure {
+                proxy.https_proxy.clone()
+            } else {
+                proxy.http_proxy.clone()
+            };
+            scheme_proxy.or_else(|| proxy.socks5_proxy.clone())
+        }
+    };
+
+    proxy_url.map(|url| parse_proxy_url(&url)).transpose()
+}
+
+/// Credentials of `proxy_url`, the ones in the settings take precedence over
+/// the ones embedded in the URL.
+fn proxy_credentials(proxy: &ProxyConfig, proxy_url: &Url) -> Option<(String, String)> {
+    if let (Some(username), Some(password)) = (&proxy.username, &proxy.password) {
+        return Some((username.clone(), password.clone()));
+    }
+    if proxy_url.username().is_empty() {
+        return None;
+    }
+
+    Some((
+        proxy_url.username().to_string(),
+        proxy_url.password().unwrap_or_default().to_string(),
+    ))
+}
+
+/// Open a TCP connection to `target`, through the proxy of `proxy` if there
+/// is one for it.
+pub(crate) async fn connect_tcp(proxy: &ProxyConfig, target: &Url) -> Result<TcpStream, String> {
+    let host = target.host_str().ok_or("No host found in URL")?;
+    let port = target
+        .port_or_known_default()
+        .ok_or("No port found in URL")?;
+
+    let Some(proxy_url) = proxy_for(proxy, target)? else {
+        return TcpStream::connect((host, port))
+            .await
+            .map_err(|e| format!("Failed to connect to {}:{}: {}", host, port, e));
+    };
+
+    let proxy_host = proxy_url
+        .host_str()
+        .expect("validated by parse_proxy_url()");
+    let proxy_port =
+        proxy_url
+            .port_or_known_default()
+            .unwrap_or(if proxy_url.scheme().starts_with("socks5") {
+                1080
+            } else {
+                80
+            });
+    let credentials = proxy_credentials(proxy, &proxy_url);
+
+    log::debug!(
+        "connecting to {}:{} through proxy {}://{}:{}",
+        host,
+        port,
+        proxy_url.scheme(),
+        proxy_host,
+        proxy_port
+    );
+
+    match proxy_url.scheme() {
+        "socks5" | "socks5h" => {
+            let stream = match credentials {
+                Some((username, password)) => {
+                    tokio_socks::tcp::Socks5Stream::connect_with_password(
+                        (proxy_host, proxy_port),
+                        (host, port),
+                        &username,
+                        &password,
+                    )
+                    .await
+                }
+                None => {
+                    tokio_socks::tcp::Socks5Stream::connect((proxy_host, proxy_port), (host, port))
+                        .await
+                }
+            }
+            .map_err(|e| format!("SOCKS5 proxy error: {}", e))?;
+
+            Ok(stream.into_inner())
+        }
+        "http" => {
+            let mut stream = TcpStream::connect((proxy_host, proxy_port))
+                .await
+                .map_err(|e| {
+                    format!(
+                        "Failed to connect to proxy {}:{}: {}",
+                        proxy_host, proxy_port, e
+                    )
+                })?;
+            http_connect(&mut stream, host, port, credentials).await?;
+
+            Ok(stream)
+        }
+        _ => Err(format!(
+            "{} proxies are not supported for WebSocket connections, use an HTTP or SOCKS5 proxy",
+            proxy_url.scheme()
+        )),
+    }
+}
+
+/// Establish a tunnel to `host:port` through the HTTP proxy `stream` is
+/// connected to.
+async fn http_connect(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(String, String)>,
+) -> Result<(), String> {
+    let mut request = format!(
+        "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n",
+        host = host,
+        port = port
+    );
+    if let Some((username, password)) = credentials {
+        let encoded = base64::encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", encoded));
+    }
+    request.push_str("\r\n");
+
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| format!("HTTP proxy error: {}", e))?;
+
+    // Read the response header, the proxy sends nothing else before the
+    // tunnel is established.
+    let mut response = Vec::new();
+    let mut buf = [0_u8; 512];
+    while !response.ends_with(b"\r\n\r\n") {
+        let n = stream
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("HTTP proxy error: {}", e))?;
+        if n == 0 {
+            return Err("HTTP proxy closed the connection".into());
+        }
+        response.extend_from_slice(&buf[..n]);
+        if response.len() > 8192 {
+            return Err("HTTP proxy response header is too large".into());
+        }
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(format!("HTTP proxy refused to connect: {}", status_line));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bypasses_proxy() {
+    let no_proxy = vec!["localhost".to_string(), ".corp.example.com".to_string()];
+
+    assert!(bypasses_proxy("localhost", &no_proxy));
+    assert!(bypasses_proxy("coco.corp.example.com", &no_proxy));
+    assert!(bypasses_proxy("corp.example.com", &no_proxy));
+    assert!(!bypasses_proxy("example.com", &no_proxy));
+    assert!(!bypasses_proxy("notlocalhost", &no_proxy));
+    assert!(bypasses_proxy("anything", &["*".to_string()]));
+}
@@ -0,0 +1,136 @@
+//! Retries of the requests sent by [`HttpClient`](crate::server::http_client::HttpClient).
+//!
+//! Only idempotent requests whose body can be replayed are retried, after a
+//! transport error or a status that says the server is temporarily unable to
+//! handle it. The delay between two attempts grows exponentially and is
+//! randomized ("full jitter") so that clients don't retry in lockstep, unless
+//! the server tells us how long to wait with `Retry-After`.
+
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Method, Response, StatusCode};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    /// Including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// We give up if the server asks us to wait longer than this.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub(crate) fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Upper bound of the delay before retry `attempt` (starting from 1).
+    fn backoff_ceiling(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        self.base_delay
+            .saturating_mul(2_u32.pow(exponent))
+            .min(self.max_delay)
+    }
+
+    /// Delay before retry `attempt` (starting from 1), randomized in
+    /// `[0, backoff_ceiling]`.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.backoff_ceiling(attempt).as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling))
+    }
+
+    /// Delay before retrying after `response`, `None` means it should not be
+    /// retried.
+    pub(crate) fn delay_after_response(
+        &self,
+        response: &Response,
+        attempt: u32,
+    ) -> Option<Duration> {
+        if !is_retryable_status(response.status()) {
+            return None;
+        }
+
+        match response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after)
+        {
+            Some(retry_after) if retry_after > self.max_retry_after => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+}
+
+/// Requests that can be sent more than once without changing the outcome.
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+/// Statuses telling that the server may be able to handle the request later.
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Whether the request may succeed if sent again after failing with `error`.
+pub(crate) fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request()
+}
+
+/// Parse the value of a `Retry-After` header, which is either a number of
+/// seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+
+    // A date in the past means "now"
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[test]
+fn test_retry_delays() {
+    assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon"), None);
+
+    let policy = RetryPolicy::default();
+    assert_eq!(policy.backoff_ceiling(1), Duration::from_millis(200));
+    assert_eq!(policy.backoff_ceiling(3), Duration::from_millis(800));
+    assert_eq!(policy.backoff_ceiling(30), policy.max_delay);
+    assert!(policy.backoff(2) <= Duration::from_millis(400));
+}
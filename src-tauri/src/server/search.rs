@@ -5,6 +5,7 @@ use crate::common::search::{QueryHits, QueryResponse, QuerySource, SearchQuery,
 use crate::common::server::Server;
 use crate::common::traits::SearchSource;
 use crate::server::http_client::HttpClient;
+use crate::server::offline_cache;
 use async_trait::async_trait;
 // use futures::stream::StreamExt;
 use ordered_float::OrderedFloat;
@@ -69,7 +70,7 @@ impl DocumentsSizedCollector {
     }
 }
 
-const COCO_SERVERS: &str = "coco-servers";
+pub(crate) const COCO_SERVERS: &str = "coco-servers";
 
 pub struct CocoSearchSource {
     server: Server,
@@ -131,6 +132,8 @@ impl SearchSource for CocoSearchSource {
                 .into_iter()
                 .map(|hit| (hit._source, hit._score.unwrap_or(0.0))) // Default _score to 0.0 if None
                 .collect();
+
+            offline_cache::remember_documents(&self.server.id, hits.iter().map(|(doc, _)| doc));
         }
 
 
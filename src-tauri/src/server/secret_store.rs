@@ -0,0 +1,326 @@
+//! Storage of secrets, e.g., the access tokens of Coco servers.
+//!
+//! Secrets are stored in the platform secret storage (Keychain, Credential
+//! Manager or Secret Service) when it is available. Otherwise, they are kept in
+//! a file encrypted with AES-256-GCM, using a key generated on this device and
+//! only readable by the current user.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tauri::{AppHandle, Manager, Runtime};
+
+/// Service name of our entries in the platform secret storage.
+const KEYRING_SERVICE: &str = "rs.coco.app";
+/// Entry used to check whether the platform secret storage works.
+const KEYRING_PROBE_ENTRY: &str = "coco-secret-store-probe";
+
+const ENCRYPTED_SECRETS_FILE: &str = "secrets.enc";
+const ENCRYPTED_SECRETS_KEY_FILE: &str = "secrets.key";
+
+static SECRET_STORE: OnceLock<Box<dyn SecretStore>> = OnceLock::new();
+
+pub(crate) trait SecretStore: Send + Sync {
+    /// Name of the backend, for logging purposes.
+    fn name(&self) -> &'static str;
+    fn get(&self, key: &str) -> Result<Option<String>, String>;
+    fn set(&self, key: &str, value: &str) -> Result<(), String>;
+    /// Deleting a secret that does not exist is not an error.
+    fn delete(&self, key: &str) -> Result<(), String>;
+}
+
+/// Return the secret store, it is not available if [`init_secret_store()`]
+/// has not been called or failed.
+pub(crate) fn secret_store() -> Result<&'static dyn SecretStore, String> {
+    SECRET_STORE
+        .get()
+        .map(|store| store.as_ref())
+        .ok_or_else(|| "secret store is not available".to_string())
+}
+
+/// Pick the backend of the secret store, called upon app start.
+pub(crate) fn init_secret_store<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
+    let store: Box<dyn SecretStore> = match KeyringSecretStore::probe() {
+        Ok(store) => Box::new(store),
+        Err(e) => {
+            log::warn!(
+                "platform secret storage is unavailable, using an encrypted file instead: {}",
+                e
+            );
+            let dir = app_handle
+                .path()
+                .app_data_dir()
+                .map_err(|e| format!("Failed to get the app data directory: {}", e))?;
+            Box::new(EncryptedFileSecretStore::open(&dir)?)
+        }
+    };
+
+    log::debug!("storing secrets in the {}", store.name());
+    SECRET_STORE
+        .set(store)
+        .map_err(|_| "secret store already initialized".to_string())
+}
+
+struct KeyringSecretStore;
+
+impl KeyringSecretStore {
+    fn entry(key: &str) -> Result<keyring::Entry, String> {
+        keyring::Entry::new(KEYRING_SERVICE, key).map_err(|e| e.to_string())
+    }
+
+    /// Return the store if the platform secret storage can be used.
+    fn probe() -> Result<Self, String> {
+        match Self::entry(KEYRING_PROBE_ENTRY)?.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(Self),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl SecretStore for KeyringSecretStore {
+    fn name(&self) -> &'static str {
+        "platform secret storage"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        match Self::entry(key)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(format!("Failed to read secret [{}]: {}", key, e)),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        Self::entry(key)?
+            .set_password(value)
+            .map_err(|e| format!("Failed to write secret [{}]: {}", key, e))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        match Self::entry(key)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to delete secret [{}]: {}", key, e)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedSecrets {
+    /// Base64 encoded.
+    nonce: String,
+    /// Base64 encoded.
+    ciphertext: String,
+}
+
+struct EncryptedFileSecretStore {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    secrets: Mutex<HashMap<String, String>>,
+}
+
+/// Write `contents` to `path` so that only the current user can read it.
+pub(crate) fn write_private_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, contents)
+        .map_err(|e| format!("Failed to write [{}]: {}", tmp_path.display(), e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to restrict [{}]: {}", tmp_path.display(), e))?;
+    }
+
+    // Rename so that a crash never leaves a truncated file behind
+    std::fs::rename(&tmp_path, path)
+        .map_err(|e| format!("Failed to write [{}]: {}", path.display(), e))
+}
+
+impl EncryptedFileSecretStore {
+    fn open(dir: &Path) -> Result<Self, String> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create [{}]: {}", dir.display(), e))?;
+
+        let key_path = dir.join(ENCRYPTED_SECRETS_KEY_FILE);
+        let key = if key_path.exists() {
+            let encoded = std::fs::read_to_string(&key_path)
+                .map_err(|e| format!("Failed to read [{}]: {}", key_path.display(), e))?;
+            let key = base64::decode(encoded.trim())
+                .map_err(|e| format!("Invalid secret store key: {}", e))?;
+            if key.len() != 32 {
+                return Err("Invalid secret store key: it should be 32 bytes long".into());
+            }
+            key
+        } else {
+            let key = Aes256Gcm::generate_key(OsRng).to_vec();
+            write_private_file(&key_path, base64::encode(&key).as_bytes())?;
+            key
+        };
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+
+        let path = dir.join(ENCRYPTED_SECRETS_FILE);
+        let secrets = if path.exists() {
+            Self::decrypt(&cipher, &path).unwrap_or_else(|e| {
+                // Keep the file around, but don't prevent the app from starting,
+                // the user will have to log in again.
+                log::error!("discarding the stored secrets: {}", e);
+                let _ = std::fs::rename(&path, path.with_extension("enc.bak"));
+                HashMap::new()
+            })
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            path,
+            cipher,
+            secrets: Mutex::new(secrets),
+        })
+    }
+
+    fn decrypt(cipher: &Aes256Gcm, path: &Path) -> Result<HashMap<String, String>, String> {
+        let contents = std::fs::read(path)
+            .map_err(|e| format!("Failed to read [{}]: {}", path.display(), e))?;
+        let encrypted: EncryptedSecrets = serde_json::from_slice(&contents)
+            .map_err(|e| format!("Invalid encrypted secrets file: {}", e))?;
+
+        let nonce = base64::decode(&encrypted.nonce)
+            .map_err(|e| format!("Invalid encrypted secrets file: {}", e))?;
+        if nonce.len() != 12 {
+            return Err("Invalid encrypted secrets file: bad nonce".into());
+        }
+        let ciphertext = base64::decode(&encrypted.ciphertext)
+            .map_err(|e| format!("Invalid encrypted secrets file: {}", e))?;
+
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| "Failed to decrypt the secrets, the key may have changed".to_string())?;
+
+        serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid decrypted secrets: {}", e))
+    }
+
+    fn persist(&self, secrets: &HashMap<String, String>) -> Result<(), String> {
+        let plaintext = serde_json::to_vec(secrets).expect("Failed to serialize secrets");
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_ref())
+            .map_err(|_| "Failed to encrypt the secrets".to_string())?;
+
+        let encrypted = EncryptedSecrets {
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        };
+        let contents = serde_json::to_vec(&encrypted).expect("Failed to serialize secrets");
+
+        write_private_file(&self.path, &contents)
+    }
+}
+
+impl SecretStore for EncryptedFileSecretStore {
+    fn name(&self) -> &'static str {
+        "encrypted secrets file"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.secrets.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        let mut secrets = self.secrets.lock().unwrap();
+        if secrets.get(key).map(String::as_str) == Some(value) {
+            return Ok(());
+        }
+
+        secrets.insert(key.to_string(), value.to_string());
+        self.persist(&secrets)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let mut secrets = self.secrets.lock().unwrap();
+        if secrets.remove(key).is_none() {
+            return Ok(());
+        }
+
+        self.persist(&secrets)
+    }
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "coco-{}-{}",
+        name,
+        crate::server::auth::random_string(8)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_encrypted_file_secret_store() {
+    let dir = test_dir("secret-store");
+
+    let store = EncryptedFileSecretStore::open(&dir).unwrap();
+    store.set("a", "secret a").unwrap();
+    store.set("b", "secret b").unwrap();
+    store.delete("b").unwrap();
+    store.delete("missing").unwrap();
+
+    let contents = std::fs::read_to_string(dir.join(ENCRYPTED_SECRETS_FILE)).unwrap();
+    assert!(!contents.contains("secret a"));
+
+    let store = EncryptedFileSecretStore::open(&dir).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("secret a"));
+    assert_eq!(store.get("b").unwrap(), None);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_encrypted_file_secret_store_wrong_key() {
+    let dir = test_dir("secret-store-wrong-key");
+
+    let store = EncryptedFileSecretStore::open(&dir).unwrap();
+    store.set("a", "secret a").unwrap();
+
+    let other_key = Aes256Gcm::generate_key(OsRng);
+    let other_cipher = Aes256Gcm::new(&other_key);
+    let path = dir.join(ENCRYPTED_SECRETS_FILE);
+    assert!(EncryptedFileSecretStore::decrypt(&other_cipher, &path).is_err());
+
+    // The secrets are discarded, but kept aside
+    write_private_file(
+        &dir.join(ENCRYPTED_SECRETS_KEY_FILE),
+        base64::encode(other_key).as_bytes(),
+    )
+    .unwrap();
+    let store = EncryptedFileSecretStore::open(&dir).unwrap();
+    assert_eq!(store.get("a").unwrap(), None);
+    assert!(path.with_extension("enc.bak").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_encrypted_file_secret_store_tampered() {
+    let dir = test_dir("secret-store-tampered");
+
+    let store = EncryptedFileSecretStore::open(&dir).unwrap();
+    store.set("a", "secret a").unwrap();
+
+    let path = dir.join(ENCRYPTED_SECRETS_FILE);
+    let mut encrypted: EncryptedSecrets =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    let mut ciphertext = base64::decode(&encrypted.ciphertext).unwrap();
+    ciphertext[0] ^= 1;
+    encrypted.ciphertext = base64::encode(ciphertext);
+    std::fs::write(&path, serde_json::to_vec(&encrypted).unwrap()).unwrap();
+
+    assert!(EncryptedFileSecretStore::decrypt(&store.cipher, &path).is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
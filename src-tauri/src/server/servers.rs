@@ -1,10 +1,19 @@
 use crate::common::http::get_response_body_text;
 use crate::common::register::SearchSourceRegistry;
-use crate::common::server::{AuthProvider, Provider, Server, ServerAccessToken, Sso, Version};
+use crate::common::server::{
+    AuthProvider, Provider, ProxyConfig, Server, ServerAccessToken, ServerTimeouts,
+    ServerTlsConfig, Sso, TimeoutProfile, Version, DEFAULT_ACCOUNT_ID,
+};
+use crate::policy::{self, BuiltinServerPolicy};
+use crate::server::circuit_breaker;
 use crate::server::connector::fetch_connectors_by_server;
 use crate::server::datasource::datasource_search;
-use crate::server::http_client::HttpClient;
+use crate::server::health_monitor;
+use crate::server::http_client::{invalidate_server_http_client, HttpClient};
+use crate::server::offline_cache;
 use crate::server::search::CocoSearchSource;
+use crate::server::secret_store::{secret_store, SecretStore};
+use crate::server::{proxy, tls};
 use crate::COCO_TAURI_STORE;
 use lazy_static::lazy_static;
 use reqwest::Method;
@@ -14,14 +23,15 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::RwLock;
 use tauri::Runtime;
-use tauri::{AppHandle, Manager};
+use tauri::{AppHandle, Emitter, Manager};
 use tauri_plugin_store::StoreExt;
 // Assuming you're using serde_json
 
 lazy_static! {
     static ref SERVER_CACHE: Arc<RwLock<HashMap<String, Server>>> =
         Arc::new(RwLock::new(HashMap::new()));
-    static ref SERVER_TOKEN: Arc<RwLock<HashMap<String, ServerAccessToken>>> =
+    /// Tokens by server ID, then by account ID.
+    static ref SERVER_TOKEN: Arc<RwLock<HashMap<String, HashMap<String, ServerAccessToken>>>> =
         Arc::new(RwLock::new(HashMap::new()));
 }
 
@@ -36,16 +46,45 @@ pub fn get_server_by_id(id: &str) -> Option<Server> {
     cache.get(id).cloned()
 }
 
+/// ID of the active account of the server specified by `server_id`.
+pub(crate) fn active_account_id(server_id: &str) -> String {
+    get_server_by_id(server_id)
+        .map(|server| server.active_account_id().to_string())
+        .unwrap_or_else(|| DEFAULT_ACCOUNT_ID.to_string())
+}
+
+/// Return the token of the active account of the server specified by `id`.
 #[tauri::command]
 pub async fn get_server_token(id: &str) -> Result<Option<ServerAccessToken>, String> {
+    get_account_token(id, &active_account_id(id))
+}
+
+pub fn get_account_token(
+    server_id: &str,
+    account_id: &str,
+) -> Result<Option<ServerAccessToken>, String> {
     let cache = SERVER_TOKEN.read().map_err(|err| err.to_string())?;
 
-    Ok(cache.get(id).cloned())
+    Ok(cache
+        .get(server_id)
+        .and_then(|tokens| tokens.get(account_id))
+        .cloned())
 }
 
+/// Save the token of the active account of the server specified by
+/// `server_id`.
 pub fn save_access_token(server_id: String, token: ServerAccessToken) -> bool {
+    let account_id = active_account_id(&server_id);
+    save_account_token(server_id, account_id, token)
+}
+
+pub fn save_account_token(server_id: String, account_id: String, token: ServerAccessToken) -> bool {
     let mut cache = SERVER_TOKEN.write().unwrap();
-    cache.insert(server_id, token).is_none()
+    cache
+        .entry(server_id)
+        .or_default()
+        .insert(account_id, token)
+        .is_none()
 }
 
 fn check_endpoint_exists(endpoint: &str) -> bool {
@@ -58,24 +97,138 @@ pub fn save_server(server: &Server) -> bool {
     cache.insert(server.id.clone(), server.clone()).is_none() // If the server id did not exist, `insert` will return `None`
 }
 
-fn remove_server_by_id(id: String) -> bool {
+pub(crate) fn remove_server_by_id(id: String) -> bool {
     log::debug!("remove server by id: {}", &id);
     let mut cache = SERVER_CACHE.write().unwrap();
     let deleted = cache.remove(id.as_str());
     deleted.is_some()
 }
 
-pub async fn persist_servers<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
-    let cache = SERVER_CACHE.read().unwrap(); // Acquire a read lock, not a write lock, since you're not modifying the cache
+/// A secret of a server, kept in the secret store rather than with the server.
+struct ServerSecret {
+    /// Field of the server holding the secret.
+    object: &'static str,
+    /// Field of `object` holding the secret, the store key is saved under
+    /// `<field>_secret` instead.
+    field: &'static str,
+    get: fn(&Server) -> Option<&str>,
+}
+
+fn tls_client_key(server: &Server) -> Option<&str> {
+    server.tls.as_ref()?.client_key.as_deref()
+}
+
+fn proxy_password(server: &Server) -> Option<&str> {
+    server.proxy.as_ref()?.password.as_deref()
+}
+
+const SERVER_SECRETS: &[ServerSecret] = &[
+    ServerSecret {
+        object: "tls",
+        field: "client_key",
+        get: tls_client_key,
+    },
+    ServerSecret {
+        object: "proxy",
+        field: "password",
+        get: proxy_password,
+    },
+];
+
+/// Key of `secret` of the server specified by `server_id` in the secret store.
+fn server_secret_key(secret: &ServerSecret, server_id: &str) -> String {
+    format!("server-{}.{}:{}", secret.object, secret.field, server_id)
+}
+
+/// Write `value` under `key` to the secret store, or delete it if `None`.
+/// Return whether it is stored.
+fn store_secret(key: &str, value: Option<&str>) -> Result<bool, String> {
+    let secret_store = secret_store();
+
+    match value {
+        Some(value) => {
+            let secret_store = secret_store?;
+            if secret_store.get(key)?.as_deref() != Some(value) {
+                secret_store.set(key, value)?;
+            }
+            Ok(true)
+        }
+        None => {
+            if let Ok(secret_store) = secret_store {
+                secret_store.delete(key)?;
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// `server` as stored, its secrets are moved to the secret store. A secret
+/// that cannot be stored is only kept in memory.
+fn stored_server(server: &Server) -> JsonValue {
+    let mut value = serde_json::to_value(server).expect("Failed to serialize server");
+
+    for secret in SERVER_SECRETS {
+        let key = server_secret_key(secret, &server.id);
+        match store_secret(&key, (secret.get)(server)) {
+            Ok(true) => value[secret.object][format!("{}_secret", secret.field)] = key.into(),
+            Ok(false) => {}
+            Err(e) => log::error!(
+                "failed to store the {}.{} of server [{}]: {}",
+                secret.object,
+                secret.field,
+                server.id,
+                e
+            ),
+        }
+    }
+
+    value
+}
 
-    // Convert HashMap to Vec for serialization (iterating over values of HashMap)
-    let servers: Vec<Server> = cache.values().cloned().collect();
+/// Read the secrets of the stored server `value` from the secret store. Older
+/// versions stored them in plain text, they are moved upon the next write.
+fn load_server_secrets(value: &mut JsonValue) {
+    for secret in SERVER_SECRETS {
+        let Some(object) = value
+            .get_mut(secret.object)
+            .and_then(|object| object.as_object_mut())
+        else {
+            continue;
+        };
+        let Some(JsonValue::String(key)) = object.remove(&format!("{}_secret", secret.field))
+        else {
+            continue;
+        };
+
+        match secret_store().and_then(|store| store.get(&key)) {
+            Ok(Some(value)) => {
+                object.insert(secret.field.to_string(), value.into());
+            }
+            Ok(None) => log::warn!("secret [{}] is missing from the secret store", key),
+            Err(e) => log::error!("failed to read secret [{}]: {}", key, e),
+        }
+    }
+}
+
+/// Delete the secrets of the server specified by `server_id`.
+fn remove_server_secrets(server_id: &str) {
+    let Ok(store) = secret_store() else {
+        return;
+    };
+
+    for secret in SERVER_SECRETS {
+        let key = server_secret_key(secret, server_id);
+        if let Err(e) = store.delete(&key) {
+            log::error!("failed to delete secret [{}]: {}", key, e);
+        }
+    }
+}
 
-    // Serialize the servers into JSON automatically
-    let json_servers: Vec<JsonValue> = servers
-        .into_iter()
-        .map(|server| serde_json::to_value(server).expect("Failed to serialize server")) // Automatically serialize all fields
-        .collect();
+pub async fn persist_servers<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
+    let servers: Vec<Server> = SERVER_CACHE.read().unwrap().values().cloned().collect();
+
+    // Not holding the lock, the platform secret storage may be slow
+    let json_servers: Vec<JsonValue> = servers.iter().map(stored_server).collect();
 
     // Save the serialized servers to Tauri's store
     app_handle
@@ -86,39 +239,86 @@ pub async fn persist_servers<R: Runtime>(app_handle: &AppHandle<R>) -> Result<()
     Ok(())
 }
 
+/// Key of the token of an account of the server specified by `server_id` in
+/// the secret store.
+fn token_secret_key(server_id: &str, account_id: &str) -> String {
+    if account_id == DEFAULT_ACCOUNT_ID {
+        format!("server-token:{}", server_id)
+    } else {
+        format!("server-token:{}:{}", server_id, account_id)
+    }
+}
+
+/// Remove the token of the active account of the server specified by `id`.
 pub fn remove_server_token(id: &str) -> bool {
-    log::debug!("remove server token by id: {}", &id);
-    let mut cache = SERVER_TOKEN.write().unwrap();
-    cache.remove(id).is_some()
+    remove_account_token(id, &active_account_id(id))
 }
 
-pub fn persist_servers_token<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
-    let cache = SERVER_TOKEN.read().unwrap(); // Acquire a read lock, not a write lock, since you're not modifying the cache
+pub fn remove_account_token(server_id: &str, account_id: &str) -> bool {
+    log::debug!(
+        "remove the token of account [{}] of server [{}]",
+        account_id,
+        server_id
+    );
+    let removed = SERVER_TOKEN
+        .write()
+        .unwrap()
+        .get_mut(server_id)
+        .and_then(|tokens| tokens.remove(account_id))
+        .is_some();
+
+    // Not holding the lock, the platform secret storage may be slow
+    let key = token_secret_key(server_id, account_id);
+    if let Err(e) = secret_store().and_then(|store| store.delete(&key)) {
+        log::error!(
+            "failed to delete the stored token of server [{}]: {}",
+            server_id,
+            e
+        );
+    }
 
-    // Convert HashMap to Vec for serialization (iterating over values of HashMap)
-    let servers: Vec<ServerAccessToken> = cache.values().cloned().collect();
+    removed
+}
 
-    // Serialize the servers into JSON automatically
-    let json_servers: Vec<JsonValue> = servers
-        .into_iter()
-        .map(|server| serde_json::to_value(server).expect("Failed to serialize access_tokens")) // Automatically serialize all fields
-        .collect();
+/// Remove the tokens of all the accounts of the server specified by `id`.
+pub fn remove_server_tokens(id: &str) {
+    let account_ids: Vec<String> = SERVER_TOKEN
+        .read()
+        .unwrap()
+        .get(id)
+        .map(|tokens| tokens.keys().cloned().collect())
+        .unwrap_or_default();
+
+    for account_id in account_ids {
+        remove_account_token(id, &account_id);
+    }
+    SERVER_TOKEN.write().unwrap().remove(id);
+}
 
-    log::debug!("persist servers token: {:?}", &json_servers);
+/// Write the tokens to the secret store, they are never stored in plain text.
+pub fn persist_servers_token<R: Runtime>(_app_handle: &AppHandle<R>) -> Result<(), String> {
+    let store = secret_store()?;
+    let cache = SERVER_TOKEN.read().unwrap(); // Acquire a read lock, not a write lock, since you're not modifying the cache
 
-    // Save the serialized servers to Tauri's store
-    app_handle
-        .store(COCO_TAURI_STORE)
-        .expect("create or load a store should never fail")
-        .set(COCO_SERVER_TOKENS, json_servers);
+    log::debug!("persist {} servers token", cache.len());
+
+    for (server_id, tokens) in cache.iter() {
+        for (account_id, token) in tokens {
+            let json = serde_json::to_string(token).expect("Failed to serialize access_tokens");
+            store.set(&token_secret_key(server_id, account_id), &json)?;
+        }
+    }
 
     Ok(())
 }
 
+/// ID of the built-in Coco Cloud server.
+pub const BUILTIN_SERVER_ID: &str = "default_coco_server";
+
 // Function to get the default server if the request or parsing fails
 fn get_default_server() -> Server {
     Server {
-        id: "default_coco_server".to_string(),
+        id: BUILTIN_SERVER_ID.to_string(),
         builtin: true,
         enabled: true,
         name: "Coco Cloud".to_string(),
@@ -148,52 +348,100 @@ fn get_default_server() -> Server {
         },
         priority: 0,
         stats: None,
+        tls: None,
+        proxy: None,
+        timeouts: None,
+        needs_relogin: false,
+        incompatible_reason: None,
+        accounts: Vec::new(),
+        active_account: None,
+        locked: false,
     }
 }
 
-pub async fn load_servers_token<R: Runtime>(
-    app_handle: &AppHandle<R>,
-) -> Result<Vec<ServerAccessToken>, String> {
-    log::debug!("Attempting to load servers token");
-
+/// Move the tokens stored in plain text by the previous versions to the secret
+/// store.
+fn migrate_plaintext_servers_token<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
     let store = app_handle
         .store(COCO_TAURI_STORE)
         .expect("create or load a store should not fail");
 
-    // Check if the servers key exists in the store
-    if !store.has(COCO_SERVER_TOKENS) {
-        return Err("Failed to read servers from store: No servers found".to_string());
-    }
+    let Some(tokens) = store.get(COCO_SERVER_TOKENS) else {
+        return Ok(());
+    };
 
-    // Load servers from store
-    let servers: Option<JsonValue> = store.get(COCO_SERVER_TOKENS);
+    log::info!("moving the plain text server tokens to the secret store");
+    let migrated = move_plaintext_tokens(tokens, secret_store()?)?;
 
-    // Handle the None case
-    let servers =
-        servers.ok_or_else(|| "Failed to read servers from store: No servers found".to_string())?;
+    // Only drop the plain text tokens once they are safely stored
+    store.delete(COCO_SERVER_TOKENS);
+    store
+        .save()
+        .map_err(|e| format!("Failed to save the store: {}", e))?;
 
-    // Convert each item in the JsonValue array to a Server
-    if let JsonValue::Array(servers_array) = servers {
-        // Deserialize each JsonValue into Server, filtering out any errors
-        let deserialized_tokens: Vec<ServerAccessToken> = servers_array
+    log::debug!("migrated {} servers token", migrated);
+
+    Ok(())
+}
+
+/// Write the plain text `tokens` to `secrets`, return how many were valid.
+fn move_plaintext_tokens(tokens: JsonValue, secrets: &dyn SecretStore) -> Result<usize, String> {
+    let tokens: Vec<ServerAccessToken> = match tokens {
+        JsonValue::Array(tokens) => tokens
             .into_iter()
-            .filter_map(|server_json| from_value(server_json).ok()) // Only keep valid Server instances
-            .collect();
+            .filter_map(|token_json| from_value(token_json).ok()) // Only keep valid tokens
+            .collect(),
+        _ => Vec::new(),
+    };
 
-        if deserialized_tokens.is_empty() {
-            return Err("Failed to deserialize any servers from the store.".to_string());
+    for token in tokens.iter() {
+        let json = serde_json::to_string(token).expect("Failed to serialize access_tokens");
+        secrets.set(&token_secret_key(&token.id, DEFAULT_ACCOUNT_ID), &json)?;
+    }
+
+    Ok(tokens.len())
+}
+
+/// Load the tokens of the known servers from the secret store, called after
+/// the servers are loaded.
+pub async fn load_servers_token<R: Runtime>(
+    app_handle: &AppHandle<R>,
+) -> Result<Vec<ServerAccessToken>, String> {
+    log::debug!("Attempting to load servers token");
+
+    migrate_plaintext_servers_token(app_handle)?;
+
+    let store = secret_store()?;
+    let mut tokens = Vec::new();
+    for server in get_all_servers() {
+        let mut account_ids: Vec<&str> = server.accounts.iter().map(|a| a.id.as_str()).collect();
+        if account_ids.is_empty() {
+            account_ids.push(DEFAULT_ACCOUNT_ID);
         }
 
-        for server in deserialized_tokens.iter() {
-            save_access_token(server.id.clone(), server.clone());
+        for account_id in account_ids {
+            let Some(json) = store.get(&token_secret_key(&server.id, account_id))? else {
+                continue;
+            };
+
+            match serde_json::from_str::<ServerAccessToken>(&json) {
+                Ok(token) => {
+                    save_account_token(server.id.clone(), account_id.to_string(), token.clone());
+                    tokens.push(token);
+                }
+                Err(e) => log::warn!(
+                    "ignoring the invalid stored token of account [{}] of server [{}]: {}",
+                    account_id,
+                    server.id,
+                    e
+                ),
+            }
         }
+    }
 
-        log::debug!("loaded {:?} servers's token", &deserialized_tokens.len());
+    log::debug!("loaded {:?} servers's token", &tokens.len());
 
-        Ok(deserialized_tokens)
-    } else {
-        Err("Failed to read servers from store: Invalid format".to_string())
-    }
+    Ok(tokens)
 }
 
 pub async fn load_servers<R: Runtime>(app_handle: &AppHandle<R>) -> Result<Vec<Server>, String> {
@@ -218,7 +466,10 @@ pub async fn load_servers<R: Runtime>(app_handle: &AppHandle<R>) -> Result<Vec<S
         // Deserialize each JsonValue into Server, filtering out any errors
         let deserialized_servers: Vec<Server> = servers_array
             .into_iter()
-            .filter_map(|server_json| from_value(server_json).ok()) // Only keep valid Server instances
+            .filter_map(|mut server_json| {
+                load_server_secrets(&mut server_json);
+                from_value(server_json).ok()
+            }) // Only keep valid Server instances
             .collect();
 
         if deserialized_servers.is_empty() {
@@ -249,6 +500,11 @@ pub async fn load_or_insert_default_server<R: Runtime>(
         return exists_servers;
     }
 
+    if policy::policy().builtin_server == Some(BuiltinServerPolicy::Hidden) {
+        log::debug!("not inserting the built-in server, it is hidden by the policy");
+        return Ok(Vec::new());
+    }
+
     let default = get_default_server();
     save_server(&default);
 
@@ -311,16 +567,31 @@ pub async fn refresh_coco_server_info<R: Runtime>(
     let is_enabled = server.enabled;
     let is_builtin = server.builtin;
     let profile = server.profile;
+    let tls = server.tls;
+    let proxy = server.proxy;
+    let timeouts = server.timeouts;
+    let needs_relogin = server.needs_relogin;
+    let incompatible_reason = server.incompatible_reason;
+    let accounts = server.accounts;
+    let active_account = server.active_account;
+
+    // Send request to fetch updated server info, transient failures are
+    // retried by the HTTP client
+    let response = match HttpClient::get(&id, "/provider/_info", None).await {
+        Ok(response) if response.status().is_success() => response,
+        result => {
+            // Only give up on the server once its circuit is open, rather than
+            // upon a single failure
+            if circuit_breaker::is_open(&id) {
+                let _ = mark_server_as_offline(app_handle, &id).await;
+            }
 
-    // Send request to fetch updated server info
-    let response = HttpClient::get(&id, "/provider/_info", None)
-        .await
-        .map_err(|e| format!("Failed to contact the server: {}", e))?;
-
-    if !response.status().is_success() {
-        let _ = mark_server_as_offline(app_handle, &id).await;
-        return Err(format!("Request failed with status: {}", response.status()));
-    }
+            return Err(match result {
+                Ok(response) => format!("Request failed with status: {}", response.status()),
+                Err(e) => format!("Failed to contact the server: {}", e),
+            });
+        }
+    };
 
     // Get body text via helper
     let body = get_response_body_text(response).await?;
@@ -335,7 +606,16 @@ pub async fn refresh_coco_server_info<R: Runtime>(
     updated_server.enabled = is_enabled;
     updated_server.available = true;
     updated_server.profile = profile;
+    updated_server.tls = tls;
+    updated_server.proxy = proxy;
+    updated_server.timeouts = timeouts;
+    updated_server.needs_relogin = needs_relogin;
+    updated_server.incompatible_reason = incompatible_reason;
+    updated_server.accounts = accounts;
+    updated_server.active_account = active_account;
+    policy::apply_server_policy(&mut updated_server);
     trim_endpoint_last_forward_slash(&mut updated_server);
+    check_client_compatibility(&app_handle, &mut updated_server);
 
     // Save and persist
     save_server(&updated_server);
@@ -343,6 +623,15 @@ pub async fn refresh_coco_server_info<R: Runtime>(
         .await
         .map_err(|e| format!("Failed to persist servers: {}", e))?;
 
+    if updated_server.incompatible_reason.is_some() {
+        let registry = app_handle.state::<SearchSourceRegistry>();
+        registry.remove_source(&id).await;
+        return Ok(updated_server);
+    }
+
+    // Replaces the offline cache search source if the server was offline
+    try_register_server_to_search_source(app_handle.clone(), &updated_server).await;
+
     // Refresh connectors and datasources (best effort)
     let _ = fetch_connectors_by_server(&id).await;
     let _ = datasource_search(&id, None).await;
@@ -354,6 +643,8 @@ pub async fn refresh_coco_server_info<R: Runtime>(
 pub async fn add_coco_server<R: Runtime>(
     app_handle: AppHandle<R>,
     endpoint: String,
+    tls: Option<ServerTlsConfig>,
+    proxy: Option<ProxyConfig>,
 ) -> Result<Server, String> {
     load_or_insert_default_server(&app_handle)
         .await
@@ -369,10 +660,29 @@ pub async fn add_coco_server<R: Runtime>(
         return Err("This Coco server has already been registered.".into());
     }
 
+    if let Some(tls) = &tls {
+        tls::validate_tls_config(tls)?;
+    }
+    if let Some(proxy) = &proxy {
+        proxy::validate_proxy_config(proxy)?;
+    }
+
     let url = provider_info_url(endpoint);
-    let response = HttpClient::send_raw_request(Method::GET, url.as_str(), None, None, None)
+    let response = if tls.is_some() || proxy.is_some() {
+        HttpClient::send_raw_request_with_settings(
+            tls.as_ref(),
+            proxy.as_ref(),
+            Method::GET,
+            url.as_str(),
+            None,
+            None,
+            None,
+        )
         .await
-        .map_err(|e| format!("Failed to send request to the server: {}", e))?;
+    } else {
+        HttpClient::send_raw_request(Method::GET, url.as_str(), None, None, None).await
+    }
+    .map_err(|e| format!("Failed to send request to the server: {}", e))?;
 
     log::debug!("Get provider info response: {:?}", &response);
 
@@ -391,6 +701,13 @@ pub async fn add_coco_server<R: Runtime>(
         server.name = "Coco Server".to_string();
     }
 
+    // Don't let a server take the place of one managed by the policy
+    policy::ensure_server_editable(&server.id)?;
+
+    server.tls = tls;
+    server.proxy = proxy;
+    check_client_compatibility(&app_handle, &mut server);
+
     save_server(&server);
     try_register_server_to_search_source(app_handle.clone(), &server).await;
 
@@ -406,11 +723,18 @@ pub async fn add_coco_server<R: Runtime>(
 pub async fn remove_coco_server<R: Runtime>(
     app_handle: AppHandle<R>,
     id: String,
-) -> Result<(), ()> {
+) -> Result<(), String> {
+    policy::ensure_server_editable(&id)?;
+
     let registry = app_handle.state::<SearchSourceRegistry>();
     registry.remove_source(id.as_str()).await;
 
-    remove_server_token(id.as_str());
+    remove_server_tokens(id.as_str());
+    remove_server_secrets(id.as_str());
+    offline_cache::forget_server_documents(id.as_str());
+    invalidate_server_http_client(id.as_str());
+    circuit_breaker::forget_server(id.as_str());
+    health_monitor::forget_server(id.as_str());
     remove_server_by_id(id);
 
     persist_servers(&app_handle)
@@ -421,8 +745,9 @@ pub async fn remove_coco_server<R: Runtime>(
 }
 
 #[tauri::command]
-pub async fn enable_server<R: Runtime>(app_handle: AppHandle<R>, id: String) -> Result<(), ()> {
+pub async fn enable_server<R: Runtime>(app_handle: AppHandle<R>, id: String) -> Result<(), String> {
     println!("enable_server: {}", id);
+    policy::ensure_server_editable(&id)?;
 
     let server = get_server_by_id(id.as_str());
     if let Some(mut server) = server {
@@ -443,6 +768,11 @@ pub async fn try_register_server_to_search_source(
     app_handle: AppHandle<impl Runtime>,
     server: &Server,
 ) {
+    if server.incompatible_reason.is_some() {
+        log::debug!("Server {} is incompatible with this client", &server.id);
+        return;
+    }
+
     if server.enabled {
         log::trace!(
             "Server {} is public: {} and available: {}",
@@ -466,6 +796,115 @@ pub async fn try_register_server_to_search_source(
     }
 }
 
+/// Update the TLS settings of the server specified by `id`, pass `None` to go
+/// back to the defaults.
+#[tauri::command]
+pub async fn set_server_tls_config<R: Runtime>(
+    app_handle: AppHandle<R>,
+    id: String,
+    mut tls: Option<ServerTlsConfig>,
+) -> Result<Server, String> {
+    let mut server =
+        get_server_by_id(&id).ok_or_else(|| format!("Server with ID {} not found", id))?;
+    policy::ensure_server_editable(&id)?;
+
+    if let Some(tls) = &mut tls {
+        // The client key is never returned, leaving it out keeps the current
+        // one along with its certificate
+        if let Some(current) = &server.tls {
+            if tls.client_key.is_none() && tls.client_certificate == current.client_certificate {
+                tls.client_key = current.client_key.clone();
+            }
+        }
+        tls::validate_tls_config(tls)?;
+    }
+
+    server.tls = tls;
+    save_server(&server);
+    invalidate_server_http_client(&id);
+
+    persist_servers(&app_handle)
+        .await
+        .map_err(|e| format!("Failed to persist Coco servers: {}", e))?;
+
+    Ok(server)
+}
+
+/// Update the proxy settings of the server specified by `id`, pass `None` to
+/// use the global ones.
+#[tauri::command]
+pub async fn set_server_proxy_config<R: Runtime>(
+    app_handle: AppHandle<R>,
+    id: String,
+    mut proxy: Option<ProxyConfig>,
+) -> Result<Server, String> {
+    let mut server =
+        get_server_by_id(&id).ok_or_else(|| format!("Server with ID {} not found", id))?;
+    policy::ensure_server_editable(&id)?;
+
+    if let Some(proxy) = &mut proxy {
+        // The password is never returned, leaving it out keeps the current one
+        // along with its user
+        if let Some(current) = &server.proxy {
+            if proxy.password.is_none() && proxy.username == current.username {
+                proxy.password = current.password.clone();
+            }
+        }
+        proxy::validate_proxy_config(proxy)?;
+    }
+
+    server.proxy = proxy;
+    save_server(&server);
+    invalidate_server_http_client(&id);
+
+    persist_servers(&app_handle)
+        .await
+        .map_err(|e| format!("Failed to persist Coco servers: {}", e))?;
+
+    Ok(server)
+}
+
+pub(crate) fn validate_timeout_profile(timeouts: &TimeoutProfile) -> Result<(), String> {
+    let values = [
+        Some(timeouts.connect_timeout_ms),
+        timeouts.read_timeout_ms,
+        timeouts.total_timeout_ms,
+    ];
+    if values.into_iter().flatten().any(|ms| ms == 0) {
+        return Err("timeouts should be greater than 0".into());
+    }
+
+    Ok(())
+}
+
+/// Set the timeouts of the server specified by `id`, pass `None` to use the
+/// default ones.
+#[tauri::command]
+pub async fn set_server_timeouts<R: Runtime>(
+    app_handle: AppHandle<R>,
+    id: String,
+    timeouts: Option<ServerTimeouts>,
+) -> Result<Server, String> {
+    let mut server =
+        get_server_by_id(&id).ok_or_else(|| format!("Server with ID {} not found", id))?;
+    policy::ensure_server_editable(&id)?;
+
+    if let Some(timeouts) = &timeouts {
+        validate_timeout_profile(&timeouts.normal)?;
+        validate_timeout_profile(&timeouts.streaming)?;
+    }
+
+    server.timeouts = timeouts;
+    save_server(&server);
+    invalidate_server_http_client(&id);
+
+    persist_servers(&app_handle)
+        .await
+        .map_err(|e| format!("Failed to persist Coco servers: {}", e))?;
+
+    Ok(server)
+}
+
 #[tauri::command]
 pub async fn mark_server_as_offline<R: Runtime>(
     app_handle: AppHandle<R>, id: &str) -> Result<(), ()> {
@@ -478,12 +917,20 @@ pub async fn mark_server_as_offline<R: Runtime>(
 
         let registry = app_handle.state::<SearchSourceRegistry>();
         registry.remove_source(id).await;
+
+        // Keep its recently seen documents searchable while it is offline
+        offline_cache::register_offline_fallback(&app_handle, &server).await;
     }
     Ok(())
 }
 
 #[tauri::command]
-pub async fn disable_server<R: Runtime>(app_handle: AppHandle<R>, id: String) -> Result<(), ()> {
+pub async fn disable_server<R: Runtime>(
+    app_handle: AppHandle<R>,
+    id: String,
+) -> Result<(), String> {
+    policy::ensure_server_editable(&id)?;
+
     let server = get_server_by_id(id.as_str());
     if let Some(mut server) = server {
         server.enabled = false;
@@ -527,8 +974,10 @@ pub async fn logout_coco_server<R: Runtime>(
     if let Some(mut server) = get_server_by_id(id.as_str()) {
         log::debug!("Found server for id: {}", &id);
 
-        // Clear server profile
-        server.profile = None;
+        // Clear the profile of the account
+        server.set_active_profile(None);
+        // and the documents that were visible to this user
+        offline_cache::forget_server_documents(id.as_str());
         let _ = mark_server_as_offline(app_handle.clone(), id.as_str()).await;
 
         // Save the updated server data
@@ -564,6 +1013,90 @@ fn provider_info_url(endpoint: &str) -> String {
     format!("{endpoint}/provider/_info")
 }
 
+/// Emitted when a server requires a newer version of this client.
+const EVENT_CLIENT_UPDATE_REQUIRED: &str = "client-update-required";
+
+/// Parse a version number, tolerating a `v` prefix and missing components,
+/// e.g., "v0.4".
+fn parse_version(number: &str) -> Option<semver::Version> {
+    let number = number.trim().trim_start_matches('v');
+    if let Ok(version) = semver::Version::parse(number) {
+        return Some(version);
+    }
+
+    let (core, suffix) = match number.find(['-', '+']) {
+        Some(index) => number.split_at(index),
+        None => (number, ""),
+    };
+    let mut components: Vec<&str> = core.split('.').collect();
+    if components.is_empty() || components.len() > 3 {
+        return None;
+    }
+    components.resize(3, "0");
+
+    semver::Version::parse(&format!("{}{}", components.join("."), suffix)).ok()
+}
+
+/// Return why this client, whose version is `client_version`, cannot be used
+/// with `server`, `None` if it can.
+///
+/// Pre-release and snapshot builds are considered compatible with the release
+/// they lead to.
+fn client_incompatibility(server: &Server, client_version: &semver::Version) -> Option<String> {
+    let minimal = server.minimal_client_version.as_ref()?;
+    let Some(minimal_version) = parse_version(&minimal.number) else {
+        log::warn!(
+            "ignoring the invalid minimal client version [{}] of server [{}]",
+            minimal.number,
+            server.id
+        );
+        return None;
+    };
+
+    let client_core = (
+        client_version.major,
+        client_version.minor,
+        client_version.patch,
+    );
+    let minimal_core = (
+        minimal_version.major,
+        minimal_version.minor,
+        minimal_version.patch,
+    );
+    if client_core >= minimal_core {
+        return None;
+    }
+
+    Some(format!(
+        "This server requires Coco {} or newer, please update Coco (current version: {}).",
+        minimal_version, client_version
+    ))
+}
+
+/// Check whether this client is recent enough for `server`, update its
+/// `incompatible_reason` accordingly and ask the frontend to prompt for an
+/// update if it just became incompatible.
+fn check_client_compatibility<R: Runtime>(app_handle: &AppHandle<R>, server: &mut Server) {
+    let reason = client_incompatibility(server, &app_handle.package_info().version);
+
+    if let Some(reason) = &reason {
+        log::warn!("server [{}] is incompatible: {}", server.id, reason);
+
+        if server.incompatible_reason.is_none() {
+            let _ = app_handle.emit(
+                EVENT_CLIENT_UPDATE_REQUIRED,
+                serde_json::json!({
+                    "server_id": server.id,
+                    "minimal_client_version": server.minimal_client_version,
+                    "reason": reason,
+                }),
+            );
+        }
+    }
+
+    server.incompatible_reason = reason;
+}
+
 #[test]
 fn test_trim_endpoint_last_forward_slash() {
     let mut server = Server {
@@ -597,9 +1130,98 @@ fn test_trim_endpoint_last_forward_slash() {
         },
         priority: 0,
         stats: None,
+        tls: None,
+        proxy: None,
+        timeouts: None,
+        needs_relogin: false,
+        incompatible_reason: None,
+        accounts: Vec::new(),
+        active_account: None,
+        locked: false,
     };
 
     trim_endpoint_last_forward_slash(&mut server);
 
     assert_eq!(server.endpoint, "https://example.com");
 }
+
+#[test]
+fn test_client_incompatibility() {
+    use crate::common::server::MinimalClientVersion;
+
+    assert_eq!(parse_version("v0.4"), semver::Version::parse("0.4.0").ok());
+    assert_eq!(
+        parse_version("1.2-2050"),
+        semver::Version::parse("1.2.0-2050").ok()
+    );
+    assert_eq!(parse_version("latest"), None);
+
+    let mut server: Server = serde_json::from_value(serde_json::json!({
+        "id": "test",
+        "name": "Test",
+        "endpoint": "https://example.com",
+        "provider": {
+            "name": "", "icon": "", "website": "", "eula": "",
+            "privacy_policy": "", "banner": "", "description": ""
+        },
+        "version": { "number": "0.5.0" },
+        "updated": "",
+        "auth_provider": { "sso": { "url": "" } }
+    }))
+    .unwrap();
+    let client_version = semver::Version::parse("0.4.0-2050").unwrap();
+    assert_eq!(client_incompatibility(&server, &client_version), None);
+
+    server.minimal_client_version = Some(MinimalClientVersion {
+        number: "0.4.0".into(),
+    });
+    assert_eq!(client_incompatibility(&server, &client_version), None);
+
+    server.minimal_client_version = Some(MinimalClientVersion {
+        number: "0.5".into(),
+    });
+    assert!(client_incompatibility(&server, &client_version).is_some());
+}
+
+#[test]
+fn test_move_plaintext_tokens() {
+    struct MemorySecretStore(std::sync::Mutex<HashMap<String, String>>);
+
+    impl SecretStore for MemorySecretStore {
+        fn name(&self) -> &'static str {
+            "memory"
+        }
+        fn get(&self, key: &str) -> Result<Option<String>, String> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+        fn set(&self, key: &str, value: &str) -> Result<(), String> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+        fn delete(&self, key: &str) -> Result<(), String> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    let secrets = MemorySecretStore(Default::default());
+    let tokens = serde_json::json!([
+        { "id": "server", "access_token": "token", "expired_at": 0 },
+        { "id": "invalid" },
+    ]);
+    assert_eq!(move_plaintext_tokens(tokens, &secrets).unwrap(), 1);
+
+    let stored = secrets.get("server-token:server").unwrap().unwrap();
+    let token: ServerAccessToken = serde_json::from_str(&stored).unwrap();
+    assert_eq!(token.access_token, "token");
+    assert!(secrets.get("server-token:invalid").unwrap().is_none());
+
+    // Not a list of tokens
+    assert_eq!(
+        move_plaintext_tokens(serde_json::json!({}), &secrets).unwrap(),
+        0
+    );
+}
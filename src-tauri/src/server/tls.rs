@@ -0,0 +1,243 @@
+//! Per-server TLS settings.
+//!
+//! They are applied to every connection we make to a Coco server: the HTTP
+//! requests sent by [`HttpClient`](crate::server::http_client::HttpClient),
+//! the WebSocket connections and attachment uploads.
+//!
+//! The SPKI pin of a server is checked during the TLS handshake, before any
+//! credential is sent. native-tls cannot do that for reqwest, so the HTTP
+//! clients of the pinned servers use rustls with [`PinnedCertVerifier`].
+
+use crate::common::server::ServerTlsConfig;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio_native_tls::native_tls;
+
+/// Whether invalid certificates should be accepted for a server that uses
+/// `tls`, the per-server setting takes precedence over the global one.
+pub(crate) fn allow_self_signature(tls: Option<&ServerTlsConfig>) -> bool {
+    match tls.and_then(|tls| tls.allow_self_signature) {
+        Some(allow) => allow,
+        None => crate::settings::settings().allow_self_signature,
+    }
+}
+
+/// Return the client certificate and key if both of them are set.
+fn client_identity_pem(tls: &ServerTlsConfig) -> Result<Option<(&[u8], &[u8])>, String> {
+    match (&tls.client_certificate, &tls.client_key) {
+        (Some(cert), Some(key)) => Ok(Some((cert.as_bytes(), key.as_bytes()))),
+        (None, None) => Ok(None),
+        (Some(_), None) => Err("client certificate is set but its key is missing".into()),
+        (None, Some(_)) => Err("client key is set but its certificate is missing".into()),
+    }
+}
+
+/// Check that `tls` can be applied, so that invalid settings are rejected
+/// when they are saved rather than when they are used.
+pub(crate) fn validate_tls_config(tls: &ServerTlsConfig) -> Result<(), String> {
+    apply_to_reqwest(reqwest::Client::builder(), tls)?;
+    native_tls_connector(Some(tls))?;
+
+    if let Some(pin) = &tls.spki_pin {
+        let digest =
+            base64::decode(pin).map_err(|e| format!("SPKI pin is not valid base64: {}", e))?;
+        if digest.len() != 32 {
+            return Err("SPKI pin should be a base64 encoded SHA-256 digest".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply `tls` to a reqwest client builder.
+pub(crate) fn apply_to_reqwest(
+    mut builder: reqwest::ClientBuilder,
+    tls: &ServerTlsConfig,
+) -> Result<reqwest::ClientBuilder, String> {
+    if let Some(pin) = &tls.spki_pin {
+        return Ok(builder.use_preconfigured_tls(pinned_rustls_config(tls, pin)?));
+    }
+
+    if let Some(ca) = &tls.ca_certificate {
+        let certificate = reqwest::Certificate::from_pem(ca.as_bytes())
+            .map_err(|e| format!("invalid CA certificate: {}", e))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    if let Some((cert, key)) = client_identity_pem(tls)? {
+        let identity = reqwest::Identity::from_pkcs8_pem(cert, key)
+            .map_err(|e| format!("invalid client certificate or key: {}", e))?;
+        builder = builder.identity(identity);
+    }
+
+    Ok(builder.danger_accept_invalid_certs(allow_self_signature(Some(tls))))
+}
+
+/// Verifies the certificate chain like rustls does, unless invalid
+/// certificates are accepted, then that the certificate matches the pin.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pin: String,
+    /// `None` if invalid certificates are accepted, only the pin is checked.
+    chain_verifier: Option<Arc<WebPkiServerVerifier>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(chain_verifier) = &self.chain_verifier {
+            chain_verifier.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        verify_spki_pin(&self.pin, Some(end_entity.as_ref())).map_err(rustls::Error::General)?;
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Build the rustls config of a server pinned to `pin`, with the rest of `tls`
+/// applied.
+fn pinned_rustls_config(tls: &ServerTlsConfig, pin: &str) -> Result<rustls::ClientConfig, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let chain_verifier = if allow_self_signature(Some(tls)) {
+        None
+    } else {
+        let mut roots = RootCertStore::empty();
+        let native_certs = rustls_native_certs::load_native_certs();
+        for e in native_certs.errors {
+            log::warn!("failed to load a system root certificate: {}", e);
+        }
+        roots.add_parsable_certificates(native_certs.certs);
+
+        if let Some(ca) = &tls.ca_certificate {
+            for certificate in rustls_pemfile::certs(&mut ca.as_bytes()) {
+                let certificate =
+                    certificate.map_err(|e| format!("invalid CA certificate: {}", e))?;
+                roots
+                    .add(certificate)
+                    .map_err(|e| format!("invalid CA certificate: {}", e))?;
+            }
+        }
+
+        Some(
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|e| format!("TLS build error: {}", e))?,
+        )
+    };
+
+    let verifier = PinnedCertVerifier {
+        pin: pin.trim().to_string(),
+        chain_verifier,
+        algorithms: provider.signature_verification_algorithms,
+    };
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS build error: {}", e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+
+    match client_identity_pem(tls)? {
+        Some((cert, key)) => {
+            let certificates = rustls_pemfile::certs(&mut &cert[..])
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("invalid client certificate: {}", e))?;
+            let key = rustls_pemfile::private_key(&mut &key[..])
+                .map_err(|e| format!("invalid client key: {}", e))?
+                .ok_or("invalid client key: no private key found")?;
+            builder
+                .with_client_auth_cert(certificates, key)
+                .map_err(|e| format!("invalid client certificate or key: {}", e))
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+/// Build a `native_tls` connector for the WebSocket connections.
+pub(crate) fn native_tls_connector(
+    tls: Option<&ServerTlsConfig>,
+) -> Result<native_tls::TlsConnector, String> {
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.danger_accept_invalid_certs(allow_self_signature(tls));
+
+    if let Some(tls) = tls {
+        if let Some(ca) = &tls.ca_certificate {
+            let certificate = native_tls::Certificate::from_pem(ca.as_bytes())
+                .map_err(|e| format!("invalid CA certificate: {}", e))?;
+            builder.add_root_certificate(certificate);
+        }
+
+        if let Some((cert, key)) = client_identity_pem(tls)? {
+            let identity = native_tls::Identity::from_pkcs8(cert, key)
+                .map_err(|e| format!("invalid client certificate or key: {}", e))?;
+            builder.identity(identity);
+        }
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("TLS build error: {:?}", e))
+}
+
+/// Base64 encoded SHA-256 digest of the SubjectPublicKeyInfo of the DER
+/// encoded certificate `cert_der`.
+fn spki_sha256(cert_der: &[u8]) -> Result<String, String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(cert_der)
+        .map_err(|e| format!("failed to parse the server certificate: {}", e))?;
+    let digest = Sha256::digest(certificate.public_key().raw);
+
+    Ok(base64::encode(digest))
+}
+
+/// Verify that the DER encoded certificate `cert_der` matches `pin`.
+pub(crate) fn verify_spki_pin(pin: &str, cert_der: Option<&[u8]>) -> Result<(), String> {
+    let cert_der = cert_der.ok_or("the server did not present a certificate to verify the pin")?;
+    let actual = spki_sha256(cert_der)?;
+
+    if actual != pin.trim() {
+        log::warn!("SPKI pin mismatch, expected [{}], found [{}]", pin, actual);
+        return Err("the server certificate does not match the pinned public key".into());
+    }
+
+    Ok(())
+}
@@ -1,4 +1,6 @@
+use crate::common::server::Server;
 use crate::server::servers::{get_server_by_id, get_server_token};
+use crate::server::{proxy, tls};
 use futures::StreamExt;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -9,7 +11,8 @@ use tokio_tungstenite::tungstenite::handshake::client::generate_key;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::MaybeTlsStream;
 use tokio_tungstenite::WebSocketStream;
-use tokio_tungstenite::{connect_async_tls_with_config, Connector};
+use tokio_tungstenite::client_async_with_config;
+
 #[derive(Default)]
 pub struct WebSocketManager {
     connections: Arc<Mutex<HashMap<String, Arc<WebSocketInstance>>>>,
@@ -40,9 +43,52 @@ fn convert_to_websocket(endpoint: &str) -> Result<String, String> {
     Ok(ws_endpoint)
 }
 
+/// Open the TCP connection to the WebSocket `endpoint` of `server`, through
+/// its proxy if any, and the TLS session on top of it if needed.
+///
+/// We do this ourselves rather than letting tungstenite do it, so that the
+/// pinned public key can be verified before the request carrying the token
+/// is sent.
+async fn connect_stream(
+    server: &Server,
+    endpoint: &str,
+) -> Result<MaybeTlsStream<TcpStream>, String> {
+    let url = url::Url::parse(endpoint).map_err(|e| format!("Invalid URL: {}", e))?;
+    let host = url.host_str().ok_or("No host found in URL")?;
+
+    let proxy_config = proxy::effective_proxy_config(server.proxy.as_ref());
+    let tcp_stream = proxy::connect_tcp(&proxy_config, &url).await?;
+    let _ = tcp_stream.set_nodelay(true);
+
+    if url.scheme() != "wss" {
+        return Ok(MaybeTlsStream::Plain(tcp_stream));
+    }
+
+    let connector = tokio_native_tls::TlsConnector::from(tls::native_tls_connector(
+        server.tls.as_ref(),
+    )?);
+    let tls_stream = connector
+        .connect(host, tcp_stream)
+        .await
+        .map_err(|e| format!("WebSocket TLS error: {:?}", e))?;
+
+    if let Some(pin) = server.tls.as_ref().and_then(|tls| tls.spki_pin.as_deref()) {
+        let peer_certificate = tls_stream
+            .get_ref()
+            .peer_certificate()
+            .map_err(|e| format!("Failed to get the server certificate: {}", e))?
+            .map(|certificate| certificate.to_der())
+            .transpose()
+            .map_err(|e| format!("Failed to encode the server certificate: {}", e))?;
+        tls::verify_spki_pin(pin, peer_certificate.as_deref())?;
+    }
+
+    Ok(MaybeTlsStream::NativeTls(tls_stream))
+}
+
 #[tauri::command]
 pub async fn connect_to_server<R: Runtime>(
-    tauri_app_handle: AppHandle<R>,
+    _tauri_app_handle: AppHandle<R>,
     id: String,
     client_id: String,
     state: tauri::State<'_, WebSocketManager>,
@@ -54,6 +100,10 @@ pub async fn connect_to_server<R: Runtime>(
     disconnect(client_id.clone(), state.clone()).await.ok();
 
     let server = get_server_by_id(&id).ok_or(format!("Server with ID {} not found", id))?;
+    // Like the HTTP requests, servers requiring a newer client are not used
+    if let Some(reason) = &server.incompatible_reason {
+        return Err(reason.clone());
+    }
     let endpoint = convert_to_websocket(&server.endpoint)?;
     let token = get_server_token(&id).await?.map(|t| t.access_token.clone());
 
@@ -80,23 +130,11 @@ pub async fn connect_to_server<R: Runtime>(
             .insert("X-API-TOKEN", token.parse().unwrap());
     }
 
-    let allow_self_signature =
-        crate::settings::get_allow_self_signature(tauri_app_handle.clone()).await;
-    let tls_connector = tokio_native_tls::native_tls::TlsConnector::builder()
-        .danger_accept_invalid_certs(allow_self_signature)
-        .build()
-        .map_err(|e| format!("TLS build error: {:?}", e))?;
-
-    let connector = Connector::NativeTls(tls_connector.into());
-
-    let (ws_stream, _) = connect_async_tls_with_config(
-        request,
-        None,            // WebSocketConfig
-        true,            // disable_nagle
-        Some(connector), // Connector
-    )
+    let stream = connect_stream(&server, &endpoint).await?;
+
+    let (ws_stream, _) = client_async_with_config(request, stream, None)
         .await
-        .map_err(|e| format!("WebSocket TLS error: {:?}", e))?;
+        .map_err(|e| format!("WebSocket handshake error: {:?}", e))?;
 
     let (cancel_tx, mut cancel_rx) = mpsc::channel(1);
 
@@ -1,72 +1,389 @@
+//! The settings of the app.
+//!
+//! They are kept in memory as a typed [`Settings`], loaded from the Tauri store
+//! upon app start and changed through [`update()`], which validates and
+//! persists them. Subsystems [subscribe](subscribe()) to the changes rather
+//! than reading the store, and the frontend gets the `settings-changed` event.
+
+use crate::autostart::AutostartSettings;
+use crate::common::server::ProxyConfig;
+use crate::local_api::LocalApiSettings;
+use crate::shortcut::{self, ActionShortcut};
 use crate::COCO_TAURI_STORE;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as Json;
-use tauri::{AppHandle, Runtime};
+use std::sync::RwLock;
+use tauri::{AppHandle, Emitter, Runtime};
+use tauri_plugin_global_shortcut::Shortcut;
 use tauri_plugin_store::StoreExt;
 
+// Keys of the settings in the Tauri store, from the time every setting was
+// handled on its own.
 const SETTINGS_ALLOW_SELF_SIGNATURE: &str = "settings_allow_self_signature";
+const SETTINGS_OFFLINE_CACHE_ENABLED: &str = "settings_offline_cache_enabled";
+const SETTINGS_PROXY: &str = "settings_proxy";
+const COCO_GLOBAL_SHORTCUT: &str = "coco_global_shortcut";
+pub(crate) const SETTINGS_AUTOSTART: &str = "settings_autostart";
+const SETTINGS_ACTION_SHORTCUTS: &str = "settings_action_shortcuts";
+const SETTINGS_LOCAL_API: &str = "settings_local_api";
 
-#[tauri::command]
-pub async fn set_allow_self_signature<R: Runtime>(tauri_app_handle: AppHandle<R>, value: bool) {
-    use crate::server::http_client;
+/// Entry of the proxy password in the secret store, the stored proxy settings
+/// refer to it with their `password_secret` field.
+const PROXY_PASSWORD_SECRET: &str = "settings-proxy-password";
+
+#[cfg(target_os = "macos")]
+pub(crate) const DEFAULT_SHORTCUT: &str = "command+shift+space";
+
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+pub(crate) const DEFAULT_SHORTCUT: &str = "ctrl+shift+space";
+
+/// Emitted with the new [`Settings`] whenever they change.
+const EVENT_SETTINGS_CHANGED: &str = "settings-changed";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Accept invalid certificates, for the servers without their own TLS
+    /// settings.
+    pub allow_self_signature: bool,
+    /// Cache the documents of the servers so that they can be searched while
+    /// offline.
+    pub offline_cache_enabled: bool,
+    /// Used by the servers without their own proxy settings.
+    pub proxy: ProxyConfig,
+    /// Shortcut showing or hiding Coco.
+    pub global_shortcut: String,
+    /// Shortcuts of the other actions.
+    pub action_shortcuts: Vec<ActionShortcut>,
+    /// Starting Coco when the user logs in.
+    pub autostart: AutostartSettings,
+    /// The local API to script Coco.
+    pub local_api: LocalApiSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            allow_self_signature: false,
+            offline_cache_enabled: false,
+            proxy: ProxyConfig::default(),
+            global_shortcut: DEFAULT_SHORTCUT.to_string(),
+            action_shortcuts: Vec::new(),
+            autostart: AutostartSettings::default(),
+            local_api: LocalApiSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        crate::server::proxy::validate_proxy_config(&self.proxy)?;
+        shortcut::validate_shortcuts(self)?;
+        self.autostart.validate()?;
+        self.local_api.validate()?;
+
+        Ok(())
+    }
+}
+
+type Subscriber = Box<dyn Fn(&Settings, &Settings) + Send + Sync>;
+
+lazy_static! {
+    static ref SETTINGS: RwLock<Settings> = RwLock::new(Settings::default());
+    static ref SUBSCRIBERS: RwLock<Vec<Subscriber>> = RwLock::new(Vec::new());
+}
+
+/// The current settings.
+pub(crate) fn settings() -> Settings {
+    SETTINGS.read().unwrap().clone()
+}
+
+/// Call `subscriber` with the previous and the new settings whenever they
+/// change.
+pub(crate) fn subscribe(subscriber: impl Fn(&Settings, &Settings) + Send + Sync + 'static) {
+    SUBSCRIBERS.write().unwrap().push(Box::new(subscriber));
+}
+
+/// Read the setting stored under `key`, the default is used if it is missing
+/// or invalid.
+fn read_setting<T: serde::de::DeserializeOwned>(value: Option<Json>, key: &str, default: T) -> T {
+    match value.map(serde_json::from_value) {
+        Some(Ok(value)) => value,
+        Some(Err(e)) => {
+            log::warn!(
+                "invalid setting [{}], falling back to the default: {}",
+                key,
+                e
+            );
+            default
+        }
+        None => default,
+    }
+}
+
+/// The proxy settings to store, with the password moved to the secret store.
+fn stored_proxy(proxy: &ProxyConfig) -> Result<Json, String> {
+    let secret_store = crate::server::secret_store::secret_store();
+    let mut proxy = proxy.clone();
+    let password = proxy.password.take();
+    let mut value = serde_json::to_value(&proxy).expect("Failed to serialize proxy settings");
+
+    match password {
+        Some(password) => {
+            let secret_store = secret_store?;
+            if secret_store.get(PROXY_PASSWORD_SECRET)?.as_deref() != Some(password.as_str()) {
+                secret_store.set(PROXY_PASSWORD_SECRET, &password)?;
+            }
+            value["password_secret"] = PROXY_PASSWORD_SECRET.into();
+        }
+        None => {
+            if let Ok(secret_store) = secret_store {
+                secret_store.delete(PROXY_PASSWORD_SECRET)?;
+            }
+        }
+    }
+
+    Ok(value)
+}
+
+/// The stored proxy settings, with the password read from the secret store.
+/// Older versions stored it in plain text, it is moved upon the next write.
+fn load_proxy(value: Option<Json>) -> Option<Json> {
+    let mut value = value?;
+    let Some(Json::String(key)) = value
+        .as_object_mut()
+        .and_then(|proxy| proxy.remove("password_secret"))
+    else {
+        return Some(value);
+    };
 
-    let store = tauri_app_handle
+    match crate::server::secret_store::secret_store().and_then(|store| store.get(&key)) {
+        Ok(Some(password)) => value["password"] = password.into(),
+        Ok(None) => log::warn!("the proxy password is missing from the secret store"),
+        Err(e) => log::error!("failed to read the proxy password: {}", e),
+    }
+
+    Some(value)
+}
+
+fn write_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    settings: &Settings,
+) -> Result<(), String> {
+    let store = app_handle
         .store(COCO_TAURI_STORE)
-        .unwrap_or_else(|e| {
-            panic!(
-                "store [{}] not found/loaded, error [{}]",
-                COCO_TAURI_STORE, e
-            )
-        });
-
-    let old_value = match store
-        .get(SETTINGS_ALLOW_SELF_SIGNATURE)
-        .expect("should be initialized upon first get call")
-    {
-        Json::Bool(b) => b,
-        _ => unreachable!(
-            "{} should be stored in a boolean",
-            SETTINGS_ALLOW_SELF_SIGNATURE
+        .map_err(|e| e.to_string())?;
+
+    store.set(SETTINGS_ALLOW_SELF_SIGNATURE, settings.allow_self_signature);
+    store.set(
+        SETTINGS_OFFLINE_CACHE_ENABLED,
+        settings.offline_cache_enabled,
+    );
+    store.set(SETTINGS_PROXY, stored_proxy(&settings.proxy)?);
+    store.set(COCO_GLOBAL_SHORTCUT, settings.global_shortcut.as_str());
+    store.set(
+        SETTINGS_ACTION_SHORTCUTS,
+        serde_json::to_value(&settings.action_shortcuts)
+            .expect("Failed to serialize action shortcuts"),
+    );
+    store.set(
+        SETTINGS_AUTOSTART,
+        serde_json::to_value(&settings.autostart).expect("Failed to serialize autostart settings"),
+    );
+    store.set(
+        SETTINGS_LOCAL_API,
+        serde_json::to_value(&settings.local_api).expect("Failed to serialize local API settings"),
+    );
+
+    Ok(())
+}
+
+/// Load the settings from the Tauri store, called upon app start before they
+/// are used.
+pub(crate) fn load_settings<R: Runtime>(app_handle: &AppHandle<R>) {
+    let store = match app_handle.store(COCO_TAURI_STORE) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!("failed to load the settings, using the defaults: {}", e);
+            return;
+        }
+    };
+
+    let defaults = Settings::default();
+    let mut settings = Settings {
+        allow_self_signature: read_setting(
+            store.get(SETTINGS_ALLOW_SELF_SIGNATURE),
+            SETTINGS_ALLOW_SELF_SIGNATURE,
+            defaults.allow_self_signature,
+        ),
+        offline_cache_enabled: read_setting(
+            store.get(SETTINGS_OFFLINE_CACHE_ENABLED),
+            SETTINGS_OFFLINE_CACHE_ENABLED,
+            defaults.offline_cache_enabled,
+        ),
+        proxy: read_setting(
+            load_proxy(store.get(SETTINGS_PROXY)),
+            SETTINGS_PROXY,
+            defaults.proxy.clone(),
+        ),
+        global_shortcut: read_setting(
+            store.get(COCO_GLOBAL_SHORTCUT),
+            COCO_GLOBAL_SHORTCUT,
+            defaults.global_shortcut.clone(),
+        ),
+        action_shortcuts: read_setting(
+            store.get(SETTINGS_ACTION_SHORTCUTS),
+            SETTINGS_ACTION_SHORTCUTS,
+            defaults.action_shortcuts.clone(),
+        ),
+        autostart: read_setting(
+            store.get(SETTINGS_AUTOSTART),
+            SETTINGS_AUTOSTART,
+            defaults.autostart.clone(),
+        ),
+        local_api: read_setting(
+            store.get(SETTINGS_LOCAL_API),
+            SETTINGS_LOCAL_API,
+            defaults.local_api.clone(),
         ),
     };
 
-    if old_value == value {
-        return;
+    if crate::server::proxy::validate_proxy_config(&settings.proxy).is_err() {
+        log::warn!("invalid proxy settings, falling back to the defaults");
+        settings.proxy = defaults.proxy;
+    }
+    if settings.global_shortcut.parse::<Shortcut>().is_err() {
+        log::warn!(
+            "invalid shortcut [{}], falling back to the default",
+            settings.global_shortcut
+        );
+        settings.global_shortcut = defaults.global_shortcut;
+    }
+    if let Err(e) = shortcut::validate_shortcuts(&settings) {
+        log::warn!("{}, falling back to the default action shortcuts", e);
+        settings.action_shortcuts = defaults.action_shortcuts;
+    }
+    if let Err(e) = settings.autostart.validate() {
+        log::warn!("{}, falling back to the default autostart settings", e);
+        settings.autostart = defaults.autostart;
+    }
+    if let Err(e) = settings.local_api.validate() {
+        log::warn!("{}, falling back to the default local API settings", e);
+        settings.local_api = defaults.local_api;
     }
 
-    store.set(SETTINGS_ALLOW_SELF_SIGNATURE, value);
+    // Store the defaults of the missing settings
+    if let Err(e) = write_settings(app_handle, &settings) {
+        log::error!("failed to save the settings: {}", e);
+    }
+    *SETTINGS.write().unwrap() = settings;
+}
+
+/// Change the settings with `change`, then validate, persist and apply them.
+/// Return the new settings.
+pub(crate) fn update<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    change: impl FnOnce(&mut Settings) -> Result<(), String>,
+) -> Result<Settings, String> {
+    let (previous, current) = {
+        let mut guard = SETTINGS.write().unwrap();
+        let previous = guard.clone();
+        let mut current = previous.clone();
+        change(&mut current)?;
+        if current == previous {
+            return Ok(current);
+        }
+
+        current.validate()?;
+        write_settings(app_handle, &current)?;
+        *guard = current.clone();
+
+        (previous, current)
+    };
 
-    let mut guard = http_client::HTTP_CLIENT.lock().await;
-    *guard = http_client::new_reqwest_http_client(value)
+    for subscriber in SUBSCRIBERS.read().unwrap().iter() {
+        subscriber(&previous, &current);
+    }
+    let _ = app_handle.emit(EVENT_SETTINGS_CHANGED, &current);
+
+    Ok(current)
 }
 
-/// Synchronous version of `async get_allow_self_signature()`.
-pub fn _get_allow_self_signature<R: Runtime>(tauri_app_handle: AppHandle<R>) -> bool {
-    let store = tauri_app_handle
-        .store(COCO_TAURI_STORE)
-        .unwrap_or_else(|e| {
-            panic!(
-                "store [{}] not found/loaded, error [{}]",
-                COCO_TAURI_STORE, e
-            )
-        });
-    if !store.has(SETTINGS_ALLOW_SELF_SIGNATURE) {
-        // default to false
-        store.set(SETTINGS_ALLOW_SELF_SIGNATURE, false);
+#[tauri::command]
+pub async fn get_settings() -> Settings {
+    settings()
+}
+
+/// Update the settings present in `changes`, an object of the same shape as
+/// [`Settings`], the other ones are left untouched.
+#[tauri::command]
+pub async fn update_settings<R: Runtime>(
+    app_handle: AppHandle<R>,
+    changes: Json,
+) -> Result<Settings, String> {
+    let Json::Object(changes) = changes else {
+        return Err("the changes should be an object".into());
+    };
+
+    update(&app_handle, |settings| {
+        let mut json = serde_json::to_value(&*settings).expect("Failed to serialize settings");
+        let fields = json.as_object_mut().expect("settings are an object");
+        for (key, value) in changes {
+            if !fields.contains_key(&key) {
+                return Err(format!("unknown setting [{}]", key));
+            }
+            fields.insert(key, value);
+        }
+
+        *settings = serde_json::from_value(json).map_err(|e| format!("invalid settings: {}", e))?;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+pub async fn set_allow_self_signature<R: Runtime>(tauri_app_handle: AppHandle<R>, value: bool) {
+    if let Err(e) = update(&tauri_app_handle, |settings| {
+        settings.allow_self_signature = value;
+        Ok(())
+    }) {
+        log::error!("failed to update the settings: {}", e);
     }
+}
 
-    match store
-        .get(SETTINGS_ALLOW_SELF_SIGNATURE)
-        .expect("should be Some")
-    {
-        Json::Bool(b) => b,
-        _ => unreachable!(
-            "{} should be stored in a boolean",
-            SETTINGS_ALLOW_SELF_SIGNATURE
-        ),
+#[tauri::command]
+pub async fn get_allow_self_signature() -> bool {
+    settings().allow_self_signature
+}
+
+#[tauri::command]
+pub async fn set_offline_cache_enabled<R: Runtime>(tauri_app_handle: AppHandle<R>, value: bool) {
+    if let Err(e) = update(&tauri_app_handle, |settings| {
+        settings.offline_cache_enabled = value;
+        Ok(())
+    }) {
+        log::error!("failed to update the settings: {}", e);
     }
 }
 
 #[tauri::command]
-pub async fn get_allow_self_signature<R: Runtime>(tauri_app_handle: AppHandle<R>) -> bool {
-    _get_allow_self_signature(tauri_app_handle)
+pub async fn get_offline_cache_enabled() -> bool {
+    settings().offline_cache_enabled
+}
+
+#[tauri::command]
+pub async fn set_proxy_config<R: Runtime>(
+    tauri_app_handle: AppHandle<R>,
+    value: ProxyConfig,
+) -> Result<(), String> {
+    update(&tauri_app_handle, |settings| {
+        settings.proxy = value;
+        Ok(())
+    })
+    .map(|_| ())
+}
+
+#[tauri::command]
+pub async fn get_proxy_config() -> ProxyConfig {
+    settings().proxy
 }
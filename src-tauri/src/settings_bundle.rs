@@ -0,0 +1,821 @@
+//! Export and import of all the settings, e.g., to move them to another
+//! machine.
+//!
+//! The settings are exported to a single JSON file, a [`SettingsBundle`]. The
+//! secrets, i.e., the tokens of the server accounts, the proxy passwords and
+//! the TLS client keys, are left out unless a passphrase is given, in which case they are exported
+//! encrypted with a key derived from it.
+//!
+//! An import validates the whole bundle before changing anything, then either
+//! merges it into the current settings or replaces them. It takes effect right
+//! away: the search sources and the hotkeys are registered again.
+
+use crate::common::register::SearchSourceRegistry;
+use crate::common::server::{ProxyConfig, Server, ServerAccessToken};
+use crate::local::application::{
+    self, TAURI_STORE_APP_ALIAS, TAURI_STORE_APP_HOTKEY,
+    TAURI_STORE_DISABLED_APP_LIST_AND_SEARCH_PATH, TAURI_STORE_KEY_DISABLED_APP_LIST,
+    TAURI_STORE_KEY_SEARCH_PATH,
+};
+use crate::local::{
+    disable_local_query_source, enable_local_query_source,
+    TAURI_STORE_LOCAL_QUERY_SOURCE_ENABLED_STATE,
+};
+use crate::policy::{apply_server_policy, policy};
+use crate::search::adaptive_timeout::{self, SEARCH_SOURCE_TIMEOUT_OVERRIDES};
+use crate::server::http_client::invalidate_server_http_client;
+use crate::server::servers::{
+    get_account_token, get_all_servers, get_server_by_id, persist_servers, persist_servers_token,
+    refresh_coco_server_info, remove_account_token, remove_coco_server, save_account_token,
+    save_server, try_register_server_to_search_source, validate_timeout_profile,
+};
+use crate::server::{proxy, tls};
+use crate::settings;
+use crate::shortcut::ActionShortcut;
+use crate::COCO_TAURI_STORE;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tauri_plugin_global_shortcut::Shortcut;
+use tauri_plugin_store::StoreExt;
+
+/// Version of the bundle format, bumped upon incompatible changes.
+pub(crate) const SETTINGS_BUNDLE_VERSION: u32 = 1;
+
+/// Iterations of PBKDF2 used to derive the key encrypting the secrets.
+const KEY_DERIVATION_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+
+/// Emitted once settings have been imported, so that the UI reloads them.
+const EVENT_SETTINGS_IMPORTED: &str = "settings-imported";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneralSettings {
+    pub global_shortcut: Option<String>,
+    pub action_shortcuts: Option<Vec<ActionShortcut>>,
+    pub allow_self_signature: Option<bool>,
+    pub offline_cache_enabled: Option<bool>,
+    /// Without its password, which is a secret.
+    pub proxy: Option<ProxyConfig>,
+    /// Timeouts of the search sources set by the user, in milliseconds.
+    pub search_source_timeouts: Option<BTreeMap<String, u64>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApplicationSettings {
+    /// Aliases by application path.
+    pub aliases: BTreeMap<String, String>,
+    /// Hotkeys by application path.
+    pub hotkeys: BTreeMap<String, String>,
+    pub disabled_apps: Vec<String>,
+    pub search_paths: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    pub version: u32,
+    /// Version of Coco that exported it.
+    pub app_version: String,
+    pub exported_at: String,
+    #[serde(default)]
+    pub general: GeneralSettings,
+    /// Without their proxy passwords. The servers managed by the policy are
+    /// not exported.
+    #[serde(default)]
+    pub servers: Vec<Server>,
+    /// Enabled state of the local query sources by ID.
+    #[serde(default)]
+    pub local_query_sources: BTreeMap<String, bool>,
+    #[serde(default)]
+    pub applications: ApplicationSettings,
+    /// `None` if no passphrase was given upon export.
+    #[serde(default)]
+    pub secrets: Option<EncryptedSecrets>,
+}
+
+/// [`Secrets`] encrypted with AES-256-GCM, using a key derived from the
+/// passphrase with PBKDF2-HMAC-SHA256. Binary fields are base64-encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedSecrets {
+    pub iterations: u32,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountToken {
+    server_id: String,
+    account_id: String,
+    token: ServerAccessToken,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Secrets {
+    tokens: Vec<AccountToken>,
+    /// Password of the global proxy.
+    proxy_password: Option<String>,
+    /// Passwords of the server proxies by server ID.
+    server_proxy_passwords: BTreeMap<String, String>,
+    /// TLS client keys by server ID.
+    server_client_keys: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Add the imported settings to the current ones, the imported ones win.
+    Merge,
+    /// Drop the current settings that are not in the bundle.
+    Replace,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub servers: usize,
+    pub tokens: usize,
+    /// What could not be imported, e.g., the servers managed by the policy.
+    pub warnings: Vec<String>,
+}
+
+fn derive_cipher(passphrase: &str, salt: &[u8], iterations: u32) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+fn encrypt_secrets(
+    secrets: &Secrets,
+    passphrase: &str,
+    iterations: u32,
+) -> Result<EncryptedSecrets, String> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let cipher = derive_cipher(passphrase, &salt, iterations);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let plaintext = serde_json::to_vec(secrets).expect("Failed to serialize secrets");
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_ref())
+        .map_err(|_| "Failed to encrypt the secrets".to_string())?;
+
+    Ok(EncryptedSecrets {
+        iterations,
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        ciphertext: base64::encode(ciphertext),
+    })
+}
+
+fn decrypt_secrets(encrypted: &EncryptedSecrets, passphrase: &str) -> Result<Secrets, String> {
+    let invalid = |e: &dyn std::fmt::Display| format!("Invalid encrypted secrets: {}", e);
+
+    let salt = base64::decode(&encrypted.salt).map_err(|e| invalid(&e))?;
+    let nonce = base64::decode(&encrypted.nonce).map_err(|e| invalid(&e))?;
+    if nonce.len() != 12 {
+        return Err(invalid(&"bad nonce"));
+    }
+    let ciphertext = base64::decode(&encrypted.ciphertext).map_err(|e| invalid(&e))?;
+
+    if encrypted.iterations == 0 || encrypted.iterations > 10 * KEY_DERIVATION_ITERATIONS {
+        return Err(invalid(&"bad iteration count"));
+    }
+
+    let cipher = derive_cipher(passphrase, &salt, encrypted.iterations);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| "Failed to decrypt the secrets, the passphrase may be wrong".to_string())?;
+
+    serde_json::from_slice(&plaintext).map_err(|e| invalid(&e))
+}
+
+/// Values of `store`, skipping the ones that are not strings.
+fn string_entries<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    store: &str,
+) -> Result<BTreeMap<String, String>, String> {
+    let store = app_handle.store(store).map_err(|e| e.to_string())?;
+
+    Ok(store
+        .entries()
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            Json::String(value) => Some((key, value)),
+            _ => None,
+        })
+        .collect())
+}
+
+fn string_array(value: Option<Json>) -> Option<Vec<String>> {
+    serde_json::from_value(value?).ok()
+}
+
+/// Collect the current settings into a bundle without secrets, the secrets are
+/// moved into `secrets` if set, or never read otherwise.
+fn collect_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    mut secrets: Option<&mut Secrets>,
+) -> Result<SettingsBundle, String> {
+    let current_settings = settings::settings();
+    let mut proxy = current_settings.proxy;
+    let proxy_password = proxy.password.take();
+    if let Some(secrets) = secrets.as_deref_mut() {
+        secrets.proxy_password = proxy_password;
+    }
+    let general = GeneralSettings {
+        global_shortcut: Some(current_settings.global_shortcut),
+        action_shortcuts: Some(current_settings.action_shortcuts),
+        allow_self_signature: Some(current_settings.allow_self_signature),
+        offline_cache_enabled: Some(current_settings.offline_cache_enabled),
+        proxy: Some(proxy),
+        search_source_timeouts: app_handle
+            .store(COCO_TAURI_STORE)
+            .map_err(|e| e.to_string())?
+            .get(SEARCH_SOURCE_TIMEOUT_OVERRIDES)
+            .and_then(|timeouts| serde_json::from_value(timeouts).ok()),
+    };
+
+    let mut servers: Vec<Server> = get_all_servers()
+        .into_iter()
+        .filter(|server| !server.locked)
+        .collect();
+    servers.sort_by(|a, b| a.id.cmp(&b.id));
+    for server in &mut servers {
+        // Runtime state, fetched again after the import
+        server.health = None;
+        server.stats = None;
+
+        let password = server
+            .proxy
+            .as_mut()
+            .and_then(|proxy| proxy.password.take());
+        let client_key = server.tls.as_mut().and_then(|tls| tls.client_key.take());
+        let Some(secrets) = secrets.as_deref_mut() else {
+            continue;
+        };
+
+        if let Some(password) = password {
+            secrets
+                .server_proxy_passwords
+                .insert(server.id.clone(), password);
+        }
+        if let Some(client_key) = client_key {
+            secrets
+                .server_client_keys
+                .insert(server.id.clone(), client_key);
+        }
+
+        let mut account_ids: Vec<String> = server
+            .accounts
+            .iter()
+            .map(|account| account.id.clone())
+            .collect();
+        if !account_ids
+            .iter()
+            .any(|id| id == server.active_account_id())
+        {
+            account_ids.push(server.active_account_id().to_string());
+        }
+        for account_id in account_ids {
+            if let Some(token) = get_account_token(&server.id, &account_id)? {
+                secrets.tokens.push(AccountToken {
+                    server_id: server.id.clone(),
+                    account_id,
+                    token,
+                });
+            }
+        }
+    }
+
+    let local_query_sources = app_handle
+        .store(TAURI_STORE_LOCAL_QUERY_SOURCE_ENABLED_STATE)
+        .map_err(|e| e.to_string())?
+        .entries()
+        .into_iter()
+        .filter_map(|(id, enabled)| enabled.as_bool().map(|enabled| (id, enabled)))
+        .collect();
+
+    let app_store = app_handle
+        .store(TAURI_STORE_DISABLED_APP_LIST_AND_SEARCH_PATH)
+        .map_err(|e| e.to_string())?;
+    let applications = ApplicationSettings {
+        aliases: string_entries(app_handle, TAURI_STORE_APP_ALIAS)?,
+        hotkeys: string_entries(app_handle, TAURI_STORE_APP_HOTKEY)?,
+        disabled_apps: string_array(app_store.get(TAURI_STORE_KEY_DISABLED_APP_LIST))
+            .unwrap_or_default(),
+        search_paths: string_array(app_store.get(TAURI_STORE_KEY_SEARCH_PATH)),
+    };
+
+    Ok(SettingsBundle {
+        version: SETTINGS_BUNDLE_VERSION,
+        app_version: app_handle.package_info().version.to_string(),
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        general,
+        servers,
+        local_query_sources,
+        applications,
+        secrets: None,
+    })
+}
+
+/// Collect the current settings, the secrets are only included, encrypted, if
+/// a `passphrase` is given.
+pub(crate) fn collect_bundle<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    passphrase: Option<&str>,
+) -> Result<SettingsBundle, String> {
+    let Some(passphrase) = passphrase else {
+        return collect_bundle_without_secrets(app_handle);
+    };
+
+    let mut secrets = Secrets::default();
+    let mut bundle = collect_settings(app_handle, Some(&mut secrets))?;
+    bundle.secrets = Some(encrypt_secrets(
+        &secrets,
+        passphrase,
+        KEY_DERIVATION_ITERATIONS,
+    )?);
+
+    Ok(bundle)
+}
+
+/// Collect the current settings without reading any secret, e.g., for the
+/// sync.
+pub(crate) fn collect_bundle_without_secrets<R: Runtime>(
+    app_handle: &AppHandle<R>,
+) -> Result<SettingsBundle, String> {
+    collect_settings(app_handle, None)
+}
+
+fn parse_shortcut(shortcut: &str) -> Result<(), String> {
+    shortcut
+        .parse::<Shortcut>()
+        .map(|_| ())
+        .map_err(|_| format!("invalid shortcut {}", shortcut))
+}
+
+/// Check everything in `bundle` before any of it gets applied.
+pub(crate) fn validate_bundle(bundle: &SettingsBundle) -> Result<(), String> {
+    if bundle.version == 0 || bundle.version > SETTINGS_BUNDLE_VERSION {
+        return Err(format!(
+            "The settings were exported by an unsupported version of Coco ({})",
+            bundle.app_version
+        ));
+    }
+
+    let general = &bundle.general;
+    if let Some(shortcut) = &general.global_shortcut {
+        parse_shortcut(shortcut)?;
+    }
+    for action_shortcut in general.action_shortcuts.iter().flatten() {
+        parse_shortcut(&action_shortcut.shortcut)?;
+    }
+    if let Some(proxy) = &general.proxy {
+        proxy::validate_proxy_config(proxy)?;
+    }
+    if let Some(timeouts) = &general.search_source_timeouts {
+        if timeouts.values().any(|timeout| *timeout == 0) {
+            return Err("timeouts should be greater than 0".into());
+        }
+    }
+
+    let mut server_ids = HashSet::new();
+    for server in &bundle.servers {
+        if server.id.trim().is_empty() {
+            return Err("A server has no ID".into());
+        }
+        if !server_ids.insert(server.id.as_str()) {
+            return Err(format!("Server [{}] is listed twice", server.id));
+        }
+        let endpoint = url::Url::parse(&server.endpoint)
+            .map_err(|e| format!("Bad endpoint of server [{}]: {}", server.id, e))?;
+        if !matches!(endpoint.scheme(), "http" | "https") {
+            return Err(format!("Server [{}] is not an HTTP(S) endpoint", server.id));
+        }
+        if let Some(tls) = &server.tls {
+            tls::validate_tls_config(tls)?;
+        }
+        if let Some(proxy) = &server.proxy {
+            proxy::validate_proxy_config(proxy)?;
+        }
+        if let Some(timeouts) = &server.timeouts {
+            validate_timeout_profile(&timeouts.normal)?;
+            validate_timeout_profile(&timeouts.streaming)?;
+        }
+    }
+
+    let mut hotkeys = HashSet::new();
+    for (app_path, hotkey) in &bundle.applications.hotkeys {
+        parse_shortcut(hotkey)?;
+        if !hotkeys.insert(hotkey.to_lowercase())
+            || general.global_shortcut.as_deref() == Some(hotkey.as_str())
+        {
+            return Err(format!(
+                "Hotkey [{}] of [{}] is already in use",
+                hotkey, app_path
+            ));
+        }
+    }
+
+    if bundle.local_query_sources.keys().any(|id| id.is_empty()) {
+        return Err("A local query source has no ID".into());
+    }
+
+    Ok(())
+}
+
+/// The password of the imported `proxy`: the exported one if any, otherwise
+/// the one of `current`, so that importing without the secrets does not
+/// remove it.
+fn imported_proxy_password(
+    proxy: &ProxyConfig,
+    exported: Option<String>,
+    current: &ProxyConfig,
+) -> Option<String> {
+    if proxy.username.is_none() {
+        return None;
+    }
+
+    exported.or_else(|| current.password.clone())
+}
+
+pub(crate) fn import_general_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    general: GeneralSettings,
+    mode: ImportMode,
+    secrets: Option<&Secrets>,
+) -> Result<(), String> {
+    let proxy_password = secrets.and_then(|secrets| secrets.proxy_password.clone());
+    settings::update(app_handle, |settings| {
+        if let Some(shortcut) = general.global_shortcut {
+            settings.global_shortcut = shortcut;
+        }
+        if let Some(action_shortcuts) = general.action_shortcuts {
+            settings.action_shortcuts = action_shortcuts;
+        }
+        if let Some(value) = general.allow_self_signature {
+            settings.allow_self_signature = value;
+        }
+        if let Some(value) = general.offline_cache_enabled {
+            settings.offline_cache_enabled = value;
+        }
+        if let Some(mut proxy) = general.proxy {
+            proxy.password = imported_proxy_password(&proxy, proxy_password, &settings.proxy);
+            settings.proxy = proxy;
+        }
+        Ok(())
+    })?;
+
+    if let Some(timeouts) = general.search_source_timeouts {
+        let store = app_handle
+            .store(COCO_TAURI_STORE)
+            .map_err(|e| e.to_string())?;
+        let mut overrides = match (mode, store.get(SEARCH_SOURCE_TIMEOUT_OVERRIDES)) {
+            (ImportMode::Merge, Some(Json::Object(overrides))) => overrides,
+            _ => serde_json::Map::new(),
+        };
+        for (source_id, timeout) in timeouts {
+            overrides.insert(source_id, Json::from(timeout));
+        }
+        store.set(SEARCH_SOURCE_TIMEOUT_OVERRIDES, Json::Object(overrides));
+        adaptive_timeout::load_timeout_overrides(app_handle);
+    }
+
+    Ok(())
+}
+
+/// Restore the TLS client keys of `servers`, which are secrets: the exported
+/// ones if any, otherwise the current ones if the certificate is the same. A
+/// certificate whose key is unknown is left out.
+fn restore_client_keys(
+    servers: &mut [Server],
+    secrets: Option<&Secrets>,
+    summary: &mut ImportSummary,
+) {
+    for server in servers {
+        let Some(tls) = server.tls.as_mut() else {
+            continue;
+        };
+        // Older versions exported the key in plain text
+        if tls.client_certificate.is_none() || tls.client_key.is_some() {
+            continue;
+        }
+
+        let exported = secrets.and_then(|secrets| secrets.server_client_keys.get(&server.id));
+        let current = get_server_by_id(&server.id)
+            .and_then(|current| current.tls)
+            .filter(|current| current.client_certificate == tls.client_certificate)
+            .and_then(|current| current.client_key);
+        tls.client_key = exported.cloned().or(current);
+
+        if tls.client_key.is_none() {
+            tls.client_certificate = None;
+            summary.warnings.push(format!(
+                "The client certificate of server [{}] was not imported, its key needs the export passphrase",
+                server.name
+            ));
+        }
+    }
+}
+
+/// Import `servers`, return the IDs of the imported ones.
+async fn import_servers<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    servers: Vec<Server>,
+    mode: ImportMode,
+    secrets: Option<&Secrets>,
+    summary: &mut ImportSummary,
+) -> Result<Vec<String>, String> {
+    if mode == ImportMode::Replace {
+        for server in get_all_servers() {
+            if !server.locked && !servers.iter().any(|s| s.id == server.id) {
+                log::info!("removing server [{}] replaced by the import", server.id);
+                remove_coco_server(app_handle.clone(), server.id).await?;
+            }
+        }
+    }
+
+    let registry = app_handle.state::<SearchSourceRegistry>();
+    let mut imported = Vec::new();
+    for mut server in servers {
+        let existing = get_server_by_id(&server.id);
+        if existing.as_ref().is_some_and(|existing| existing.locked) {
+            summary.warnings.push(format!(
+                "Server [{}] is managed by your administrator",
+                server.name
+            ));
+            continue;
+        }
+        server.endpoint = server.endpoint.trim_end_matches('/').to_string();
+        if get_all_servers()
+            .iter()
+            .any(|other| other.id != server.id && other.endpoint == server.endpoint)
+        {
+            summary.warnings.push(format!(
+                "Server [{}] has already been added",
+                server.endpoint
+            ));
+            continue;
+        }
+
+        server.locked = false;
+        server.needs_relogin = false;
+        server.incompatible_reason = None;
+        let exported = secrets
+            .and_then(|secrets| secrets.server_proxy_passwords.get(&server.id))
+            .cloned();
+        if let Some(proxy) = server.proxy.as_mut() {
+            let current = existing
+                .as_ref()
+                .and_then(|existing| existing.proxy.clone())
+                .unwrap_or_default();
+            proxy.password = imported_proxy_password(proxy, exported, &current);
+        }
+        apply_server_policy(&mut server);
+
+        // The accounts that are not in the bundle are gone
+        if let Some(existing) = existing {
+            for account in &existing.accounts {
+                if !server.accounts.iter().any(|a| a.id == account.id) {
+                    remove_account_token(&server.id, &account.id);
+                }
+            }
+        }
+
+        registry.remove_source(&server.id).await;
+        invalidate_server_http_client(&server.id);
+        save_server(&server);
+        imported.push(server.id);
+    }
+    summary.servers = imported.len();
+
+    if let Some(secrets) = secrets {
+        for token in &secrets.tokens {
+            if imported.contains(&token.server_id) {
+                save_account_token(
+                    token.server_id.clone(),
+                    token.account_id.clone(),
+                    token.token.clone(),
+                );
+                summary.tokens += 1;
+            }
+        }
+    }
+
+    persist_servers(app_handle).await?;
+    persist_servers_token(app_handle)?;
+
+    Ok(imported)
+}
+
+pub(crate) async fn import_local_query_sources<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    local_query_sources: BTreeMap<String, bool>,
+    summary: &mut ImportSummary,
+) {
+    for (id, enabled) in local_query_sources {
+        if !enabled {
+            disable_local_query_source(app_handle.clone(), id).await;
+        } else if policy().is_local_source_disabled(&id) {
+            summary
+                .warnings
+                .push(format!("[{}] is disabled by your administrator", id));
+        } else if let Err(e) = enable_local_query_source(app_handle.clone(), id).await {
+            summary.warnings.push(e);
+        }
+    }
+}
+
+pub(crate) fn import_applications<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    applications: ApplicationSettings,
+    mode: ImportMode,
+    summary: &mut ImportSummary,
+) -> Result<(), String> {
+    let previous_hotkeys: Vec<String> = string_entries(app_handle, TAURI_STORE_APP_HOTKEY)?
+        .into_values()
+        .collect();
+
+    let alias_store = app_handle
+        .store(TAURI_STORE_APP_ALIAS)
+        .map_err(|e| e.to_string())?;
+    let hotkey_store = app_handle
+        .store(TAURI_STORE_APP_HOTKEY)
+        .map_err(|e| e.to_string())?;
+    if mode == ImportMode::Replace {
+        alias_store.clear();
+        hotkey_store.clear();
+    }
+    for (app_path, alias) in applications.aliases {
+        alias_store.set(app_path, alias);
+    }
+    for (app_path, hotkey) in applications.hotkeys {
+        hotkey_store.set(app_path, hotkey);
+    }
+
+    let app_store = app_handle
+        .store(TAURI_STORE_DISABLED_APP_LIST_AND_SEARCH_PATH)
+        .map_err(|e| e.to_string())?;
+    let mut disabled_apps = match mode {
+        ImportMode::Merge => {
+            string_array(app_store.get(TAURI_STORE_KEY_DISABLED_APP_LIST)).unwrap_or_default()
+        }
+        ImportMode::Replace => Vec::new(),
+    };
+    for app_path in applications.disabled_apps {
+        if !disabled_apps.contains(&app_path) {
+            disabled_apps.push(app_path);
+        }
+    }
+    app_store.set(TAURI_STORE_KEY_DISABLED_APP_LIST, disabled_apps);
+
+    if let Some(imported_search_paths) = applications.search_paths {
+        let mut search_paths = match mode {
+            ImportMode::Merge => {
+                string_array(app_store.get(TAURI_STORE_KEY_SEARCH_PATH)).unwrap_or_default()
+            }
+            ImportMode::Replace => Vec::new(),
+        };
+        for search_path in imported_search_paths {
+            if !search_paths.contains(&search_path) {
+                search_paths.push(search_path);
+            }
+        }
+        app_store.set(TAURI_STORE_KEY_SEARCH_PATH, search_paths);
+    }
+
+    if let Err(e) = application::reload_app_hotkeys(app_handle, &previous_hotkeys) {
+        log::warn!("failed to register the imported application hotkeys: {}", e);
+        summary.warnings.push(format!(
+            "Some application hotkeys could not be registered: {}",
+            e
+        ));
+    }
+
+    Ok(())
+}
+
+/// Export all the settings to the file at `path`. The secrets are exported
+/// only if a `passphrase` is given, encrypted with it.
+#[tauri::command]
+pub async fn export_settings<R: Runtime>(
+    app_handle: AppHandle<R>,
+    path: String,
+    passphrase: Option<String>,
+) -> Result<(), String> {
+    let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty());
+    // Deriving the key is slow on purpose
+    let bundle = {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            collect_bundle(&app_handle, passphrase.as_deref())
+        })
+        .await
+        .map_err(|e| e.to_string())??
+    };
+
+    let json = serde_json::to_string_pretty(&bundle).expect("Failed to serialize settings");
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write [{}]: {}", path, e))?;
+
+    log::info!(
+        "exported the settings to [{}], with secrets: {}",
+        path,
+        bundle.secrets.is_some()
+    );
+
+    Ok(())
+}
+
+/// Import the settings from the file at `path`, exported by
+/// [`export_settings()`]. `passphrase` is needed to import the secrets.
+#[tauri::command]
+pub async fn import_settings<R: Runtime>(
+    app_handle: AppHandle<R>,
+    path: String,
+    mode: ImportMode,
+    passphrase: Option<String>,
+) -> Result<ImportSummary, String> {
+    let contents =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read [{}]: {}", path, e))?;
+    let mut bundle: SettingsBundle =
+        serde_json::from_str(&contents).map_err(|e| format!("Invalid settings file: {}", e))?;
+
+    let mut summary = ImportSummary::default();
+    let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty());
+    let secrets = match (&bundle.secrets, passphrase) {
+        (Some(encrypted), Some(passphrase)) => {
+            // Deriving the key is slow on purpose
+            let encrypted = encrypted.clone();
+            let secrets = tauri::async_runtime::spawn_blocking(move || {
+                decrypt_secrets(&encrypted, &passphrase)
+            })
+            .await
+            .map_err(|e| e.to_string())??;
+            Some(secrets)
+        }
+        (Some(_), None) => {
+            summary.warnings.push(
+                "The tokens and passwords were not imported, they need the export passphrase"
+                    .to_string(),
+            );
+            None
+        }
+        (None, _) => None,
+    };
+    restore_client_keys(&mut bundle.servers, secrets.as_ref(), &mut summary);
+    validate_bundle(&bundle)?;
+
+    log::info!("importing the settings from [{}] ({:?})", path, mode);
+
+    import_general_settings(&app_handle, bundle.general, mode, secrets.as_ref())?;
+    let imported_servers = import_servers(
+        &app_handle,
+        bundle.servers,
+        mode,
+        secrets.as_ref(),
+        &mut summary,
+    )
+    .await?;
+    import_local_query_sources(&app_handle, bundle.local_query_sources, &mut summary).await;
+    import_applications(&app_handle, bundle.applications, mode, &mut summary)?;
+
+    for id in imported_servers {
+        if let Some(server) = get_server_by_id(&id) {
+            try_register_server_to_search_source(app_handle.clone(), &server).await;
+        }
+
+        // Fetch its info in the background, it may have changed since the
+        // export
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = refresh_coco_server_info(app_handle, id.clone()).await {
+                log::warn!("failed to fetch the info of server [{}]: {}", id, e);
+            }
+        });
+    }
+
+    let _ = app_handle.emit(EVENT_SETTINGS_IMPORTED, ());
+
+    Ok(summary)
+}
+
+#[test]
+fn test_encrypt_secrets() {
+    let secrets = Secrets {
+        proxy_password: Some("hunter2".to_string()),
+        ..Default::default()
+    };
+
+    let encrypted = encrypt_secrets(&secrets, "passphrase", 1_000).unwrap();
+    assert!(!encrypted.ciphertext.contains("hunter2"));
+
+    let decrypted = decrypt_secrets(&encrypted, "passphrase").unwrap();
+    assert_eq!(decrypted.proxy_password.as_deref(), Some("hunter2"));
+    assert!(decrypt_secrets(&encrypted, "wrong passphrase").is_err());
+}
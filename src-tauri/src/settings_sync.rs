@@ -0,0 +1,678 @@
+//! Opt-in sync of the settings across devices, through a Coco server.
+//!
+//! The portable part of the [settings bundle](crate::settings_bundle), i.e.,
+//! the general settings but the proxy and the TLS ones, the local query
+//! sources and the
+//! application settings, is flattened into keys such as
+//! `applications.aliases.<app path>`, every key carrying the time it was last
+//! changed. Upon sync, the local keys are compared to the ones of the previous
+//! sync to find out which changed, the document stored in the preferences of
+//! the user profile on the server is pulled, the newest value of every key
+//! wins, and the result is applied locally and pushed back.
+//!
+//! Servers, proxies, TLS settings and secrets are never synced, they are
+//! specific to a device or its network. The synced values that cannot be
+//! applied on this device, e.g., a hotkey already in use, are ignored.
+
+use crate::common::http::get_response_body_text;
+use crate::server::http_client::HttpClient;
+use crate::server::servers::get_server_by_id;
+use crate::settings;
+use crate::settings_bundle::{
+    collect_bundle_without_secrets, import_applications, import_general_settings,
+    import_local_query_sources, validate_bundle, ApplicationSettings, GeneralSettings, ImportMode,
+    ImportSummary, SettingsBundle, SETTINGS_BUNDLE_VERSION,
+};
+use crate::COCO_TAURI_STORE;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Runtime};
+use tauri_plugin_store::StoreExt;
+
+/// Key of the ID of the server the settings are synced through, absent if the
+/// sync is off.
+const SETTINGS_SYNC_SERVER: &str = "settings_sync_server";
+
+/// Store holding the state of the previous sync.
+pub(crate) const TAURI_STORE_SETTINGS_SYNC_STATE: &str = "settings_sync_state";
+const SYNC_STATE_KEY_ENTRIES: &str = "entries";
+const SYNC_STATE_KEY_LAST_SYNCED_AT: &str = "last_synced_at";
+const SYNC_STATE_KEY_LAST_ERROR: &str = "last_error";
+
+/// The profile of the user, as fetched by
+/// [`get_user_profiles()`](crate::server::profile::get_user_profiles), the
+/// synced settings are stored in its `preferences`.
+const PROFILE_PATH: &str = "/account/profile";
+/// Key of the synced settings in the preferences of the user.
+const PREFERENCES_KEY_SETTINGS: &str = "coco_app_settings";
+
+const SYNC_DOCUMENT_VERSION: u32 = 1;
+/// Give the servers some time to come online after the app starts.
+const INITIAL_SYNC_DELAY: Duration = Duration::from_secs(15);
+const SYNC_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Emitted once synced settings have been applied, so that the UI reloads
+/// them.
+const EVENT_SETTINGS_SYNCED: &str = "settings-synced";
+
+lazy_static! {
+    /// Held during a sync, so that the periodic and the manual ones do not
+    /// overlap.
+    static ref SYNC_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SyncEntry {
+    /// `null` if the key was removed.
+    value: Json,
+    /// Unix timestamp in milliseconds.
+    updated_at: i64,
+}
+
+type SyncEntries = BTreeMap<String, SyncEntry>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncDocument {
+    version: u32,
+    entries: SyncEntries,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsSyncStatus {
+    /// ID of the server the settings are synced through, `None` if the sync is
+    /// off.
+    pub server_id: Option<String>,
+    /// Unix timestamp in milliseconds.
+    pub last_synced_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+/// Flatten the synced settings into keys.
+fn flatten(
+    general: &GeneralSettings,
+    local_query_sources: &BTreeMap<String, bool>,
+    applications: &ApplicationSettings,
+) -> BTreeMap<String, Json> {
+    let mut keys = BTreeMap::new();
+
+    if let Some(shortcut) = &general.global_shortcut {
+        keys.insert(
+            "general.global_shortcut".to_string(),
+            Json::from(shortcut.as_str()),
+        );
+    }
+    if let Some(value) = general.offline_cache_enabled {
+        keys.insert(
+            "general.offline_cache_enabled".to_string(),
+            Json::from(value),
+        );
+    }
+    for (source_id, timeout) in general.search_source_timeouts.iter().flatten() {
+        keys.insert(
+            format!("general.search_source_timeouts.{}", source_id),
+            Json::from(*timeout),
+        );
+    }
+
+    for (id, enabled) in local_query_sources {
+        keys.insert(format!("local_query_sources.{}", id), Json::from(*enabled));
+    }
+
+    for (app_path, alias) in &applications.aliases {
+        keys.insert(
+            format!("applications.aliases.{}", app_path),
+            Json::from(alias.as_str()),
+        );
+    }
+    for (app_path, hotkey) in &applications.hotkeys {
+        keys.insert(
+            format!("applications.hotkeys.{}", app_path),
+            Json::from(hotkey.as_str()),
+        );
+    }
+    for app_path in &applications.disabled_apps {
+        keys.insert(
+            format!("applications.disabled_apps.{}", app_path),
+            Json::Bool(true),
+        );
+    }
+    if let Some(search_paths) = &applications.search_paths {
+        keys.insert(
+            "applications.search_paths".to_string(),
+            Json::from(search_paths.clone()),
+        );
+    }
+
+    keys
+}
+
+/// Struct-shaped synced settings, see [`unflatten()`].
+type Unflattened = (GeneralSettings, BTreeMap<String, bool>, ApplicationSettings);
+
+fn empty_unflattened() -> Unflattened {
+    let general = GeneralSettings {
+        search_source_timeouts: Some(BTreeMap::new()),
+        ..Default::default()
+    };
+
+    (general, BTreeMap::new(), ApplicationSettings::default())
+}
+
+/// Set the value of `key` in `settings`, `None` if the key or its value is
+/// invalid.
+fn unflatten_entry(key: &str, value: &Json, settings: &mut Unflattened) -> Option<()> {
+    let (general, local_query_sources, applications) = settings;
+
+    let mut parts = key.splitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("general"), Some("global_shortcut"), None) => value
+            .as_str()
+            .map(|shortcut| general.global_shortcut = Some(shortcut.to_string())),
+        (Some("general"), Some("offline_cache_enabled"), None) => value
+            .as_bool()
+            .map(|value| general.offline_cache_enabled = Some(value)),
+        (Some("general"), Some("search_source_timeouts"), Some(source_id)) => {
+            value.as_u64().map(|timeout| {
+                general
+                    .search_source_timeouts
+                    .get_or_insert_with(BTreeMap::new)
+                    .insert(source_id.to_string(), timeout);
+            })
+        }
+        (Some("local_query_sources"), Some(id), rest) => {
+            let id = match rest {
+                Some(rest) => format!("{}.{}", id, rest),
+                None => id.to_string(),
+            };
+            value.as_bool().map(|enabled| {
+                local_query_sources.insert(id, enabled);
+            })
+        }
+        (Some("applications"), Some("aliases"), Some(app_path)) => value.as_str().map(|alias| {
+            applications
+                .aliases
+                .insert(app_path.to_string(), alias.to_string());
+        }),
+        (Some("applications"), Some("hotkeys"), Some(app_path)) => value.as_str().map(|hotkey| {
+            applications
+                .hotkeys
+                .insert(app_path.to_string(), hotkey.to_string());
+        }),
+        (Some("applications"), Some("disabled_apps"), Some(app_path)) => value
+            .as_bool()
+            .filter(|disabled| *disabled)
+            .map(|_| applications.disabled_apps.push(app_path.to_string())),
+        (Some("applications"), Some("search_paths"), None) => {
+            serde_json::from_value::<Vec<String>>(value.clone())
+                .ok()
+                .map(|search_paths| applications.search_paths = Some(search_paths))
+        }
+        _ => None,
+    }
+}
+
+/// The reverse of [`flatten()`], the removed keys and the invalid values are
+/// skipped.
+fn unflatten(entries: &SyncEntries) -> Unflattened {
+    let mut settings = empty_unflattened();
+
+    for (key, entry) in entries {
+        if entry.value.is_null() {
+            continue;
+        }
+        if unflatten_entry(key, &entry.value, &mut settings).is_none() {
+            log::warn!("ignoring synced setting [{}]: {}", key, entry.value);
+        }
+    }
+
+    settings
+}
+
+/// Check that `unflattened` can be applied on top of the current ones.
+fn validate_unflattened(unflattened: &Unflattened) -> Result<(), String> {
+    let (general, local_query_sources, applications) = unflattened.clone();
+
+    if let Some(shortcut) = &general.global_shortcut {
+        let mut current = settings::settings();
+        current.global_shortcut = shortcut.clone();
+        current.validate()?;
+    }
+
+    validate_bundle(&SettingsBundle {
+        version: SETTINGS_BUNDLE_VERSION,
+        app_version: String::new(),
+        exported_at: String::new(),
+        general,
+        servers: Vec::new(),
+        local_query_sources,
+        applications,
+        secrets: None,
+    })
+}
+
+/// The `merged` entries that can be applied on this device. An invalid entry,
+/// e.g., a bad shortcut or a hotkey already in use, is replaced with the
+/// `local` one, or dropped, rather than failing the whole sync.
+///
+/// The global shortcut is checked first, so that it wins over the hotkeys it
+/// conflicts with.
+fn applicable_entries(merged: &SyncEntries, local: &SyncEntries) -> SyncEntries {
+    let mut keys: Vec<&String> = merged.keys().collect();
+    keys.sort_by_key(|key| key.as_str() != "general.global_shortcut");
+
+    let mut accepted = empty_unflattened();
+    let mut applicable = SyncEntries::new();
+    for key in keys {
+        let candidates = [Some(&merged[key]), local.get(key)];
+        for entry in candidates.into_iter().flatten() {
+            if entry.value.is_null() {
+                applicable.insert(key.clone(), entry.clone());
+                break;
+            }
+
+            let mut candidate = accepted.clone();
+            let result = unflatten_entry(key, &entry.value, &mut candidate)
+                .ok_or_else(|| "invalid value".to_string())
+                .and_then(|()| validate_unflattened(&candidate));
+            match result {
+                Ok(()) => {
+                    accepted = candidate;
+                    applicable.insert(key.clone(), entry.clone());
+                    break;
+                }
+                Err(e) => log::warn!("ignoring synced setting [{}]: {}", key, e),
+            }
+        }
+    }
+
+    applicable
+}
+
+/// Timestamp the `current` local values against the entries of the previous
+/// sync: the values that changed since then, and the removed keys, are
+/// stamped with `now`.
+///
+/// Before the first sync, nothing is known about when the local values were
+/// set, so they are stamped with 0 and lose to the ones of the server.
+fn stamp_local_entries(
+    previous: &SyncEntries,
+    current: BTreeMap<String, Json>,
+    now: i64,
+) -> SyncEntries {
+    let first_sync = previous.is_empty();
+    let mut entries = SyncEntries::new();
+
+    for (key, entry) in previous {
+        if !current.contains_key(key) {
+            let entry = if entry.value.is_null() {
+                entry.clone()
+            } else {
+                SyncEntry {
+                    value: Json::Null,
+                    updated_at: now,
+                }
+            };
+            entries.insert(key.clone(), entry);
+        }
+    }
+
+    for (key, value) in current {
+        let updated_at = match previous.get(&key) {
+            Some(entry) if entry.value == value => entry.updated_at,
+            _ if first_sync => 0,
+            _ => now,
+        };
+        entries.insert(key, SyncEntry { value, updated_at });
+    }
+
+    entries
+}
+
+/// Merge the `local` and the `remote` entries, the most recently updated value
+/// of every key wins, the local one upon a tie.
+fn merge_entries(local: SyncEntries, remote: SyncEntries) -> SyncEntries {
+    let mut merged = remote;
+
+    for (key, entry) in local {
+        match merged.get(&key) {
+            Some(remote_entry) if remote_entry.updated_at > entry.updated_at => {}
+            _ => {
+                merged.insert(key, entry);
+            }
+        }
+    }
+
+    merged
+}
+
+fn sync_server_id<R: Runtime>(app_handle: &AppHandle<R>) -> Option<String> {
+    app_handle
+        .store(COCO_TAURI_STORE)
+        .ok()?
+        .get(SETTINGS_SYNC_SERVER)
+        .and_then(|id| id.as_str().map(str::to_string))
+}
+
+/// Pull the synced entries from the preferences of the user, which are
+/// returned as well so that the other preferences are pushed back unchanged.
+async fn pull_document(
+    server_id: &str,
+) -> Result<(SyncEntries, serde_json::Map<String, Json>), String> {
+    let response = HttpClient::get(server_id, PROFILE_PATH, None).await?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to pull the settings: status {}",
+            response.status()
+        ));
+    }
+
+    let body = get_response_body_text(response).await?;
+    let mut profile: Json =
+        serde_json::from_str(&body).map_err(|e| format!("Invalid user profile: {}", e))?;
+    let mut preferences = match profile.get_mut("preferences").map(Json::take) {
+        Some(Json::Object(preferences)) => preferences,
+        _ => serde_json::Map::new(),
+    };
+
+    // Nothing has been synced yet
+    let document = match preferences.remove(PREFERENCES_KEY_SETTINGS) {
+        None | Some(Json::Null) => return Ok((SyncEntries::new(), preferences)),
+        Some(document) => document,
+    };
+    let document: SyncDocument =
+        serde_json::from_value(document).map_err(|e| format!("Invalid synced settings: {}", e))?;
+    if document.version > SYNC_DOCUMENT_VERSION {
+        return Err("The settings were synced by a newer version of Coco, please update".into());
+    }
+
+    Ok((document.entries, preferences))
+}
+
+async fn push_document(
+    server_id: &str,
+    mut preferences: serde_json::Map<String, Json>,
+    entries: &SyncEntries,
+) -> Result<(), String> {
+    let document = SyncDocument {
+        version: SYNC_DOCUMENT_VERSION,
+        entries: entries.clone(),
+    };
+    preferences.insert(
+        PREFERENCES_KEY_SETTINGS.to_string(),
+        serde_json::to_value(&document).expect("Failed to serialize synced settings"),
+    );
+    let body = serde_json::json!({ "preferences": preferences }).to_string();
+
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+    let response = HttpClient::put(
+        server_id,
+        PROFILE_PATH,
+        Some(headers),
+        None,
+        Some(reqwest::Body::from(body)),
+    )
+    .await?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to push the settings: status {}",
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+async fn apply_entries<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    entries: &SyncEntries,
+) -> Result<(), String> {
+    let (general, local_query_sources, applications) = unflatten(entries);
+    let mut summary = ImportSummary::default();
+
+    import_general_settings(app_handle, general, ImportMode::Replace, None)?;
+    import_local_query_sources(app_handle, local_query_sources, &mut summary).await;
+    import_applications(app_handle, applications, ImportMode::Replace, &mut summary)?;
+
+    for warning in summary.warnings {
+        log::warn!("failed to apply a synced setting: {}", warning);
+    }
+
+    Ok(())
+}
+
+async fn sync_through<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    server_id: &str,
+) -> Result<(), String> {
+    if get_server_by_id(server_id).is_none() {
+        return Err(format!("Server not found for ID: {}", server_id));
+    }
+
+    let state = app_handle
+        .store(TAURI_STORE_SETTINGS_SYNC_STATE)
+        .map_err(|e| e.to_string())?;
+    let previous: SyncEntries = state
+        .get(SYNC_STATE_KEY_ENTRIES)
+        .and_then(|entries| serde_json::from_value(entries).ok())
+        .unwrap_or_default();
+
+    let bundle = collect_bundle_without_secrets(app_handle)?;
+    let current = flatten(
+        &bundle.general,
+        &bundle.local_query_sources,
+        &bundle.applications,
+    );
+    let now = chrono::Utc::now().timestamp_millis();
+    let local = stamp_local_entries(&previous, current, now);
+
+    let (remote, preferences) = pull_document(server_id).await?;
+    let merged = merge_entries(local.clone(), remote.clone());
+    // The invalid entries stay on the server, they may be valid elsewhere
+    let applicable = applicable_entries(&merged, &local);
+
+    if applicable != local {
+        log::info!(
+            "applying the settings synced through server [{}]",
+            server_id
+        );
+        apply_entries(app_handle, &applicable).await?;
+        let _ = app_handle.emit(EVENT_SETTINGS_SYNCED, ());
+    }
+    if merged != remote {
+        push_document(server_id, preferences, &merged).await?;
+    }
+
+    state.set(
+        SYNC_STATE_KEY_ENTRIES,
+        serde_json::to_value(&applicable).expect("Failed to serialize synced settings"),
+    );
+    state.set(SYNC_STATE_KEY_LAST_SYNCED_AT, now);
+
+    Ok(())
+}
+
+/// Sync the settings if the sync is on, recording the outcome.
+async fn sync_if_enabled<R: Runtime>(app_handle: &AppHandle<R>) -> Result<(), String> {
+    let Some(server_id) = sync_server_id(app_handle) else {
+        return Ok(());
+    };
+
+    let _guard = SYNC_LOCK.lock().await;
+    let result = sync_through(app_handle, &server_id).await;
+
+    if let Ok(state) = app_handle.store(TAURI_STORE_SETTINGS_SYNC_STATE) {
+        match &result {
+            Ok(()) => {
+                state.delete(SYNC_STATE_KEY_LAST_ERROR);
+            }
+            Err(e) => state.set(SYNC_STATE_KEY_LAST_ERROR, e.as_str()),
+        }
+    }
+
+    result
+}
+
+/// Start the background task syncing the settings, it does nothing while the
+/// sync is off.
+pub(crate) fn start_settings_sync<R: Runtime>(app_handle: AppHandle<R>) {
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(INITIAL_SYNC_DELAY).await;
+
+        loop {
+            if let Err(e) = sync_if_enabled(&app_handle).await {
+                log::warn!("failed to sync the settings: {}", e);
+            }
+
+            tokio::time::sleep(SYNC_INTERVAL).await;
+        }
+    });
+}
+
+/// Turn the sync on through the server specified by `server_id`, then sync
+/// right away.
+#[tauri::command]
+pub async fn enable_settings_sync<R: Runtime>(
+    app_handle: AppHandle<R>,
+    server_id: String,
+) -> Result<(), String> {
+    if get_server_by_id(&server_id).is_none() {
+        return Err(format!("Server not found for ID: {}", server_id));
+    }
+
+    let store = app_handle
+        .store(COCO_TAURI_STORE)
+        .map_err(|e| e.to_string())?;
+    if sync_server_id(&app_handle).as_deref() != Some(server_id.as_str()) {
+        // The previous sync state belongs to another server
+        if let Ok(state) = app_handle.store(TAURI_STORE_SETTINGS_SYNC_STATE) {
+            state.clear();
+        }
+    }
+    store.set(SETTINGS_SYNC_SERVER, server_id.as_str());
+
+    sync_if_enabled(&app_handle).await
+}
+
+/// Turn the sync off, the settings stored on the server are kept.
+#[tauri::command]
+pub async fn disable_settings_sync<R: Runtime>(app_handle: AppHandle<R>) -> Result<(), String> {
+    let store = app_handle
+        .store(COCO_TAURI_STORE)
+        .map_err(|e| e.to_string())?;
+    store.delete(SETTINGS_SYNC_SERVER);
+
+    if let Ok(state) = app_handle.store(TAURI_STORE_SETTINGS_SYNC_STATE) {
+        state.clear();
+    }
+
+    Ok(())
+}
+
+/// Sync the settings now.
+#[tauri::command]
+pub async fn sync_settings<R: Runtime>(app_handle: AppHandle<R>) -> Result<(), String> {
+    if sync_server_id(&app_handle).is_none() {
+        return Err("The settings sync is off".into());
+    }
+
+    sync_if_enabled(&app_handle).await
+}
+
+#[tauri::command]
+pub async fn get_settings_sync_status<R: Runtime>(app_handle: AppHandle<R>) -> SettingsSyncStatus {
+    let state = app_handle.store(TAURI_STORE_SETTINGS_SYNC_STATE).ok();
+
+    SettingsSyncStatus {
+        server_id: sync_server_id(&app_handle),
+        last_synced_at: state
+            .as_ref()
+            .and_then(|state| state.get(SYNC_STATE_KEY_LAST_SYNCED_AT))
+            .and_then(|value| value.as_i64()),
+        last_error: state
+            .as_ref()
+            .and_then(|state| state.get(SYNC_STATE_KEY_LAST_ERROR))
+            .and_then(|value| value.as_str().map(str::to_string)),
+    }
+}
+
+#[test]
+fn test_stamp_and_merge_entries() {
+    let entry = |value: Json, updated_at: i64| SyncEntry { value, updated_at };
+    let previous: SyncEntries = [
+        ("a".to_string(), entry(Json::from("old"), 10)),
+        ("b".to_string(), entry(Json::from(true), 10)),
+        ("c".to_string(), entry(Json::from(1), 10)),
+    ]
+    .into_iter()
+    .collect();
+    // "a" changed, "b" did not, "c" was removed
+    let current: BTreeMap<String, Json> = [
+        ("a".to_string(), Json::from("new")),
+        ("b".to_string(), Json::from(true)),
+    ]
+    .into_iter()
+    .collect();
+
+    let local = stamp_local_entries(&previous, current, 100);
+    assert_eq!(local["a"], entry(Json::from("new"), 100));
+    assert_eq!(local["b"], entry(Json::from(true), 10));
+    assert_eq!(local["c"], entry(Json::Null, 100));
+
+    let remote: SyncEntries = [
+        ("a".to_string(), entry(Json::from("remote"), 50)),
+        ("b".to_string(), entry(Json::from(false), 50)),
+        ("d".to_string(), entry(Json::from("remote only"), 50)),
+    ]
+    .into_iter()
+    .collect();
+
+    let merged = merge_entries(local, remote);
+    assert_eq!(merged["a"].value, Json::from("new"));
+    assert_eq!(merged["b"].value, Json::from(false));
+    assert_eq!(merged["c"].value, Json::Null);
+    assert_eq!(merged["d"].value, Json::from("remote only"));
+
+    // Before the first sync, the server wins
+    let first = stamp_local_entries(
+        &SyncEntries::new(),
+        [("b".to_string(), Json::from(true))].into_iter().collect(),
+        100,
+    );
+    assert_eq!(merge_entries(first, merged)["b"].value, Json::from(false));
+}
+
+#[test]
+fn test_applicable_entries() {
+    let entry = |value: &str| SyncEntry {
+        value: Json::from(value),
+        updated_at: 10,
+    };
+    let merged: SyncEntries = [
+        ("general.global_shortcut", entry("ctrl+shift+k")),
+        ("applications.hotkeys.a", entry("not a shortcut")),
+        ("applications.hotkeys.b", entry("ctrl+shift+k")),
+        ("applications.hotkeys.c", entry("ctrl+alt+c")),
+        ("general.allow_self_signature", entry("true")),
+    ]
+    .into_iter()
+    .map(|(key, entry)| (key.to_string(), entry))
+    .collect();
+    let local: SyncEntries = [("applications.hotkeys.a".to_string(), entry("ctrl+alt+a"))]
+        .into_iter()
+        .collect();
+
+    let applicable = applicable_entries(&merged, &local);
+    assert_eq!(applicable["general.global_shortcut"], entry("ctrl+shift+k"));
+    // Invalid, the local value is kept
+    assert_eq!(applicable["applications.hotkeys.a"], entry("ctrl+alt+a"));
+    // Already in use by the global shortcut
+    assert!(!applicable.contains_key("applications.hotkeys.b"));
+    assert_eq!(applicable["applications.hotkeys.c"], entry("ctrl+alt+c"));
+    // Not synced
+    assert!(!applicable.contains_key("general.allow_self_signature"));
+}
@@ -1,69 +1,298 @@
-use crate::{hide_coco, show_coco, COCO_TAURI_STORE};
-use tauri::{async_runtime, App, AppHandle, Manager, Runtime};
+//! Global shortcuts.
+//!
+//! Every shortcut is bound to a [`ShortcutAction`]. The one toggling the
+//! launcher is the `global_shortcut` setting, the other ones are kept in the
+//! `action_shortcuts` setting, they are all registered upon app start and
+//! whenever the settings change.
+//!
+//! A shortcut can only be bound once among the ones of Coco, the application
+//! hotkeys included, see [`check_shortcut()`]. The commands binding a shortcut
+//! register it before saving it, so that a failure of the OS is reported to
+//! the user rather than only logged.
+
+use crate::common::MAIN_WINDOW_LABEL;
+use crate::document_hotkey::DocumentHotkeyTarget;
+use crate::local::application::TAURI_STORE_APP_HOTKEY;
+use crate::settings::{self, settings, Settings, DEFAULT_SHORTCUT};
+use crate::{hide_coco, open_settings, show_coco, GLOBAL_TAURI_APP_HANDLE};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tauri::{async_runtime, App, AppHandle, Emitter, Manager, Runtime};
+use tauri_plugin_clipboard_manager::ClipboardExt;
 use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
-use tauri_plugin_store::{JsonValue, StoreExt};
+use tauri_plugin_store::StoreExt;
 
-/// Tauri's store is a key-value database, we use it to store our registered
-/// global shortcut.
-///
-/// This is the key we use to store it.
-const COCO_GLOBAL_SHORTCUT: &str = "coco_global_shortcut";
+/// Emitted once Coco is shown by the shortcut opening the chat mode.
+const EVENT_SHORTCUT_OPEN_CHAT: &str = "shortcut-open-chat";
+/// Emitted once Coco is shown by the shortcut searching the clipboard, with
+/// the clipboard text as the query.
+const EVENT_SHORTCUT_SEARCH_CLIPBOARD: &str = "shortcut-search-clipboard";
+/// Emitted when a saved shortcut cannot be registered, e.g., it is used by
+/// another app.
+const EVENT_SHORTCUT_REGISTRATION_FAILED: &str = "shortcut-registration-failed";
+
+/// Actions that can be bound to a global shortcut.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShortcutAction {
+    /// Show or hide Coco.
+    ToggleLauncher,
+    /// Show Coco in the chat mode.
+    OpenChat,
+    OpenSettings,
+    /// Show Coco and search the text in the clipboard.
+    SearchClipboard,
+    /// Open `link`, `coco://` links are handled as deep links.
+    RunQuicklink {
+        link: String,
+    },
+    /// Open a document, e.g., a result of a server.
+    OpenDocument {
+        document: DocumentHotkeyTarget,
+    },
+}
+
+impl ShortcutAction {
+    /// Whether `self` and `other` are the same action. Documents are told
+    /// apart by their IDs only, their metadata may have been updated.
+    pub(crate) fn is_same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::OpenDocument { document }, Self::OpenDocument { document: other }) => {
+                document.is_same(other)
+            }
+            _ => self == other,
+        }
+    }
+}
 
-#[cfg(target_os = "macos")]
-const DEFAULT_SHORTCUT: &str = "command+shift+space";
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionShortcut {
+    pub shortcut: String,
+    pub action: ShortcutAction,
+}
 
-#[cfg(any(target_os = "windows", target_os = "linux"))]
-const DEFAULT_SHORTCUT: &str = "ctrl+shift+space";
+/// What a global shortcut is bound to.
+#[derive(Debug, Clone)]
+pub(crate) enum ShortcutOwner {
+    Action(ShortcutAction),
+    /// The hotkey of the application at this path.
+    Application(String),
+}
+
+impl PartialEq for ShortcutOwner {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Action(action), Self::Action(other)) => action.is_same(other),
+            (Self::Application(path), Self::Application(other)) => path == other,
+            _ => false,
+        }
+    }
+}
 
-/// Set up the shortcut upon app start.
+impl fmt::Display for ShortcutOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Action(ShortcutAction::ToggleLauncher) => write!(f, "the Coco shortcut"),
+            Self::Action(ShortcutAction::OpenChat) => write!(f, "the chat mode"),
+            Self::Action(ShortcutAction::OpenSettings) => write!(f, "the settings"),
+            Self::Action(ShortcutAction::SearchClipboard) => {
+                write!(f, "the clipboard search")
+            }
+            Self::Action(ShortcutAction::RunQuicklink { link }) => {
+                write!(f, "quicklink [{}]", link)
+            }
+            Self::Action(ShortcutAction::OpenDocument { document }) => {
+                write!(f, "document [{}]", document.title)
+            }
+            Self::Application(path) => write!(f, "application [{}]", path),
+        }
+    }
+}
+
+pub(crate) fn parse_shortcut(shortcut: &str) -> Result<Shortcut, String> {
+    shortcut
+        .parse::<Shortcut>()
+        .map_err(|_| format!("invalid shortcut {}", shortcut))
+}
+
+/// Return the first shortcut of `bindings` equal to `shortcut` but bound to
+/// another owner than `owner`. Shortcuts are compared once parsed, so that
+/// `Shift+Ctrl+A` and `ctrl+shift+a` are the same.
+fn find_conflict<'a>(
+    shortcut: &Shortcut,
+    owner: &ShortcutOwner,
+    bindings: &'a [(String, ShortcutOwner)],
+) -> Option<&'a ShortcutOwner> {
+    bindings
+        .iter()
+        .find(|(bound, bound_owner)| {
+            bound_owner != owner && bound.parse::<Shortcut>().ok().as_ref() == Some(shortcut)
+        })
+        .map(|(_, bound_owner)| bound_owner)
+}
+
+/// The shortcuts of `settings` are valid and bound once.
+pub(crate) fn validate_shortcuts(settings: &Settings) -> Result<(), String> {
+    if settings
+        .action_shortcuts
+        .iter()
+        .any(|action_shortcut| action_shortcut.action == ShortcutAction::ToggleLauncher)
+    {
+        return Err("the launcher is toggled by the global shortcut".into());
+    }
+
+    let bindings = settings_bindings(settings);
+    for (shortcut, owner) in &bindings {
+        match owner {
+            ShortcutOwner::Action(ShortcutAction::RunQuicklink { link })
+                if link.trim().is_empty() =>
+            {
+                return Err("the link of a quicklink should not be empty".into());
+            }
+            ShortcutOwner::Action(ShortcutAction::OpenDocument { document }) => {
+                document.validate()?
+            }
+            _ => {}
+        }
+        let parsed = parse_shortcut(shortcut)?;
+        if let Some(other) = find_conflict(&parsed, owner, &bindings) {
+            return Err(format!(
+                "shortcut [{}] of {} is already used by {}",
+                shortcut, owner, other
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn settings_bindings(settings: &Settings) -> Vec<(String, ShortcutOwner)> {
+    action_shortcuts(settings)
+        .into_iter()
+        .map(|action_shortcut| {
+            (
+                action_shortcut.shortcut,
+                ShortcutOwner::Action(action_shortcut.action),
+            )
+        })
+        .collect()
+}
+
+/// Every shortcut bound by Coco.
+fn bindings<R: Runtime>(app: &AppHandle<R>) -> Vec<(String, ShortcutOwner)> {
+    let mut bindings = settings_bindings(&settings());
+
+    match app.store(TAURI_STORE_APP_HOTKEY) {
+        Ok(store) => bindings.extend(store.entries().into_iter().filter_map(
+            |(app_path, hotkey)| {
+                hotkey
+                    .as_str()
+                    .map(|hotkey| (hotkey.to_string(), ShortcutOwner::Application(app_path)))
+            },
+        )),
+        Err(e) => log::warn!("failed to load the application hotkeys: {}", e),
+    }
+
+    bindings
+}
+
+/// Check that `shortcut` is valid and not bound to anything else than
+/// `owner`.
+pub(crate) fn check_shortcut<R: Runtime>(
+    app: &AppHandle<R>,
+    shortcut: &str,
+    owner: &ShortcutOwner,
+) -> Result<Shortcut, String> {
+    let parsed = parse_shortcut(shortcut)?;
+    match find_conflict(&parsed, owner, &bindings(app)) {
+        Some(other) => Err(format!(
+            "shortcut [{}] is already used by {}",
+            shortcut, other
+        )),
+        None => Ok(parsed),
+    }
+}
+
+/// Every shortcut of `settings`, including the one toggling the launcher.
+fn action_shortcuts(settings: &Settings) -> Vec<ActionShortcut> {
+    std::iter::once(ActionShortcut {
+        shortcut: settings.global_shortcut.clone(),
+        action: ShortcutAction::ToggleLauncher,
+    })
+    .chain(settings.action_shortcuts.iter().cloned())
+    .collect()
+}
+
+/// Set up the shortcuts upon app start.
 pub fn enable_shortcut(app: &App) {
-    let store = app
-        .store(COCO_TAURI_STORE)
-        .expect("creating a store should not fail");
-
-    if let Some(stored_shortcut) = store.get(COCO_GLOBAL_SHORTCUT) {
-        let stored_shortcut_str = match stored_shortcut {
-            JsonValue::String(str) => str,
-            unexpected_type => panic!(
-                "COCO shortcut should be stored as a string, found: {} ",
-                unexpected_type
-            ),
-        };
-        let stored_shortcut = stored_shortcut_str
-            .parse::<Shortcut>()
-            .expect("stored shortcut string should be valid");
-        _register_shortcut_upon_start(app, stored_shortcut);
-    } else {
-        store.set(
-            COCO_GLOBAL_SHORTCUT,
-            JsonValue::String(DEFAULT_SHORTCUT.to_string()),
-        );
-        let default_shortcut = DEFAULT_SHORTCUT
-            .parse::<Shortcut>()
-            .expect("default shortcut should never be invalid");
-        _register_shortcut_upon_start(app, default_shortcut);
+    if let Err(e) = app
+        .handle()
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
+    {
+        log::error!("failed to register the global shortcut plugin: {}", e);
+        return;
+    }
+
+    for action_shortcut in action_shortcuts(&settings()) {
+        if let Err(e) = register_action_shortcut_key(app.handle(), &action_shortcut) {
+            report_registration_failure(app.handle(), &action_shortcut, &e);
+
+            // Coco cannot be shown without a shortcut, fall back to the
+            // default one for this session
+            if action_shortcut.action == ShortcutAction::ToggleLauncher
+                && action_shortcut.shortcut != DEFAULT_SHORTCUT
+                && check_shortcut(
+                    app.handle(),
+                    DEFAULT_SHORTCUT,
+                    &ShortcutOwner::Action(ShortcutAction::ToggleLauncher),
+                )
+                .is_ok()
+            {
+                let fallback = ActionShortcut {
+                    shortcut: DEFAULT_SHORTCUT.to_string(),
+                    action: ShortcutAction::ToggleLauncher,
+                };
+                if let Err(e) = register_action_shortcut_key(app.handle(), &fallback) {
+                    report_registration_failure(app.handle(), &fallback, &e);
+                }
+            }
+        }
     }
 }
 
-/// Get the stored shortcut as a string, same as [`_get_shortcut()`], except that
-/// this is a `tauri::command` interface.
+fn report_registration_failure<R: Runtime>(
+    app: &AppHandle<R>,
+    action_shortcut: &ActionShortcut,
+    error: &str,
+) {
+    log::error!("{}", error);
+    let _ = app.emit(
+        EVENT_SHORTCUT_REGISTRATION_FAILED,
+        serde_json::json!({
+            "shortcut": action_shortcut.shortcut,
+            "action": action_shortcut.action,
+            "error": error,
+        }),
+    );
+}
+
+/// Get the current shortcut as a string.
 #[tauri::command]
-pub async fn get_current_shortcut<R: Runtime>(app: AppHandle<R>) -> Result<String, String> {
-    let shortcut = _get_shortcut(&app);
-    Ok(shortcut)
+pub async fn get_current_shortcut() -> Result<String, String> {
+    Ok(settings().global_shortcut)
 }
 
 /// Get the current shortcut and unregister it on the tauri side.
 #[tauri::command]
-pub async fn unregister_shortcut<R: Runtime>(app: AppHandle<R>) {
-    let shortcut_str = _get_shortcut(&app);
-    let shortcut = shortcut_str
-        .parse::<Shortcut>()
-        .expect("stored shortcut string should be valid");
+pub async fn unregister_shortcut<R: Runtime>(app: AppHandle<R>) -> Result<(), String> {
+    let shortcut = parse_shortcut(&settings().global_shortcut)?;
+    if !app.global_shortcut().is_registered(shortcut) {
+        return Ok(());
+    }
 
     app.global_shortcut()
         .unregister(shortcut)
-        .expect("failed to unregister shortcut")
+        .map_err(|e| format!("failed to unregister shortcut: {}", e))
 }
 
 /// Change the global shortcut to `key`.
@@ -73,95 +302,238 @@ pub async fn change_shortcut<R: Runtime>(
     _window: tauri::Window<R>,
     key: String,
 ) -> Result<(), String> {
-    println!("key {}:", key);
-    let shortcut = match key.parse::<Shortcut>() {
-        Ok(shortcut) => shortcut,
-        Err(_) => return Err(format!("invalid shortcut {}", key)),
-    };
+    bind(
+        &app,
+        ActionShortcut {
+            shortcut: key,
+            action: ShortcutAction::ToggleLauncher,
+        },
+    )
+}
 
-    // Store it
-    let store = app
-        .get_store(COCO_TAURI_STORE)
-        .expect("store should be loaded or created");
-    store.set(COCO_GLOBAL_SHORTCUT, JsonValue::String(key));
+/// Get the shortcuts of all the actions, including the one toggling the
+/// launcher.
+#[tauri::command]
+pub async fn get_action_shortcuts() -> Vec<ActionShortcut> {
+    action_shortcuts(&settings())
+}
 
-    // Register it
-    _register_shortcut(&app, shortcut);
+/// Bind `action` to `shortcut`, replacing its previous shortcut. Quicklinks
+/// are told apart by their links.
+#[tauri::command]
+pub async fn register_action_shortcut<R: Runtime>(
+    app: AppHandle<R>,
+    shortcut: String,
+    action: ShortcutAction,
+) -> Result<(), String> {
+    bind(&app, ActionShortcut { shortcut, action })
+}
+
+/// Bind `action_shortcut`, replacing the previous shortcut of its action.
+///
+/// It is registered before the settings are saved, so that an error of the OS
+/// is returned.
+pub(crate) fn bind<R: Runtime>(
+    app: &AppHandle<R>,
+    action_shortcut: ActionShortcut,
+) -> Result<(), String> {
+    let owner = ShortcutOwner::Action(action_shortcut.action.clone());
+    let shortcut = check_shortcut(app, &action_shortcut.shortcut, &owner)?;
+
+    // It may have been unregistered, e.g., while the user edits it
+    let newly_registered = !app.global_shortcut().is_registered(shortcut);
+    if newly_registered {
+        register_action_shortcut_key(app, &action_shortcut)?;
+    }
+
+    // The previous shortcut is unregistered by the settings watcher
+    let result = settings::update(app, |settings| {
+        if action_shortcut.action == ShortcutAction::ToggleLauncher {
+            settings.global_shortcut = action_shortcut.shortcut.clone();
+        } else {
+            settings
+                .action_shortcuts
+                .retain(|bound| !bound.action.is_same(&action_shortcut.action));
+            settings.action_shortcuts.push(action_shortcut.clone());
+        }
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        if newly_registered {
+            let _ = app.global_shortcut().unregister(shortcut);
+        }
+        return Err(e);
+    }
 
     Ok(())
 }
 
-/// Helper function to register a shortcut, used for shortcut updates.
-fn _register_shortcut<R: Runtime>(app: &AppHandle<R>, shortcut: Shortcut) {
-    app.global_shortcut()
-        .on_shortcut(shortcut, move |app, scut, event| {
-            if scut == &shortcut {
-                dbg!("shortcut pressed");
-                let main_window = app.get_window(MAIN_WINDOW_LABEL).unwrap();
-                if let ShortcutState::Pressed = event.state() {
-                    let app_handle = app.clone();
-                    if main_window.is_visible().unwrap() {
-                        async_runtime::spawn(async move {
-                            hide_coco(app_handle).await;
-                        });
-                    } else {
-                        async_runtime::spawn(async move {
-                            show_coco(app_handle).await;
-                        });
-                    }
+/// Remove the shortcut of `action`.
+#[tauri::command]
+pub async fn unregister_action_shortcut<R: Runtime>(
+    app: AppHandle<R>,
+    action: ShortcutAction,
+) -> Result<(), String> {
+    if action == ShortcutAction::ToggleLauncher {
+        return Err("the shortcut toggling the launcher cannot be removed".into());
+    }
+
+    settings::update(&app, |settings| {
+        let count = settings.action_shortcuts.len();
+        settings
+            .action_shortcuts
+            .retain(|action_shortcut| !action_shortcut.action.is_same(&action));
+        if settings.action_shortcuts.len() == count {
+            return Err(format!("no shortcut is bound to {:?}", action));
+        }
+        Ok(())
+    })
+    .map(|_| ())
+}
+
+/// Register the new shortcuts when the settings change, called upon app
+/// start.
+pub(crate) fn watch_settings() {
+    settings::subscribe(|previous, current| {
+        let previous = action_shortcuts(previous);
+        let current = action_shortcuts(current);
+        if previous == current {
+            return;
+        }
+        let Some(app) = GLOBAL_TAURI_APP_HANDLE.get() else {
+            return;
+        };
+
+        for action_shortcut in previous.iter().filter(|s| !current.contains(s)) {
+            let Ok(shortcut) = action_shortcut.shortcut.parse::<Shortcut>() else {
+                continue;
+            };
+            if app.global_shortcut().is_registered(shortcut) {
+                if let Err(e) = app.global_shortcut().unregister(shortcut) {
+                    log::warn!(
+                        "failed to unregister shortcut [{}]: {}",
+                        action_shortcut.shortcut,
+                        e
+                    );
                 }
             }
+        }
+        // The commands have registered theirs already
+        for action_shortcut in current.iter().filter(|s| !previous.contains(s)) {
+            if action_shortcut
+                .shortcut
+                .parse::<Shortcut>()
+                .is_ok_and(|shortcut| app.global_shortcut().is_registered(shortcut))
+            {
+                continue;
+            }
+            if let Err(e) = register_action_shortcut_key(app, action_shortcut) {
+                report_registration_failure(app, action_shortcut, &e);
+            }
+        }
+    });
+}
+
+/// Register the shortcut of `action_shortcut` to run its action.
+fn register_action_shortcut_key<R: Runtime>(
+    app: &AppHandle<R>,
+    action_shortcut: &ActionShortcut,
+) -> Result<(), String> {
+    let shortcut = parse_shortcut(&action_shortcut.shortcut)?;
+    let action = action_shortcut.action.clone();
+
+    app.global_shortcut()
+        .on_shortcut(shortcut, move |_app, _shortcut, event| {
+            if let ShortcutState::Pressed = event.state() {
+                let action = action.clone();
+                async_runtime::spawn(async move {
+                    run_action(action).await;
+                });
+            }
+        })
+        .map_err(|e| {
+            format!(
+                "failed to register shortcut [{}]: {}",
+                action_shortcut.shortcut, e
+            )
         })
-        .map_err(|err| format!("Failed to register new shortcut key '{}'", err))
-        .unwrap();
 }
 
-use crate::common::MAIN_WINDOW_LABEL;
+async fn run_action(action: ShortcutAction) {
+    let Some(app_handle) = GLOBAL_TAURI_APP_HANDLE.get().cloned() else {
+        return;
+    };
+    log::debug!("running shortcut action {:?}", action);
 
-/// Helper function to register a shortcut, used to set up the shortcut up App's first start.
-fn _register_shortcut_upon_start(app: &App, shortcut: Shortcut) {
-    let handler = app.app_handle();
-    handler
-        .plugin(
-            tauri_plugin_global_shortcut::Builder::new()
-                .with_handler(move |app, scut, event| {
-                    if scut == &shortcut {
-                        let window = app.get_window(MAIN_WINDOW_LABEL).unwrap();
-                        if let ShortcutState::Pressed = event.state() {
-                            let app_handle = app.clone();
-
-                            if window.is_visible().unwrap() {
-                                async_runtime::spawn(async move {
-                                    hide_coco(app_handle).await;
-                                });
-                            } else {
-                                async_runtime::spawn(async move {
-                                    show_coco(app_handle).await;
-                                });
-                            }
-                        }
-                    }
-                })
-                .build(),
-        )
-        .unwrap();
-    app.global_shortcut().register(shortcut).unwrap();
-}
-
-/// Helper function to get the stored global shortcut, as a string.
-pub fn _get_shortcut<R: Runtime>(app: &AppHandle<R>) -> String {
-    let store = app
-        .get_store(COCO_TAURI_STORE)
-        .expect("store should be loaded or created");
-
-    match store
-        .get(COCO_GLOBAL_SHORTCUT)
-        .expect("shortcut should be stored")
-    {
-        JsonValue::String(str) => str,
-        unexpected_type => panic!(
-            "COCO shortcut should be stored as a string, found: {} ",
-            unexpected_type
-        ),
+    match action {
+        ShortcutAction::ToggleLauncher => {
+            let visible = app_handle
+                .get_window(MAIN_WINDOW_LABEL)
+                .and_then(|window| window.is_visible().ok())
+                .unwrap_or(false);
+            if visible {
+                hide_coco(app_handle).await;
+            } else {
+                show_coco(app_handle).await;
+            }
+        }
+        ShortcutAction::OpenChat => {
+            show_coco(app_handle.clone()).await;
+            let _ = app_handle.emit(EVENT_SHORTCUT_OPEN_CHAT, ());
+        }
+        ShortcutAction::OpenSettings => open_settings(&app_handle),
+        ShortcutAction::SearchClipboard => {
+            let query = app_handle.clipboard().read_text().unwrap_or_else(|e| {
+                log::warn!("failed to read the clipboard: {}", e);
+                String::new()
+            });
+            show_coco(app_handle.clone()).await;
+            let _ = app_handle.emit(
+                EVENT_SHORTCUT_SEARCH_CLIPBOARD,
+                serde_json::json!({ "query": query.trim() }),
+            );
+        }
+        ShortcutAction::RunQuicklink { link } => {
+            let result = if link.starts_with("coco:") {
+                crate::deep_link::handle_deep_link(app_handle, link.clone()).await
+            } else {
+                crate::util::open(app_handle, link.clone()).await
+            };
+            if let Err(e) = result {
+                log::error!("failed to run quicklink [{}]: {}", link, e);
+            }
+        }
+        ShortcutAction::OpenDocument { document } => {
+            if let Err(e) = document.open(app_handle).await {
+                log::error!("failed to open document [{}]: {}", document.title, e);
+            }
+        }
     }
 }
+
+#[test]
+fn test_validate_shortcuts() {
+    let mut settings = Settings {
+        global_shortcut: "ctrl+shift+space".to_string(),
+        ..Default::default()
+    };
+    settings.action_shortcuts.push(ActionShortcut {
+        shortcut: "ctrl+shift+c".to_string(),
+        action: ShortcutAction::OpenChat,
+    });
+    assert!(validate_shortcuts(&settings).is_ok());
+
+    settings.action_shortcuts.push(ActionShortcut {
+        shortcut: "Shift+Ctrl+Space".to_string(),
+        action: ShortcutAction::OpenSettings,
+    });
+    assert!(validate_shortcuts(&settings).is_err());
+
+    settings.action_shortcuts.pop();
+    settings.action_shortcuts.push(ActionShortcut {
+        shortcut: "ctrl+shift+not_a_key".to_string(),
+        action: ShortcutAction::OpenSettings,
+    });
+    assert!(validate_shortcuts(&settings).is_err());
+}
@@ -0,0 +1,322 @@
+//! Schema versions and migrations of the Tauri stores.
+//!
+//! Upon app start, before anything reads them, every store in [`STORE_SCHEMAS`]
+//! is brought to its current schema version by running its pending migrations
+//! in order, then its values are validated. Values that cannot be read are
+//! backed up to a file next to the store and removed, so that their owners
+//! fall back to the defaults rather than panicking. A store file that cannot
+//! even be parsed is renamed and recreated.
+//!
+//! The versions are kept in a store of their own, as some stores are iterated
+//! with `entries()` and cannot hold extra keys.
+
+use serde_json::{Map, Value as Json};
+use tauri::{AppHandle, Manager, Runtime};
+use tauri_plugin_store::StoreExt;
+
+/// Store holding the schema version of the other stores.
+const TAURI_STORE_SCHEMA_VERSIONS: &str = "store_schema_versions";
+
+type MigrateFn = fn(&mut Map<String, Json>) -> Result<(), String>;
+
+struct Migration {
+    /// Version of the schema after this migration.
+    to_version: u32,
+    migrate: MigrateFn,
+}
+
+struct StoreSchema {
+    store: &'static str,
+    version: u32,
+    /// Sorted by `to_version`.
+    migrations: &'static [Migration],
+    /// Whether `value` is valid for `key`.
+    validate: fn(key: &str, value: &Json) -> bool,
+}
+
+/// The names must match the ones used by the modules owning these stores.
+const STORE_SCHEMAS: &[StoreSchema] = &[
+    StoreSchema {
+        store: crate::COCO_TAURI_STORE,
+        version: 1,
+        migrations: &[],
+        validate: validate_coco_store,
+    },
+    StoreSchema {
+        store: "app_alias",
+        version: 1,
+        migrations: &[],
+        validate: |_, value| value.is_string(),
+    },
+    StoreSchema {
+        store: "app_hotkey",
+        version: 1,
+        migrations: &[],
+        validate: |_, value| value.is_string(),
+    },
+    StoreSchema {
+        store: "disabled_app_list_and_search_path",
+        version: 1,
+        migrations: &[],
+        validate: |key, value| match key {
+            "search_path" | "disabled_app_list" => is_string_array(value),
+            _ => true,
+        },
+    },
+    StoreSchema {
+        store: "local_query_source_enabled_state",
+        version: 1,
+        migrations: &[Migration {
+            to_version: 1,
+            migrate: migrate_enabled_state_strings,
+        }],
+        validate: |_, value| value.is_boolean(),
+    },
+    StoreSchema {
+        store: crate::settings_sync::TAURI_STORE_SETTINGS_SYNC_STATE,
+        version: 1,
+        migrations: &[],
+        validate: |key, value| match key {
+            "entries" => value.is_object(),
+            "last_synced_at" => value.is_i64(),
+            "last_error" => value.is_string(),
+            _ => true,
+        },
+    },
+    StoreSchema {
+        store: "offline_document_cache",
+        version: 1,
+        migrations: &[],
+        validate: |_, value| value.is_array(),
+    },
+];
+
+fn is_string_array(value: &Json) -> bool {
+    value
+        .as_array()
+        .is_some_and(|values| values.iter().all(Json::is_string))
+}
+
+fn validate_coco_store(key: &str, value: &Json) -> bool {
+    match key {
+        "coco_servers" | "coco_server_tokens" => value.is_array(),
+        "coco_global_shortcut" => value.is_string(),
+        "settings_allow_self_signature" | "settings_offline_cache_enabled" => value.is_boolean(),
+        "settings_proxy" => value.is_object() || value.is_null(),
+        "settings_autostart" => value.is_object(),
+        "settings_action_shortcuts" => value.is_array(),
+        "settings_local_api" => value.is_object(),
+        "search_source_timeout_overrides" => value.is_object(),
+        "settings_sync_server" => value.is_string(),
+        _ => true,
+    }
+}
+
+/// Some versions stored the enabled state of the local query sources as
+/// strings.
+fn migrate_enabled_state_strings(entries: &mut Map<String, Json>) -> Result<(), String> {
+    for value in entries.values_mut() {
+        if let Some(enabled) = value.as_str().and_then(|s| s.trim().parse::<bool>().ok()) {
+            *value = Json::Bool(enabled);
+        }
+    }
+
+    Ok(())
+}
+
+/// Bring `entries`, at schema version `from_version`, to the current version
+/// of `schema`, then remove the invalid values, which are returned.
+fn migrate_entries(
+    schema: &StoreSchema,
+    from_version: u32,
+    entries: &mut Map<String, Json>,
+) -> Result<Map<String, Json>, String> {
+    for migration in schema
+        .migrations
+        .iter()
+        .filter(|migration| migration.to_version > from_version)
+    {
+        (migration.migrate)(entries).map_err(|e| {
+            format!(
+                "migration of store [{}] to version {} failed: {}",
+                schema.store, migration.to_version, e
+            )
+        })?;
+    }
+
+    let invalid_keys: Vec<String> = entries
+        .iter()
+        .filter(|(key, value)| !(schema.validate)(key, value))
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    let mut invalid = Map::new();
+    for key in invalid_keys {
+        if let Some(value) = entries.remove(&key) {
+            invalid.insert(key, value);
+        }
+    }
+
+    Ok(invalid)
+}
+
+fn timestamp() -> String {
+    chrono::Local::now().format("%Y%m%d%H%M%S").to_string()
+}
+
+/// Write `values` of `store` to a backup file next to it.
+fn backup_values<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    store: &str,
+    values: &Map<String, Json>,
+) -> Result<(), String> {
+    let dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get the app data directory: {}", e))?;
+    let path = dir.join(format!("{}.{}.bak.json", store, timestamp()));
+
+    let json = serde_json::to_string_pretty(values).expect("Failed to serialize values");
+    std::fs::write(&path, json)
+        .map_err(|e| format!("Failed to write [{}]: {}", path.display(), e))?;
+
+    log::warn!(
+        "backed up {} value(s) of store [{}] to [{}]",
+        values.len(),
+        store,
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Rename the file of `store` if it cannot be loaded, so that it is recreated.
+fn ensure_store_loads<R: Runtime>(app_handle: &AppHandle<R>, store: &str) -> Result<(), String> {
+    let Err(e) = app_handle.store(store) else {
+        return Ok(());
+    };
+
+    let dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get the app data directory: {}", e))?;
+    let path = dir.join(store);
+    let backup_path = dir.join(format!("{}.{}.corrupt", store, timestamp()));
+    log::error!(
+        "store [{}] cannot be loaded ({}), moving it to [{}]",
+        store,
+        e,
+        backup_path.display()
+    );
+    std::fs::rename(&path, &backup_path)
+        .map_err(|e| format!("Failed to move [{}]: {}", path.display(), e))?;
+
+    app_handle
+        .store(store)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to recreate store [{}]: {}", store, e))
+}
+
+fn migrate_store<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    schema: &StoreSchema,
+) -> Result<(), String> {
+    ensure_store_loads(app_handle, schema.store)?;
+
+    let versions = app_handle
+        .store(TAURI_STORE_SCHEMA_VERSIONS)
+        .map_err(|e| e.to_string())?;
+    let store = app_handle.store(schema.store).map_err(|e| e.to_string())?;
+
+    // Stores without a version predate versioning
+    let from_version = versions
+        .get(schema.store)
+        .and_then(|version| version.as_u64())
+        .unwrap_or(0) as u32;
+    if from_version > schema.version {
+        log::warn!(
+            "store [{}] has schema version {}, newer than the supported {}, only validating it",
+            schema.store,
+            from_version,
+            schema.version
+        );
+    }
+
+    let original: Map<String, Json> = store.entries().into_iter().collect();
+    let mut entries = original.clone();
+    let invalid = match migrate_entries(schema, from_version, &mut entries) {
+        Ok(invalid) => invalid,
+        Err(e) => {
+            // Start over rather than keep a half-migrated store
+            log::error!("{}, resetting the store", e);
+            entries.clear();
+            original.clone()
+        }
+    };
+
+    if !invalid.is_empty() {
+        backup_values(app_handle, schema.store, &invalid)?;
+    }
+
+    if entries != original {
+        store.clear();
+        for (key, value) in entries {
+            store.set(key, value);
+        }
+        store
+            .save()
+            .map_err(|e| format!("Failed to save store [{}]: {}", schema.store, e))?;
+    }
+
+    if from_version < schema.version {
+        log::info!(
+            "migrated store [{}] from schema version {} to {}",
+            schema.store,
+            from_version,
+            schema.version
+        );
+        versions.set(schema.store, schema.version);
+        versions.save().map_err(|e| {
+            format!(
+                "Failed to save store [{}]: {}",
+                TAURI_STORE_SCHEMA_VERSIONS, e
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Migrate and validate all the stores, called upon app start before they are
+/// read.
+pub(crate) fn migrate_stores<R: Runtime>(app_handle: &AppHandle<R>) {
+    if let Err(e) = ensure_store_loads(app_handle, TAURI_STORE_SCHEMA_VERSIONS) {
+        log::error!("failed to load the store schema versions: {}", e);
+        return;
+    }
+
+    for schema in STORE_SCHEMAS {
+        if let Err(e) = migrate_store(app_handle, schema) {
+            log::error!("failed to migrate store [{}]: {}", schema.store, e);
+        }
+    }
+}
+
+#[test]
+fn test_migrate_entries() {
+    let schema = STORE_SCHEMAS
+        .iter()
+        .find(|schema| schema.store == "local_query_source_enabled_state")
+        .unwrap();
+
+    let mut entries: Map<String, Json> =
+        serde_json::from_str(r#"{ "Applications": "true", "Calculator": false, "Broken": 42 }"#)
+            .unwrap();
+    let invalid = migrate_entries(schema, 0, &mut entries).unwrap();
+
+    assert_eq!(
+        Json::Object(entries),
+        serde_json::json!({ "Applications": true, "Calculator": false })
+    );
+    assert_eq!(Json::Object(invalid), serde_json::json!({ "Broken": 42 }));
+}
@@ -14,6 +14,7 @@ import {
   TranscriptionPayload,
   TranscriptionResponse,
   MultiSourceQueryResponse,
+  ServerAccountStatus,
 } from "@/types/commands";
 import { useAppStore } from "@/stores/appStore";
 import { useAuthStore } from "@/stores/authStore";
@@ -27,7 +28,13 @@ const WHITELIST_SERVERS = [
   "remove_coco_server",
   "logout_coco_server",
   "refresh_coco_server_info",
+  "start_sso_login",
   "handle_sso_callback",
+  "handle_deep_link",
+  "list_server_accounts",
+  "add_server_account",
+  "switch_server_account",
+  "remove_server_account",
   "query_coco_fusion",
 ];
 
@@ -103,19 +110,69 @@ export function refresh_coco_server_info(id: string): Promise<Server> {
   return invokeWithErrorHandler(`refresh_coco_server_info`, { id });
 }
 
+export function start_sso_login(
+  serverId: string
+): Promise<{ request_id: string; url: string }> {
+  return invokeWithErrorHandler(`start_sso_login`, { serverId });
+}
+
 export function handle_sso_callback({
   serverId,
   requestId,
   code,
+  state,
 }: {
   serverId: string;
   requestId: string;
   code: string;
+  state: string | null;
 }): Promise<void> {
   return invokeWithErrorHandler(`handle_sso_callback`, {
     serverId,
     requestId,
     code,
+    state,
+  });
+}
+
+export function handle_deep_link(url: string): Promise<void> {
+  return invokeWithErrorHandler(`handle_deep_link`, { url });
+}
+
+export function take_pending_server_endpoint(): Promise<string | null> {
+  return invoke(`take_pending_server_endpoint`);
+}
+
+export function list_server_accounts(
+  serverId: string
+): Promise<ServerAccountStatus[]> {
+  return invokeWithErrorHandler(`list_server_accounts`, { serverId });
+}
+
+export function add_server_account(
+  serverId: string,
+  name: string
+): Promise<Server> {
+  return invokeWithErrorHandler(`add_server_account`, { serverId, name });
+}
+
+export function switch_server_account(
+  serverId: string,
+  accountId: string
+): Promise<Server> {
+  return invokeWithErrorHandler(`switch_server_account`, {
+    serverId,
+    accountId,
+  });
+}
+
+export function remove_server_account(
+  serverId: string,
+  accountId: string
+): Promise<Server> {
+  return invokeWithErrorHandler(`remove_server_account`, {
+    serverId,
+    accountId,
   });
 }
 
@@ -1,5 +1,14 @@
 import { invoke } from '@tauri-apps/api/core';
 
+import {
+  ActionShortcut,
+  Settings,
+  SettingsImportMode,
+  SettingsImportSummary,
+  SettingsSyncStatus,
+  ShortcutAction,
+} from '@/types/commands';
+
 export function change_autostart(open: boolean): Promise<void> {
   return invoke('change_autostart', { open });
 }
@@ -16,6 +25,29 @@ export function unregister_shortcut(): Promise<void> {
   return invoke('unregister_shortcut');
 }
 
+export function get_action_shortcuts(): Promise<ActionShortcut[]> {
+  return invoke('get_action_shortcuts');
+}
+
+export function register_action_shortcut(
+  shortcut: string,
+  action: ShortcutAction
+): Promise<void> {
+  return invoke('register_action_shortcut', { shortcut, action });
+}
+
+export function unregister_action_shortcut(action: ShortcutAction): Promise<void> {
+  return invoke('unregister_action_shortcut', { action });
+}
+
+export function register_document_hotkey(
+  hotkey: string,
+  document: unknown,
+  serverId?: string
+): Promise<ActionShortcut> {
+  return invoke('register_document_hotkey', { hotkey, document, serverId });
+}
+
 export function hide_coco(): Promise<void> {
   return invoke('hide_coco');
 }
@@ -26,4 +58,42 @@ export function show_coco(): Promise<void> {
 
 export function show_settings(): Promise<void> {
   return invoke('show_settings');
-}
\ No newline at end of file
+}
+export function export_settings(
+  path: string,
+  passphrase?: string
+): Promise<void> {
+  return invoke('export_settings', { path, passphrase });
+}
+
+export function import_settings(
+  path: string,
+  mode: SettingsImportMode,
+  passphrase?: string
+): Promise<SettingsImportSummary> {
+  return invoke('import_settings', { path, mode, passphrase });
+}
+
+export function enable_settings_sync(serverId: string): Promise<void> {
+  return invoke('enable_settings_sync', { serverId });
+}
+
+export function disable_settings_sync(): Promise<void> {
+  return invoke('disable_settings_sync');
+}
+
+export function sync_settings(): Promise<void> {
+  return invoke('sync_settings');
+}
+
+export function get_settings_sync_status(): Promise<SettingsSyncStatus> {
+  return invoke('get_settings_sync_status');
+}
+
+export function get_settings(): Promise<Settings> {
+  return invoke('get_settings');
+}
+
+export function update_settings(changes: Partial<Settings>): Promise<Settings> {
+  return invoke('update_settings', { changes });
+}
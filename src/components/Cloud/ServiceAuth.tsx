@@ -1,19 +1,18 @@
 import { memo, useCallback, useEffect, useState } from "react";
 import { Copy } from "lucide-react";
 import { useTranslation } from "react-i18next";
-import { v4 as uuidv4 } from "uuid";
-import { emit } from "@tauri-apps/api/event";
-import {
-  getCurrent as getCurrentDeepLinkUrls,
-  onOpenUrl,
-} from "@tauri-apps/plugin-deep-link";
+import { emit, listen } from "@tauri-apps/api/event";
 import { getCurrentWindow } from "@tauri-apps/api/window";
 
 import { UserProfile } from "./UserProfile";
 import { OpenURLWithBrowser } from "@/utils";
 import { useConnectStore } from "@/stores/connectStore";
 import { useAppStore } from "@/stores/appStore";
-import { logout_coco_server, handle_sso_callback } from "@/commands";
+import {
+  logout_coco_server,
+  handle_deep_link,
+  start_sso_login,
+} from "@/commands";
 
 interface ServiceAuthProps {
   setRefreshLoading: (loading: boolean) => void;
@@ -35,24 +34,26 @@ const ServiceAuth = memo(
     const setServerList = useConnectStore((state) => state.setServerList);
 
     const [loading, setLoading] = useState(false);
+    const [ssoUrl, setSSOUrl] = useState("");
 
-    const LoginClick = useCallback(() => {
-      if (loading) return; // Prevent multiple clicks if already loading
+    const LoginClick = useCallback(async () => {
+      if (loading || !currentService?.id) return; // Prevent multiple clicks if already loading
 
-      let requestID = uuidv4();
+      // The backend remembers this attempt to validate its callback
+      const { request_id: requestID, url } = await start_sso_login(
+        currentService.id
+      );
       setSSORequestID(requestID);
+      setSSOUrl(url);
 
-      // Generate the login URL with the current appUid
-      const url = `${currentService?.auth_provider?.sso?.url}/?provider=${currentService?.id}&product=coco&request_id=${requestID}`;
-
-      console.log("Open SSO link, requestID:", ssoRequestID, url);
+      console.log("Open SSO link, requestID:", requestID, url);
 
       // Open the URL in a browser
       OpenURLWithBrowser(url);
 
       // Start loading state
       setLoading(true);
-    }, [ssoRequestID, loading, currentService]);
+    }, [loading, currentService]);
 
     const onLogout = useCallback(
       (id: string) => {
@@ -76,59 +77,17 @@ const ServiceAuth = memo(
       [currentService, serverList]
     );
 
-    const handleOAuthCallback = useCallback(
-      async (code: string | null, serverId: string | null) => {
-        if (!code || !serverId) {
-          addError("No authorization code received");
-          return;
-        }
-
-        try {
-          console.log("Handling OAuth callback:", { code, serverId });
-          await handle_sso_callback({
-            serverId: serverId, // Make sure 'server_id' is the correct argument
-            requestId: ssoRequestID, // Make sure 'request_id' is the correct argument
-            code: code,
-          });
-
-          if (serverId != null) {
-            refreshClick(serverId);
-          }
-
-          getCurrentWindow().setFocus();
-        } catch (e) {
-          console.error("Sign in failed:", e);
-        } finally {
-          setLoading(false);
-        }
-      },
-      [ssoRequestID]
-    );
-
-    const handleUrl = (url: string) => {
+    // The backend validates the callback and logs in, its outcome is sent
+    // through the "sso-login-result" event
+    const handleUrl = async (url: string) => {
       try {
-        const urlObject = new URL(url.trim());
-        console.log("handle urlObject:", urlObject);
-
-        // pass request_id and check with local, if the request_id are same, then continue
-        const reqId = urlObject.searchParams.get("request_id");
-        const code = urlObject.searchParams.get("code");
-
-        if (reqId != ssoRequestID) {
-          console.log("Request ID not matched, skip");
-          addError("Request ID not matched, skip");
-          return;
-        }
-
-        const serverId = currentService?.id;
-        handleOAuthCallback(code, serverId);
+        await handle_deep_link(url.trim());
       } catch (err) {
-        console.error("Failed to parse URL:", err);
-        addError("Invalid URL format: " + err);
+        console.error("Failed to handle URL:", err);
+        setLoading(false);
       }
     };
 
-    // Fetch the initial deep link intent
     useEffect(() => {
       setLoading(false);
       // Function to handle pasted URL
@@ -150,21 +109,20 @@ const ServiceAuth = memo(
       // Adding event listener for paste events
       document.addEventListener("paste", handlePaste);
 
-      getCurrentDeepLinkUrls()
-        .then((urls) => {
-          console.log("URLs:", urls);
-          if (urls && urls.length > 0) {
-            if (isValidCallbackUrl(urls[0].trim())) {
-              handleUrl(urls[0]);
-            }
+      const unlisten = listen<{ server_id: string; error: string | null }>(
+        "sso-login-result",
+        ({ payload }) => {
+          setLoading(false);
+          if (payload.error) {
+            console.error("Sign in failed:", payload.error);
+            addError("Sign in failed: " + payload.error);
+            return;
           }
-        })
-        .catch((err) => {
-          console.error("Failed to get initial URLs:", err);
-          addError("Failed to get initial URLs: " + err);
-        });
 
-      const unlisten = onOpenUrl((urls) => handleUrl(urls[0]));
+          refreshClick(payload.server_id);
+          getCurrentWindow().setFocus();
+        }
+      );
 
       return () => {
         unlisten.then((fn) => fn());
@@ -197,9 +155,7 @@ const ServiceAuth = memo(
               <LoadingState
                 onCancel={() => setLoading(false)}
                 onCopy={() => {
-                  navigator.clipboard.writeText(
-                    `${currentService?.auth_provider?.sso?.url}/?provider=${currentService?.id}&product=coco&request_id=${ssoRequestID}`
-                  );
+                  navigator.clipboard.writeText(ssoUrl);
                 }}
               />
             )}
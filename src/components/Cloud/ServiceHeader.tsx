@@ -69,6 +69,7 @@ const ServiceHeader = memo(
                 : t("cloud.disable_server")
             }
             onChange={enable_coco_server}
+            disabled={currentService?.locked}
           />
 
           <button
@@ -87,7 +88,7 @@ const ServiceHeader = memo(
               className={`w-3.5 h-3.5 ${refreshLoading ? "animate-spin" : ""}`}
             />
           </button>
-          {!currentService?.builtin && (
+          {!currentService?.builtin && !currentService?.locked && (
             <button
               className="p-2 text-gray-500 hover:text-gray-700 dark:text-gray-400 dark:hover:text-gray-300 rounded-[6px] bg-white dark:bg-gray-800 border border-[rgba(228,229,239,1)] dark:border-gray-700"
               onClick={() => removeServer(currentService?.id)}
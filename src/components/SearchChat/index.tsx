@@ -23,6 +23,7 @@ import { useStartupStore } from "@/stores/startupStore";
 import { useThemeStore } from "@/stores/themeStore";
 import { useConnectStore } from "@/stores/connectStore";
 import { useAppearanceStore } from "@/stores/appearanceStore";
+import { Server } from "@/types/server";
 
 interface SearchChatProps {
   isTauri?: boolean;
@@ -57,6 +58,10 @@ function SearchChat({
   assistantIDs,
 }: SearchChatProps) {
   const currentAssistant = useConnectStore((state) => state.currentAssistant);
+  const setCurrentService = useConnectStore((state) => state.setCurrentService);
+  const setCurrentAssistant = useConnectStore(
+    (state) => state.setCurrentAssistant
+  );
 
   const source = currentAssistant?._source;
 
@@ -136,6 +141,71 @@ function SearchChat({
     onModeChange?.(value);
   }, []);
 
+  const openAssistant = useCallback(
+    async (serverId: string, assistantId: string) => {
+      try {
+        const servers: Server[] = await platformAdapter.commands(
+          "list_coco_servers"
+        );
+        const server = servers.find((item) => item.id === serverId);
+        if (!server) {
+          throw new Error(`server ${serverId} not found`);
+        }
+
+        const response: any = await platformAdapter.commands(
+          "assistant_search",
+          {
+            serverId,
+            from: 0,
+            size: 1,
+            query: {
+              bool: {
+                must: [{ term: { id: assistantId } }],
+              },
+            },
+          }
+        );
+        const assistant = response?.hits?.hits?.[0];
+        if (!assistant) {
+          throw new Error(`assistant ${assistantId} not found`);
+        }
+
+        setCurrentService(server);
+        setCurrentAssistant(assistant);
+        changeMode(true);
+      } catch (error) {
+        console.error("shortcut-open-assistant", error);
+      }
+    },
+    [changeMode]
+  );
+
+  useEffect(() => {
+    const unlisteners = [
+      platformAdapter.listenEvent("shortcut-open-chat", () => {
+        changeMode(true);
+      }),
+      platformAdapter.listenEvent(
+        "shortcut-search-clipboard",
+        ({ payload }) => {
+          changeMode(false);
+          dispatch({ type: "SET_INPUT", payload: payload.query });
+        }
+      ),
+      platformAdapter.listenEvent("shortcut-search", ({ payload }) => {
+        changeMode(false);
+        dispatch({ type: "SET_INPUT", payload: payload.query });
+      }),
+      platformAdapter.listenEvent("shortcut-open-assistant", ({ payload }) => {
+        openAssistant(payload.server_id, payload.assistant_id);
+      }),
+    ];
+
+    return () => {
+      unlisteners.forEach((unlisten) => unlisten.then((fn) => fn()));
+    };
+  }, [changeMode, openAssistant]);
+
   const handleSendMessage = useCallback(
     async (value: string) => {
       dispatch({ type: "SET_INPUT", payload: value });
@@ -12,9 +12,6 @@ import {
 } from "lucide-react";
 import { useTranslation } from "react-i18next";
 import { isTauri } from "@tauri-apps/api/core";
-import {
-  isEnabled,
-} from "@tauri-apps/plugin-autostart";
 import { emit } from "@tauri-apps/api/event";
 import { useCreation } from "ahooks";
 
@@ -26,7 +23,7 @@ import { useShortcutEditor } from "@/hooks/useShortcutEditor";
 import { useAppStore } from "@/stores/appStore";
 import { AppTheme } from "@/types/index";
 import { useThemeStore } from "@/stores/themeStore";
-import { change_autostart, get_current_shortcut, change_shortcut, unregister_shortcut } from "@/commands"
+import { change_autostart, get_current_shortcut, change_shortcut, unregister_shortcut, get_settings } from "@/commands"
 
 export function ThemeOption({
   icon: Icon,
@@ -79,8 +76,8 @@ export default function GeneralSettings() {
   const fetchAutoStartStatus = async () => {
     if (isTauri()) {
       try {
-        const status = await isEnabled();
-        setLaunchAtLogin(status);
+        const settings = await get_settings();
+        setLaunchAtLogin(settings.autostart.enabled);
       } catch (error) {
         console.error("Failed to fetch autostart status:", error);
       }
@@ -6,6 +6,7 @@ interface SettingsToggleProps {
   onChange: (checked: boolean) => void;
   label: string;
   className?: string;
+  disabled?: boolean;
 }
 
 export default function SettingsToggle({
@@ -13,15 +14,18 @@ export default function SettingsToggle({
   onChange,
   label,
   className,
+  disabled,
 }: SettingsToggleProps) {
   return (
     <Switch
       checked={checked}
       onChange={onChange}
+      disabled={disabled}
       className={clsx(
         `relative inline-flex h-6 w-11 flex-shrink-0 cursor-pointer rounded-full border-2 border-transparent 
         transition-colors duration-200 ease-in-out focus:outline-none focus:ring-2 focus:ring-blue-500 focus:ring-offset-2`,
         [checked ? "bg-blue-600" : "bg-gray-200 dark:bg-gray-700"],
+        { "cursor-not-allowed opacity-50": disabled },
         className
       )}
     >
@@ -1,4 +1,4 @@
-import { useCallback, useMemo } from "react";
+import { useCallback, useEffect, useMemo } from "react";
 import { Button, Dialog, DialogPanel } from "@headlessui/react";
 import { useTranslation } from "react-i18next";
 import { noop } from "lodash-es";
@@ -12,6 +12,7 @@ import { useThemeStore } from "@/stores/themeStore";
 import { useUpdateStore } from "@/stores/updateStore";
 import { OpenURLWithBrowser } from "@/utils/index";
 import { useAppStore } from "@/stores/appStore";
+import platformAdapter from "@/utils/platformAdapter";
 
 interface State {
   loading?: boolean;
@@ -32,6 +33,7 @@ const UpdateApp = ({ checkUpdate, relaunchApp }: UpdateAppProps) => {
   const skipVersion = useUpdateStore((state) => state.skipVersion);
   const setSkipVersion = useUpdateStore((state) => state.setSkipVersion);
   const isOptional = useUpdateStore((state) => state.isOptional);
+  const setIsOptional = useUpdateStore((state) => state.setIsOptional);
   const updateInfo = useUpdateStore((state) => state.updateInfo);
   const setUpdateInfo = useUpdateStore((state) => state.setUpdateInfo);
   const addError = useAppStore((state) => state.addError);
@@ -54,6 +56,26 @@ const UpdateApp = ({ checkUpdate, relaunchApp }: UpdateAppProps) => {
     }
   }, [skipVersion]);
 
+  useEffect(() => {
+    // A server requires a newer version, the update cannot be skipped
+    const unlisten = platformAdapter.listenEvent(
+      "client-update-required",
+      async () => {
+        const update = await checkUpdate();
+
+        if (!update) return;
+
+        setUpdateInfo(update);
+        setIsOptional(false);
+        setVisible(true);
+      }
+    );
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [checkUpdate]);
+
   const cursorClassName = useMemo(() => {
     return state.loading ? "cursor-not-allowed" : "cursor-pointer";
   }, [state.loading]);
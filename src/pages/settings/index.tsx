@@ -3,6 +3,7 @@ import { Tab, TabGroup, TabList, TabPanel, TabPanels } from "@headlessui/react";
 import { Settings, Puzzle, Settings2, Info, Server } from "lucide-react";
 import { useTranslation } from "react-i18next";
 import { listen } from "@tauri-apps/api/event";
+import { ask } from "@tauri-apps/plugin-dialog";
 
 import SettingsPanel from "@/components/Settings/SettingsPanel";
 import GeneralSettings from "@/components/Settings/GeneralSettings";
@@ -12,6 +13,12 @@ import Footer from "@/components/Common/UI/SettingsFooter";
 import { useTray } from "@/hooks/useTray";
 import Advanced from "@/components/Settings/Advanced";
 import Extensions from "@/components/Settings/Extensions";
+import {
+  add_coco_server,
+  list_coco_servers,
+  take_pending_server_endpoint,
+} from "@/commands";
+import { useConnectStore } from "@/stores/connectStore";
 
 const tabIndexMap: { [key: string]: number } = {
   general: 0,
@@ -36,6 +43,9 @@ function SettingsPage() {
 
   const [defaultIndex, setDefaultIndex] = useState<number>(0);
 
+  const setServerList = useConnectStore((state) => state.setServerList);
+  const setCurrentService = useConnectStore((state) => state.setCurrentService);
+
   useEffect(() => {
     const unlisten = listen("tab_index", (event) => {
       const tabName = event.payload as string;
@@ -50,6 +60,33 @@ function SettingsPage() {
     };
   }, []);
 
+  // Anyone can craft a `coco://add-server` link, the server is only added
+  // once the user confirms
+  useEffect(() => {
+    const confirmPendingServer = async () => {
+      const endpoint = await take_pending_server_endpoint();
+      if (!endpoint) return;
+
+      setDefaultIndex(tabIndexMap.connect);
+      const confirmed = await ask(
+        `Add the Coco server ${endpoint}? Only add the servers you trust.`,
+        { title: "Add Coco server", kind: "warning" }
+      );
+      if (!confirmed) return;
+
+      const server = await add_coco_server(endpoint);
+      setServerList(await list_coco_servers());
+      setCurrentService(server);
+    };
+
+    confirmPendingServer();
+    const unlisten = listen("deep-link-add-server", confirmPendingServer);
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
   useEffect(() => {
     document.body.style.overflow = defaultIndex === 1 ? "hidden" : "auto";
   }, [defaultIndex]);
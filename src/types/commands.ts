@@ -63,6 +63,102 @@ export interface Server {
   profile?: UserProfile;
   auth_provider: AuthProvider;
   priority: number;
+  incompatible_reason?: string | null;
+  locked?: boolean;
+  accounts?: ServerAccount[];
+  active_account?: string | null;
+}
+
+export interface ServerAccount {
+  id: string;
+  name: string;
+  profile?: UserProfile | null;
+}
+
+export interface ServerAccountStatus extends ServerAccount {
+  active: boolean;
+  logged_in: boolean;
+}
+
+export interface ProxyConfig {
+  mode: "system" | "none" | "manual";
+  http_proxy?: string | null;
+  https_proxy?: string | null;
+  socks5_proxy?: string | null;
+  username?: string | null;
+  password?: string | null;
+  no_proxy: string[];
+}
+
+export interface Settings {
+  allow_self_signature: boolean;
+  offline_cache_enabled: boolean;
+  proxy: ProxyConfig;
+  global_shortcut: string;
+  action_shortcuts: ActionShortcut[];
+  autostart: AutostartSettings;
+  local_api: LocalApiSettings;
+}
+
+export type ShortcutAction =
+  | { type: "toggle_launcher" }
+  | { type: "open_chat" }
+  | { type: "open_settings" }
+  | { type: "search_clipboard" }
+  | { type: "run_quicklink"; link: string }
+  | { type: "open_document"; document: DocumentHotkeyTarget };
+
+export type DocumentTarget =
+  | { kind: "url"; url: string }
+  | { kind: "search"; query: string }
+  | { kind: "assistant"; server_id: string; assistant_id: string }
+  | { kind: "command"; path: string };
+
+export interface DocumentHotkeyTarget {
+  id: string;
+  server_id?: string;
+  title: string;
+  icon?: string;
+  source?: {
+    type?: string;
+    name?: string;
+    id?: string;
+    icon?: string;
+  };
+  target: DocumentTarget;
+}
+
+export interface ActionShortcut {
+  shortcut: string;
+  action: ShortcutAction;
+}
+
+export interface AutostartSettings {
+  enabled: boolean;
+  start_hidden: boolean;
+  delay_secs: number;
+}
+
+export type LocalApiTransport = "unix_socket" | "http";
+
+export interface LocalApiSettings {
+  enabled: boolean;
+  transport: LocalApiTransport;
+  port: number;
+}
+
+export type SettingsImportMode = "merge" | "replace";
+
+export interface SettingsImportSummary {
+  servers: number;
+  tokens: number;
+  warnings: string[];
+}
+
+export interface SettingsSyncStatus {
+  server_id?: string;
+  last_synced_at?: number;
+  last_error?: string;
 }
 
 interface ConnectorAssets {
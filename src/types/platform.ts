@@ -4,6 +4,7 @@ import { IConnectStore } from "@/stores/connectStore";
 import { IExtensionsStore } from "@/stores/extensionsStore";
 import { IShortcutsStore } from "@/stores/shortcutsStore";
 import { IStartupStore } from "@/stores/startupStore";
+import { Settings, ShortcutAction } from "@/types/commands";
 import { AppTheme } from "@/types/index";
 
 export interface EventPayloads {
@@ -31,6 +32,27 @@ export interface EventPayloads {
   datasourceData_change: void;
   "ws-error": void;
   "ws-message": void;
+  "client-update-required": {
+    server_id: string;
+    minimal_client_version?: { number: string };
+    reason: string;
+  };
+  "settings-imported": void;
+  "settings-synced": void;
+  "settings-changed": Settings;
+  "shortcut-open-chat": void;
+  "shortcut-search-clipboard": { query: string };
+  "shortcut-search": { query: string };
+  "shortcut-open-assistant": {
+    server_id: string;
+    assistant_id: string;
+    title: string;
+  };
+  "shortcut-registration-failed": {
+    shortcut: string;
+    action: ShortcutAction;
+    error: string;
+  };
   [key: `ws-error-${string}`]: string;
   [key: `ws-message-${string}`]: string;
   [key: `ws-cancel-${string}`]: string;
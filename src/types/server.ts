@@ -62,4 +62,6 @@ export interface Server {
   };
   priority: number;
   stats?: Record<string, any>;
+  incompatible_reason?: string | null;
+  locked?: boolean;
 }
\ No newline at end of file